@@ -23,45 +23,55 @@ const Cookies = {
 };
 
 // ===========================================
-// API HELPER - VERSÃO CORRIGIDA COM ID
+// API HELPER
 // ===========================================
+// Especificação completa em /api/openapi.json (e /api/docs)
+const BASE_API = "/api/v1";
+
 window.API = {
   // ===========================================
   // REQUISIÇÃO BASE
   // ===========================================
-  async request(rota, dados = null, metodo = null) {
+  // chave: Idempotency-Key da escrita; repetir a mesma chave não aplica de
+  // novo, só devolve a resposta da primeira vez
+  async request(rota, dados = null, metodo = null, chave = null) {
     const options = {
       method: metodo || (dados ? "POST" : "GET"),
       headers: {
-        "Content-Type": "application/x-www-form-urlencoded",
+        "Content-Type": "application/json",
       },
     };
+    if (chave) options.headers["Idempotency-Key"] = chave;
 
     if (dados && options.method !== "GET") {
-      const params = new URLSearchParams();
-
-      if (typeof dados === "object" && !Array.isArray(dados)) {
-        for (let [key, value] of Object.entries(dados)) {
-          params.append(key, value);
-        }
-      } else if (typeof dados === "string") {
-        params.append("nome", dados);
-      }
-
-      options.body = params.toString();
+      options.body = JSON.stringify(
+        typeof dados === "string" ? { nome: dados } : dados,
+      );
     }
 
     try {
-      const response = await fetch(`/api/${rota}`, options);
+      const response = await fetch(`${BASE_API}/${rota}`, options);
       const text = await response.text();
 
+      // Erros vêm como { codigo, mensagem }; a mensagem já é para o usuário
+      if (!response.ok) {
+        let corpo = {};
+        try {
+          corpo = JSON.parse(text);
+        } catch {}
+        const erro = new Error(corpo.mensagem || `Erro ${response.status}`);
+        erro.codigo = corpo.codigo;
+        erro.status = response.status;
+        throw erro;
+      }
+
       try {
         return JSON.parse(text);
       } catch {
         return text;
       }
     } catch (error) {
-      console.error(`❌ Erro na requisição para /api/${rota}:`, error);
+      console.error(`❌ Erro na requisição para ${BASE_API}/${rota}:`, error);
       throw error;
     }
   },
@@ -74,15 +84,14 @@ window.API = {
   },
 
   // ===========================================
-  // SEÇÕES (CORRIGIDO - com ID)
+  // SEÇÕES
   // ===========================================
   listar_secoes: function () {
     return this.request("secoes");
   },
 
   criar_secao: function (nome) {
-    // Backend exige campo 'id'
-    return this.request("secoes", { id: 0, nome: nome });
+    return this.request("secoes", { nome });
   },
 
   deletar_secao: function (id) {
@@ -90,15 +99,14 @@ window.API = {
   },
 
   // ===========================================
-  // TIPOS (CORRIGIDO - com ID)
+  // TIPOS
   // ===========================================
   listar_tipos: function (secaoId) {
     return this.request(`tipos/secao/${secaoId}`);
   },
 
   criar_tipo: function (nome, secaoId) {
-    // Backend exige campo 'id'
-    return this.request("tipos", { id: 0, nome: nome, id_secao: secaoId });
+    return this.request("tipos", { nome, id_secao: secaoId });
   },
 
   deletar_tipo: function (id) {
@@ -106,40 +114,45 @@ window.API = {
   },
 
   // ===========================================
-  // PRODUTOS (CORRIGIDO - com ID)
+  // PRODUTOS
   // ===========================================
-  listar_produtos: function (tipoId) {
-    return this.request(`produtos/tipo/${tipoId}`);
+  // Listas paginadas devolvem { itens, total, limite, deslocamento }.
+  // opcoes: limite, deslocamento, ordem (nome | validade | quantidade),
+  // direcao (asc | desc), validade_de, validade_ate, com_prateleira, secao
+  listar_produtos: function (tipoId, opcoes = {}) {
+    return this.request(`produtos/tipo/${tipoId}?${new URLSearchParams(opcoes)}`);
   },
 
-  criar_produto: function (nome, tipoId) {
-    return this.request("produtos", { 
-        id: 0, 
-        nome: nome, 
-        tipo_id: tipoId  // ← tentando os dois formatos
-    });
-},
+  // termo casa com nome, código de barras ou código de lote, pelo começo das
+  // palavras e sem diferenciar acentos; sem opcoes.ordem, vem por relevância
+  pesquisar: function (termo, opcoes = {}) {
+    return this.request(`pesquisar?${new URLSearchParams({ q: termo, ...opcoes })}`);
+  },
+
+  criar_produto: function (nome, tipoId, codigoBarras = null) {
+    return this.request("produtos", { nome, id_tipo: tipoId, codigo_barras: codigoBarras });
+  },
 
   deletar_produto: function (id) {
     return this.request(`produtos/${id}`, null, "DELETE");
   },
 
   // ===========================================
-  // LOTES (CORRIGIDO - com ID)
+  // LOTES
   // ===========================================
-  listar_lotes: function (produtoId) {
-    return this.request(`lotes/produto/${produtoId}`);
+  listar_lotes: function (produtoId, opcoes = {}) {
+    return this.request(`lotes/produto/${produtoId}?${new URLSearchParams(opcoes)}`);
   },
 
   criar_lote: function (dados) {
     return this.request("lotes", {
-        id: 0,
-        produto_id: dados.produto_id || dados.produtoId,
-        validade: dados.validade,
-        quantidade_total: dados.quantidade_total || dados.quantidadeTotal,
-        quantidade_prateleira: dados.quantidade_prateleira || dados.quantidadePrateleira,
+      id_produto: dados.produto_id || dados.produtoId,
+      validade: dados.validade,
+      quantidade_total: dados.quantidade_total || dados.quantidadeTotal,
+      quantidade_prateleira: dados.quantidade_prateleira || dados.quantidadePrateleira,
+      codigo: dados.codigo || null,
     });
-},
+  },
 
   deletar_lote: function (id) {
     return this.request(`lotes/${id}`, null, "DELETE");
@@ -155,12 +168,43 @@ window.API = {
   // ===========================================
   // OPERAÇÕES
   // ===========================================
-  vender_lote: function (id, quantidade) {
-    return this.request(`vender/${id}`, { quantidade });
+  // Gere a chave quando o usuário começa a ação (ao abrir o diálogo de
+  // venda, por exemplo) e reuse-a nas novas tentativas: um toque duplo ou
+  // um reenvio com a mesma chave vende uma vez só.
+  nova_chave: function () {
+    return "k_" + Date.now().toString(36) + Math.random().toString(36).substring(2, 12);
+  },
+
+  vender_lote: function (id, quantidade, chave = this.nova_chave()) {
+    return this.request(`vender/${id}`, { quantidade }, null, chave);
+  },
+
+  abastecer_prateleira: function (id, quantidade, chave = this.nova_chave()) {
+    return this.request(`abastecer/${id}`, { quantidade }, null, chave);
+  },
+
+  // motivo: vencido | avariado | furto | consumo_interno | doacao
+  registrar_baixa: function (id, dados, chave = this.nova_chave()) {
+    return this.request(`lotes/${id}/baixa`, dados, null, chave);
+  },
+
+  listar_baixas: function () {
+    return this.request("baixas");
+  },
+
+  sugestoes_rebaixa: function () {
+    return this.request("rebaixas/sugestoes");
+  },
+
+  aceitar_rebaixa: function (id, descontoPercentual) {
+    return this.request(`lotes/${id}/rebaixa`, {
+      desconto_percentual: descontoPercentual,
+    });
   },
 
-  abastecer_prateleira: function (id, quantidade) {
-    return this.request(`abastecer/${id}`, { quantidade });
+  historico_estoque: function (filtros = {}) {
+    const params = new URLSearchParams(filtros);
+    return this.request(`historico?${params}`);
   },
 
   // ===========================================
@@ -170,19 +214,99 @@ window.API = {
     return this.request("relatorio");
   },
 
-  produtos_a_vencer: function (dias) {
-    return this.request(`vencer/${dias}`);
+  produtos_a_vencer: function (dias, opcoes = {}) {
+    return this.request(`vencer/${dias}?${new URLSearchParams(opcoes)}`);
   },
 
   // ===========================================
   // CSV
   // ===========================================
   exportar_csv: function () {
-    return fetch("/api/exportar").then((r) => r.text());
+    return fetch(`${BASE_API}/exportar`).then((r) => r.text());
   },
 
   importar_csv: function (csvData) {
-    return this.request("importar", { csvData });
+    return fetch(`${BASE_API}/importar`, { method: "POST", body: csvData }).then((r) =>
+      r.text(),
+    );
+  },
+
+  exportar_xlsx: function (planilhas = "por_secao") {
+    return fetch(`${BASE_API}/exportar.xlsx?planilhas=${planilhas}`).then((r) => r.blob());
+  },
+
+  importar_xlsx: function (arquivo) {
+    return fetch(`${BASE_API}/importar`, { method: "POST", body: arquivo }).then((r) =>
+      r.text(),
+    );
+  },
+
+  gerar_backup: function () {
+    return fetch(`${BASE_API}/backup`).then((r) => r.json());
+  },
+
+  restaurar_backup: function (backup) {
+    return fetch(`${BASE_API}/backup/restaurar`, {
+      method: "POST",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify(backup),
+    }).then((r) => r.text());
+  },
+
+  // ===========================================
+  // SINCRONIZAÇÃO (SEM REDE)
+  // ===========================================
+  // Vendas, abastecimentos e lotes novos feitos sem rede ficam numa fila no
+  // aparelho. tipo: "vender" | "abastecer" | "criar_lote"; dados como na API
+  // (id_lote ou lote_operacao, quantidade; ou os campos do lote). Devolve o
+  // id da operação, que serve de lote_operacao para o lote criado.
+  enfileirar_operacao: function (tipo, dados) {
+    const fila = JSON.parse(localStorage.getItem("fila_sincronizacao") || "[]");
+    const id = "op_" + Date.now().toString(36) + Math.random().toString(36).substring(2, 10);
+    fila.push({ id, momento: new Date().toISOString(), acao: tipo, ...dados });
+    localStorage.setItem("fila_sincronizacao", JSON.stringify(fila));
+    return id;
+  },
+
+  // Envia a fila e devolve { resultados, token, alteracoes }. Só tira da
+  // fila o que foi enviado; se a rede cair no meio, reenviar é seguro.
+  sincronizar: async function () {
+    const fila = JSON.parse(localStorage.getItem("fila_sincronizacao") || "[]");
+    const token = localStorage.getItem("token_sincronizacao");
+    const resposta = await this.request("sincronizar", {
+      desde: token === null ? null : Number(token),
+      operacoes: fila,
+    });
+
+    const atual = JSON.parse(localStorage.getItem("fila_sincronizacao") || "[]");
+    const enviadas = new Set(fila.map((op) => op.id));
+    localStorage.setItem(
+      "fila_sincronizacao",
+      JSON.stringify(atual.filter((op) => !enviadas.has(op.id))),
+    );
+    localStorage.setItem("token_sincronizacao", resposta.token);
+    return resposta;
+  },
+
+  // ===========================================
+  // ATUALIZAÇÕES AO VIVO
+  // ===========================================
+  // aoReceber recebe cada evento do servidor, como
+  // { evento: "lote_alterado", id, id_produto }. O EventSource reconecta
+  // sozinho; o que mudou enquanto estava desconectado chega como
+  // "eventos_perdidos", para a página recarregar tudo.
+  ouvir_eventos: function (aoReceber) {
+    const fonte = new EventSource(`${BASE_API}/eventos`);
+    let caiu = false;
+    fonte.onmessage = (mensagem) => aoReceber(JSON.parse(mensagem.data));
+    fonte.onerror = () => {
+      caiu = true;
+    };
+    fonte.onopen = () => {
+      if (caiu) aoReceber({ evento: "eventos_perdidos" });
+      caiu = false;
+    };
+    return fonte;
   },
 
   // ===========================================
@@ -0,0 +1,387 @@
+use chrono::Local;
+use rusqlite::{types::Value, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+use crate::erro::{ErroApp, Json};
+use crate::eventos::{self, Evento};
+use crate::init_db;
+
+/// Versão do formato do backup. Mudou o formato, muda o número.
+pub const VERSAO_BACKUP: u32 = 1;
+
+/// Tabelas do banco de produtos, na ordem em que são restauradas.
+/// Configurações (regras e limites de rebaixa, estoques mínimos) entram aqui também.
+const TABELAS: [&str; 12] = [
+    "secoes",
+    "tipos",
+    "produtos",
+    "lotes",
+    "precos",
+    "vendas",
+    "baixas",
+    "rebaixas",
+    "estoque_minimo",
+    "regras_rebaixa",
+    "limites_rebaixa",
+    "historico_estoque",
+];
+
+// ===========================================
+// ESTRUTURAS DE DADOS
+// ===========================================
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Usuario {
+    pub id: i32,
+    pub nome: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Backup {
+    pub versao: u32,
+    pub gerado_em: String,
+    /// Linhas de cada tabela, como objetos coluna → valor
+    #[schema(value_type = HashMap<String, Vec<Object>>)]
+    pub tabelas: BTreeMap<String, Vec<Map<String, JsonValue>>>,
+    /// Usuários sem as senhas
+    pub usuarios: Vec<Usuario>,
+}
+
+// ===========================================
+// CONVERSÃO ENTRE SQLITE E JSON
+// ===========================================
+
+fn para_json(valor: Value) -> JsonValue {
+    match valor {
+        Value::Null => JsonValue::Null,
+        Value::Integer(n) => JsonValue::from(n),
+        Value::Real(n) => JsonValue::from(n),
+        Value::Text(texto) => JsonValue::from(texto),
+        Value::Blob(bytes) => JsonValue::from(bytes),
+    }
+}
+
+fn de_json(valor: &JsonValue) -> Option<Value> {
+    match valor {
+        JsonValue::Null => Some(Value::Null),
+        JsonValue::Bool(b) => Some(Value::Integer(*b as i64)),
+        JsonValue::Number(n) => n.as_i64().map(Value::Integer).or_else(|| n.as_f64().map(Value::Real)),
+        JsonValue::String(texto) => Some(Value::Text(texto.clone())),
+        JsonValue::Array(itens) => itens.iter()
+            .map(|item| item.as_u64().and_then(|b| u8::try_from(b).ok()))
+            .collect::<Option<Vec<u8>>>()
+            .map(Value::Blob),
+        JsonValue::Object(_) => None,
+    }
+}
+
+fn colunas(conn: &Connection, tabela: &str) -> Result<Vec<String>, rusqlite::Error> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", tabela))?;
+    let nomes = stmt.query_map([], |row| row.get(1))?;
+    nomes.collect()
+}
+
+// ===========================================
+// GERAR E RESTAURAR
+// ===========================================
+
+pub fn gerar_backup(conn: &Connection, usuarios: Vec<Usuario>) -> Result<Backup, rusqlite::Error> {
+    let mut tabelas = BTreeMap::new();
+
+    for tabela in TABELAS {
+        let colunas = colunas(conn, tabela)?;
+        let mut stmt = conn.prepare(&format!("SELECT * FROM {} ORDER BY rowid", tabela))?;
+        let mut linhas = stmt.query([])?;
+
+        let mut registros = Vec::new();
+        while let Some(row) = linhas.next()? {
+            let mut registro = Map::new();
+            for (i, coluna) in colunas.iter().enumerate() {
+                registro.insert(coluna.clone(), para_json(row.get(i)?));
+            }
+            registros.push(registro);
+        }
+        tabelas.insert(tabela.to_string(), registros);
+    }
+
+    Ok(Backup {
+        versao: VERSAO_BACKUP,
+        gerado_em: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        tabelas,
+        usuarios,
+    })
+}
+
+#[derive(Debug)]
+pub enum ErroRestauracao {
+    /// O backup é de outra versão, falta tabela ou tem coluna/valor que não existe aqui
+    Invalido(String),
+    Banco(rusqlite::Error),
+}
+
+impl From<rusqlite::Error> for ErroRestauracao {
+    fn from(erro: rusqlite::Error) -> Self {
+        ErroRestauracao::Banco(erro)
+    }
+}
+
+/// Substitui todo o conteúdo das tabelas pelo do backup, mantendo os ids.
+/// Roda numa transação: se qualquer linha falhar, nada muda.
+pub fn restaurar_backup(conn: &mut Connection, backup: &Backup) -> Result<usize, ErroRestauracao> {
+    if backup.versao != VERSAO_BACKUP {
+        return Err(ErroRestauracao::Invalido(format!(
+            "Versão de backup {} não suportada (esperada {})", backup.versao, VERSAO_BACKUP
+        )));
+    }
+    if let Some(tabela) = backup.tabelas.keys().find(|tabela| !TABELAS.contains(&tabela.as_str())) {
+        return Err(ErroRestauracao::Invalido(format!("Tabela desconhecida no backup: {}", tabela)));
+    }
+
+    // Com as chaves estrangeiras desligadas a ordem das tabelas não importa e
+    // o DELETE não apaga em cascata; a conferência fica para o fim.
+    conn.pragma_update(None, "foreign_keys", false)?;
+    let resultado = substituir_tabelas(conn, backup);
+    conn.pragma_update(None, "foreign_keys", true)?;
+    resultado
+}
+
+fn substituir_tabelas(conn: &mut Connection, backup: &Backup) -> Result<usize, ErroRestauracao> {
+    let tx = conn.transaction()?;
+
+    for tabela in TABELAS.iter().rev() {
+        tx.execute(&format!("DELETE FROM {}", tabela), [])?;
+    }
+
+    let mut restauradas = 0;
+    for tabela in TABELAS {
+        let registros = backup.tabelas.get(tabela)
+            .ok_or_else(|| ErroRestauracao::Invalido(format!("Falta a tabela {} no backup", tabela)))?;
+        let existentes = colunas(&tx, tabela)?;
+
+        for registro in registros {
+            if let Some(coluna) = registro.keys().find(|coluna| !existentes.contains(coluna)) {
+                return Err(ErroRestauracao::Invalido(format!("Coluna desconhecida no backup: {}.{}", tabela, coluna)));
+            }
+            let nomes: Vec<&str> = registro.keys().map(|coluna| coluna.as_str()).collect();
+            let valores = registro.values()
+                .map(de_json)
+                .collect::<Option<Vec<Value>>>()
+                .ok_or_else(|| ErroRestauracao::Invalido(format!("Valor inválido na tabela {}", tabela)))?;
+
+            let sql = format!(
+                "INSERT INTO {} ({}) VALUES ({})",
+                tabela,
+                nomes.join(", "),
+                vec!["?"; nomes.len()].join(", ")
+            );
+            tx.prepare_cached(&sql)?
+                .execute(rusqlite::params_from_iter(valores))
+                .map_err(|e| ErroRestauracao::Invalido(format!("Registro inválido na tabela {}: {}", tabela, e)))?;
+            restauradas += 1;
+        }
+    }
+
+    // Registros órfãos (de um banco antigo, de antes das chaves estrangeiras
+    // valerem, ou de um backup editado) recusam o backup inteiro
+    let orfao = tx.query_row("PRAGMA foreign_key_check", [], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, Option<i64>>(1)?, row.get::<_, String>(2)?))
+    }).optional()?;
+    if let Some((tabela, id, referida)) = orfao {
+        return Err(ErroRestauracao::Invalido(format!(
+            "Registro {} da tabela {} aponta para um registro de {} que não está no backup",
+            id.map_or("sem id".to_string(), |id| id.to_string()),
+            tabela,
+            referida,
+        )));
+    }
+
+    tx.commit()?;
+    Ok(restauradas)
+}
+
+fn listar_usuarios(conn: &Connection) -> Result<Vec<Usuario>, rusqlite::Error> {
+    let mut stmt = conn.prepare("SELECT id, nome FROM usuarios ORDER BY id")?;
+    let usuarios = stmt.query_map([], |row| Ok(Usuario { id: row.get(0)?, nome: row.get(1)? }))?;
+    usuarios.collect()
+}
+
+// ===========================================
+// HANDLERS DE BACKUP
+// ===========================================
+
+// Backup e restauração mexem no estoque inteiro: as rotas passam por
+// `usuarios::exigir_admin` (cookie `sessao` de um admin).
+
+#[utoipa::path(get, path = "/backup", tag = "dados",
+    responses((status = 200, body = Backup),
+        (status = 401, description = "Sem sessão: entre pelo /login"),
+        (status = 403, description = "O usuário da sessão não é admin")))]
+pub async fn gerar_backup_handler() -> Result<Json<Backup>, ErroApp> {
+    let conn = init_db()?;
+    let usuarios = listar_usuarios(&conn)?;
+
+    let backup = gerar_backup(&conn, usuarios)?;
+
+    Ok(Json(backup))
+}
+
+/// Restaura os dados do estoque. Senhas não vão no backup, então as contas
+/// de usuário ficam como estão; as que faltarem aqui são apenas informadas.
+/// O corpo pode ter até `limite_envio_mb`.
+#[utoipa::path(post, path = "/backup/restaurar", tag = "dados", request_body = Backup,
+    responses((status = 200, description = "Quantos registros foram restaurados", body = String),
+        (status = 401, description = "Sem sessão: entre pelo /login"),
+        (status = 403, description = "O usuário da sessão não é admin"),
+        (status = 400, description = "Backup inválido ou maior que limite_envio_mb")))]
+pub async fn restaurar_backup_handler(Json(backup): Json<Backup>) -> Result<String, ErroApp> {
+    let mut conn = init_db()?;
+
+    let resumo = restaurar(&mut conn, &backup)?;
+    eventos::publicar(Evento::EstoqueSubstituido);
+
+    Ok(resumo)
+}
+
+fn restaurar(conn: &mut Connection, backup: &Backup) -> Result<String, ErroApp> {
+    let restauradas = restaurar_backup(conn, backup).map_err(|erro| match erro {
+        ErroRestauracao::Invalido(mensagem) => ErroApp::invalido("backup_invalido", mensagem),
+        ErroRestauracao::Banco(erro) => ErroApp::from(erro),
+    })?;
+
+    let existentes = listar_usuarios(conn)?;
+    let faltando: Vec<&str> = backup.usuarios.iter()
+        .filter(|usuario| !existentes.iter().any(|existente| existente.nome == usuario.nome))
+        .map(|usuario| usuario.nome.as_str())
+        .collect();
+
+    if faltando.is_empty() {
+        Ok(format!("Backup restaurado: {} registros", restauradas))
+    } else {
+        Ok(format!(
+            "Backup restaurado: {} registros. Usuários sem conta neste servidor: {}",
+            restauradas,
+            faltando.join(", ")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn banco() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::criar_schema(&conn).unwrap();
+        conn
+    }
+
+    fn sem_data(mut backup: Backup) -> String {
+        backup.gerado_em.clear();
+        serde_json::to_string(&backup).unwrap()
+    }
+
+    #[test]
+    fn backup_restaurado_em_banco_vazio_fica_igual() {
+        let origem = banco();
+        origem.execute_batch(
+            "INSERT INTO secoes (id, nome) VALUES (1, 'Padaria'), (3, 'Açougue');
+             INSERT INTO tipos (id, nome, id_secao) VALUES (1, 'Pães', 1), (2, 'Bovinos', 3);
+             INSERT INTO produtos (id, nome, id_tipo, codigo_barras) VALUES (1, 'Francês', 1, '789'), (5, 'Alcatra', 2, NULL);
+             INSERT INTO lotes (id, id_produto, validade, quantidade_total, quantidade_prateleira, codigo)
+                 VALUES (1, 1, '2030-01-01', 10, 4, 'L1'), (2, 5, '2030-02-01', 3, 3, NULL);
+             INSERT INTO precos (id_produto, custo_centavos, preco_venda_centavos, vigente_desde)
+                 VALUES (1, 50, 90, '2026-01-01'), (5, 3000, 4599, '2026-01-02 10:00:00');
+             INSERT INTO vendas (id_lote, id_produto, quantidade, data) VALUES (1, 1, 2, '2026-01-03 08:00:00');
+             INSERT INTO baixas (id_lote, id_produto, motivo, quantidade, observacao, data)
+                 VALUES (2, 5, 'avariado', 1, 'embalagem rasgada', '2026-01-04 09:00:00');
+             INSERT INTO rebaixas (id_lote, desconto_percentual, preco_original_centavos, preco_centavos, data)
+                 VALUES (2, 20, 4599, 3679, '2026-01-05 07:00:00');
+             INSERT INTO estoque_minimo VALUES (1, 5, 20, 2);
+             INSERT INTO regras_rebaixa (dias_restantes, sobra_minima_percentual, desconto_percentual) VALUES (3, 0, 30);
+             INSERT INTO limites_rebaixa VALUES (3, 25);
+             INSERT INTO historico_estoque VALUES ('2026-01-01', 1, 1, 1, 10, 4, 0, 0);"
+        ).unwrap();
+        let backup = gerar_backup(&origem, Vec::new()).unwrap();
+        let json = serde_json::to_string(&backup).unwrap();
+
+        let mut destino = banco();
+        let restauradas = restaurar_backup(&mut destino, &serde_json::from_str(&json).unwrap()).unwrap();
+
+        assert_eq!(restauradas, 17);
+        assert_eq!(sem_data(gerar_backup(&destino, Vec::new()).unwrap()), sem_data(backup));
+        // As chaves estrangeiras voltam a valer depois da restauração
+        destino.execute("DELETE FROM secoes WHERE id = 3", []).unwrap();
+        let rebaixas: i32 = destino.query_row("SELECT COUNT(*) FROM rebaixas", [], |row| row.get(0)).unwrap();
+        assert_eq!(rebaixas, 0);
+    }
+
+    #[test]
+    fn backup_com_registro_orfao_nao_muda_nada() {
+        let origem = banco();
+        origem.execute_batch(
+            "INSERT INTO secoes (id, nome) VALUES (1, 'Padaria');
+             INSERT INTO tipos (id, nome, id_secao) VALUES (1, 'Pães', 1);
+             INSERT INTO produtos (id, nome, id_tipo) VALUES (1, 'Francês', 1);"
+        ).unwrap();
+        let mut backup = gerar_backup(&origem, Vec::new()).unwrap();
+        backup.tabelas.get_mut("produtos").unwrap()[0].insert("id_tipo".to_string(), JsonValue::from(9));
+
+        let mut destino = banco();
+        destino.execute("INSERT INTO secoes (id, nome) VALUES (7, 'Hortifruti')", []).unwrap();
+
+        match restaurar_backup(&mut destino, &backup) {
+            Err(ErroRestauracao::Invalido(mensagem)) => assert!(mensagem.contains("produtos"), "{}", mensagem),
+            outro => panic!("esperava backup inválido, veio {:?}", outro),
+        }
+        let secoes: Vec<String> = destino.prepare("SELECT nome FROM secoes").unwrap()
+            .query_map([], |row| row.get(0)).unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(secoes, ["Hortifruti"]);
+    }
+
+    #[tokio::test]
+    async fn backup_maior_que_2mb_passa_pelo_limite_da_rota() {
+        use axum::{body::Body, extract::DefaultBodyLimit, http::{header, Request, StatusCode}, routing::post, Router};
+        use std::sync::{Arc, Mutex};
+        use tower::ServiceExt;
+
+        let origem = banco();
+        origem.execute_batch(
+            "INSERT INTO secoes (id, nome) VALUES (1, 'Padaria');
+             INSERT INTO tipos (id, nome, id_secao) VALUES (1, 'Pães', 1);
+             INSERT INTO produtos (id, nome, id_tipo) VALUES (1, 'Francês', 1);
+             WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 30000)
+             INSERT INTO lotes (id, id_produto, validade, quantidade_total, quantidade_prateleira)
+                 SELECT i, 1, '2030-01-01', i, 0 FROM n;"
+        ).unwrap();
+        let corpo = serde_json::to_vec(&gerar_backup(&origem, Vec::new()).unwrap()).unwrap();
+        assert!(corpo.len() > 2 * 1024 * 1024, "backup com {} bytes", corpo.len());
+
+        // Mesma montagem de `rotas_api`, mas restaurando num banco em memória
+        let destino = Arc::new(Mutex::new(banco()));
+        let enviar = |limite: Option<DefaultBodyLimit>| {
+            let destino = destino.clone();
+            let mut rotas = Router::new().route("/backup/restaurar", post(move |Json(backup): Json<Backup>| async move {
+                restaurar(&mut destino.lock().unwrap(), &backup)
+            }));
+            if let Some(limite) = limite {
+                rotas = rotas.layer(limite);
+            }
+            let requisicao = Request::post("/backup/restaurar")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(corpo.clone()))
+                .unwrap();
+            rotas.oneshot(requisicao)
+        };
+
+        // Sem o limite da rota, os 2 MB padrão do axum recusam o corpo
+        assert_eq!(enviar(None).await.unwrap().status(), StatusCode::BAD_REQUEST);
+
+        let limite = DefaultBodyLimit::max(crate::config::atual().limite_envio_bytes());
+        assert_eq!(enviar(Some(limite)).await.unwrap().status(), StatusCode::OK);
+        let lotes: i32 = destino.lock().unwrap().query_row("SELECT COUNT(*) FROM lotes", [], |row| row.get(0)).unwrap();
+        assert_eq!(lotes, 30000);
+    }
+}
@@ -0,0 +1,378 @@
+use axum::{http::StatusCode, response::Json};
+use chrono::NaiveDate;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::erro::{Entrada, ErroApp, Path as AxumPath, Query};
+use crate::eventos::{self, Evento};
+use crate::init_db;
+
+// ===========================================
+// ESTRUTURAS DE DADOS
+// ===========================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MotivoBaixa {
+    Vencido,
+    Avariado,
+    Furto,
+    ConsumoInterno,
+    Doacao,
+}
+
+impl MotivoBaixa {
+    pub fn como_str(&self) -> &'static str {
+        match self {
+            MotivoBaixa::Vencido => "vencido",
+            MotivoBaixa::Avariado => "avariado",
+            MotivoBaixa::Furto => "furto",
+            MotivoBaixa::ConsumoInterno => "consumo_interno",
+            MotivoBaixa::Doacao => "doacao",
+        }
+    }
+}
+
+/// De onde as unidades baixadas saem: da prateleira (padrão) ou do estoque.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OrigemBaixa {
+    #[default]
+    Prateleira,
+    Estoque,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Baixa {
+    pub id: i32,
+    pub id_lote: i32,
+    pub id_produto: i32,
+    pub produto: String,
+    pub validade: String,
+    pub motivo: String,
+    pub quantidade: i32,
+    /// Quantidade vezes o custo vigente na data da baixa
+    pub valor_centavos: i64,
+    pub observacao: Option<String>,
+    pub data: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ResumoBaixa {
+    pub motivo: String,
+    pub registros: i32,
+    pub quantidade: i32,
+    pub valor_centavos: i64,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BaixaData {
+    pub motivo: MotivoBaixa,
+    pub quantidade: i32,
+    pub observacao: Option<String>,
+    #[serde(default)]
+    pub origem: OrigemBaixa,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct FiltroBaixas {
+    pub motivo: Option<MotivoBaixa>,
+    pub de: Option<String>,
+    pub ate: Option<String>,
+}
+
+impl FiltroBaixas {
+    /// Datas fora do formato AAAA-MM-DD fariam o filtro não achar nada, então são recusadas.
+    fn validar(&self) -> Result<(), ErroApp> {
+        let datas_validas = [&self.de, &self.ate].iter()
+            .all(|data| data.as_deref().is_none_or(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").is_ok()));
+
+        if !datas_validas {
+            return Err(ErroApp::invalido("data_invalida", "As datas devem estar no formato AAAA-MM-DD"));
+        }
+        Ok(())
+    }
+}
+
+const SELECT_BAIXAS: &str =
+    "SELECT b.id, b.id_lote, b.id_produto, COALESCE(p.nome, ''), COALESCE(l.validade, ''),
+            b.motivo, b.quantidade, bv.valor_centavos, b.observacao, b.data
+     FROM baixas b
+     JOIN baixas_valoradas bv ON bv.id = b.id
+     LEFT JOIN produtos p ON p.id = b.id_produto
+     LEFT JOIN lotes l ON l.id = b.id_lote";
+
+fn baixa_da_linha(row: &Row) -> rusqlite::Result<Baixa> {
+    Ok(Baixa {
+        id: row.get(0)?,
+        id_lote: row.get(1)?,
+        id_produto: row.get(2)?,
+        produto: row.get(3)?,
+        validade: row.get(4)?,
+        motivo: row.get(5)?,
+        quantidade: row.get(6)?,
+        valor_centavos: row.get(7)?,
+        observacao: row.get(8)?,
+        data: row.get(9)?,
+    })
+}
+
+// ===========================================
+// HANDLERS DE BAIXAS
+// ===========================================
+
+/// Registra a baixa de uma quantidade do lote. O lote nunca é apagado,
+/// mesmo que a quantidade total chegue a zero.
+#[utoipa::path(post, path = "/lotes/{id}/baixa", tag = "baixas", params(("id" = i32, Path, description = "Id do lote")), request_body(content((BaixaData = "application/json"), (BaixaData = "application/x-www-form-urlencoded"))),
+    responses((status = 201, body = Baixa)))]
+pub async fn registrar_baixa_handler(
+    AxumPath(id): AxumPath<i32>,
+    Entrada(baixa): Entrada<BaixaData>
+) -> Result<(StatusCode, Json<Baixa>), ErroApp> {
+    let mut conn = init_db()?;
+    let registrada = registrar_baixa(&mut conn, id, baixa)?;
+    eventos::publicar(Evento::LoteAlterado { id, id_produto: registrada.id_produto });
+
+    Ok((StatusCode::CREATED, Json(registrada)))
+}
+
+/// Desconta as unidades da prateleira ou do estoque do lote e grava a baixa, tudo numa transação.
+fn registrar_baixa(conn: &mut Connection, id: i32, baixa: BaixaData) -> Result<Baixa, ErroApp> {
+    if baixa.quantidade <= 0 {
+        return Err(ErroApp::invalido("quantidade_invalida", "A quantidade deve ser maior que zero"));
+    }
+
+    let tx = conn.transaction()?;
+
+    let (id_produto, total, na_prateleira): (i32, i32, i32) = tx.query_row(
+        "SELECT id_produto, quantidade_total, quantidade_prateleira FROM lotes WHERE id = ?1",
+        [id],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+    ).optional()?.ok_or(ErroApp::NaoEncontrado("Lote não encontrado"))?;
+
+    let nova_prateleira = match baixa.origem {
+        OrigemBaixa::Prateleira => {
+            if baixa.quantidade > na_prateleira {
+                return Err(ErroApp::invalido(
+                    "estoque_insuficiente",
+                    format!("Só há {} unidades na prateleira deste lote", na_prateleira),
+                ));
+            }
+            na_prateleira - baixa.quantidade
+        }
+        OrigemBaixa::Estoque => {
+            if baixa.quantidade > total - na_prateleira {
+                return Err(ErroApp::invalido(
+                    "estoque_insuficiente",
+                    format!("Só há {} unidades no estoque deste lote", total - na_prateleira),
+                ));
+            }
+            na_prateleira
+        }
+    };
+
+    tx.execute(
+        "UPDATE lotes SET quantidade_total = ?1, quantidade_prateleira = ?2 WHERE id = ?3",
+        params![total - baixa.quantidade, nova_prateleira, id],
+    )?;
+
+    let observacao = baixa.observacao
+        .map(|o| o.trim().to_string())
+        .filter(|o| !o.is_empty());
+
+    tx.execute(
+        "INSERT INTO baixas (id_lote, id_produto, motivo, quantidade, observacao)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![id, id_produto, baixa.motivo.como_str(), baixa.quantidade, observacao],
+    )?;
+
+    let registrada = tx.query_row(
+        &format!("{} WHERE b.id = ?1", SELECT_BAIXAS),
+        [tx.last_insert_rowid()],
+        baixa_da_linha
+    )?;
+
+    tx.commit()?;
+    Ok(registrada)
+}
+
+#[utoipa::path(get, path = "/baixas", tag = "baixas", params(FiltroBaixas),
+    responses((status = 200, body = Vec<Baixa>)))]
+pub async fn listar_baixas_handler(Query(filtro): Query<FiltroBaixas>) -> Result<Json<Vec<Baixa>>, ErroApp> {
+    filtro.validar()?;
+    let conn = init_db()?;
+    Ok(Json(listar_baixas(&conn, &filtro)?))
+}
+
+fn listar_baixas(conn: &Connection, filtro: &FiltroBaixas) -> Result<Vec<Baixa>, ErroApp> {
+    let mut stmt = conn.prepare(&format!(
+        "{}
+         WHERE (?1 IS NULL OR b.motivo = ?1)
+           AND (?2 IS NULL OR date(b.data) >= date(?2))
+           AND (?3 IS NULL OR date(b.data) <= date(?3))
+         ORDER BY b.data DESC, b.id DESC",
+        SELECT_BAIXAS
+    ))?;
+
+    let baixas = stmt.query_map(
+        params![filtro.motivo.map(|m| m.como_str()), &filtro.de, &filtro.ate],
+        baixa_da_linha
+    )?;
+
+    let mut resultado = Vec::new();
+    for baixa in baixas {
+        resultado.push(baixa?);
+    }
+
+    Ok(resultado)
+}
+
+#[utoipa::path(get, path = "/baixas/resumo", tag = "baixas", params(FiltroBaixas),
+    responses((status = 200, description = "Totais por motivo", body = Vec<ResumoBaixa>)))]
+pub async fn resumo_baixas_handler(Query(filtro): Query<FiltroBaixas>) -> Result<Json<Vec<ResumoBaixa>>, ErroApp> {
+    filtro.validar()?;
+    let conn = init_db()?;
+
+    let mut stmt = conn.prepare(
+        "SELECT b.motivo, COUNT(*), COALESCE(SUM(b.quantidade), 0), COALESCE(SUM(bv.valor_centavos), 0)
+         FROM baixas b
+         JOIN baixas_valoradas bv ON bv.id = b.id
+         WHERE (?1 IS NULL OR b.motivo = ?1)
+           AND (?2 IS NULL OR date(b.data) >= date(?2))
+           AND (?3 IS NULL OR date(b.data) <= date(?3))
+         GROUP BY b.motivo
+         ORDER BY b.motivo"
+    )?;
+
+    let resumo = stmt.query_map(
+        params![filtro.motivo.map(|m| m.como_str()), filtro.de, filtro.ate],
+        |row| {
+            Ok(ResumoBaixa {
+                motivo: row.get(0)?,
+                registros: row.get(1)?,
+                quantidade: row.get(2)?,
+                valor_centavos: row.get(3)?,
+            })
+        }
+    )?;
+
+    let mut resultado = Vec::new();
+    for item in resumo {
+        resultado.push(item?);
+    }
+
+    Ok(Json(resultado))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn banco_com_lote() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::criar_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO secoes (id, nome) VALUES (1, 'Mercearia');
+             INSERT INTO tipos (id, nome, id_secao) VALUES (1, 'Grãos', 1);
+             INSERT INTO produtos (id, nome, id_tipo) VALUES (1, 'ARROZ', 1);
+             INSERT INTO lotes (id, id_produto, validade, quantidade_total, quantidade_prateleira)
+                 VALUES (1, 1, '2030-01-01', 10, 4);"
+        ).unwrap();
+        conn
+    }
+
+    fn baixa(quantidade: i32, origem: OrigemBaixa) -> BaixaData {
+        BaixaData { motivo: MotivoBaixa::Avariado, quantidade, observacao: None, origem }
+    }
+
+    fn quantidades(conn: &Connection) -> (i32, i32) {
+        conn.query_row(
+            "SELECT quantidade_total, quantidade_prateleira FROM lotes WHERE id = 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?))
+        ).unwrap()
+    }
+
+    #[test]
+    fn baixa_desconta_da_prateleira_ou_do_estoque() {
+        let mut conn = banco_com_lote();
+
+        registrar_baixa(&mut conn, 1, baixa(3, OrigemBaixa::Prateleira)).unwrap();
+        assert_eq!(quantidades(&conn), (7, 1));
+
+        // Do estoque, a prateleira fica como está
+        let registrada = registrar_baixa(&mut conn, 1, baixa(2, OrigemBaixa::Estoque)).unwrap();
+        assert_eq!(quantidades(&conn), (5, 1));
+        assert_eq!((registrada.id_produto, registrada.quantidade), (1, 2));
+        assert_eq!(registrada.motivo, "avariado");
+    }
+
+    #[test]
+    fn baixa_maior_que_o_disponivel_e_recusada() {
+        let mut conn = banco_com_lote();
+
+        let erro = registrar_baixa(&mut conn, 1, baixa(5, OrigemBaixa::Prateleira)).unwrap_err();
+        assert_eq!(erro.codigo(), "estoque_insuficiente");
+        // 10 no total, 4 na prateleira: só 6 no estoque
+        let erro = registrar_baixa(&mut conn, 1, baixa(7, OrigemBaixa::Estoque)).unwrap_err();
+        assert_eq!(erro.codigo(), "estoque_insuficiente");
+
+        assert_eq!(quantidades(&conn), (10, 4));
+        let baixas: i64 = conn.query_row("SELECT COUNT(*) FROM baixas", [], |row| row.get(0)).unwrap();
+        assert_eq!(baixas, 0);
+    }
+
+    #[test]
+    fn falha_ao_gravar_a_baixa_desfaz_o_desconto_do_lote() {
+        let mut conn = banco_com_lote();
+        conn.execute_batch(
+            "CREATE TRIGGER recusa_baixa BEFORE INSERT ON baixas
+             BEGIN SELECT RAISE(ABORT, 'falha simulada'); END;"
+        ).unwrap();
+
+        assert!(registrar_baixa(&mut conn, 1, baixa(2, OrigemBaixa::Prateleira)).is_err());
+        assert_eq!(quantidades(&conn), (10, 4));
+    }
+
+    #[test]
+    fn filtro_por_periodo_e_motivo() {
+        let mut conn = banco_com_lote();
+        conn.execute_batch(
+            "INSERT INTO baixas (id_lote, id_produto, motivo, quantidade, data) VALUES
+                 (1, 1, 'vencido', 1, '2026-01-10 08:00:00'),
+                 (1, 1, 'furto', 1, '2026-01-20 23:59:00'),
+                 (1, 1, 'vencido', 1, '2026-02-01 09:00:00');"
+        ).unwrap();
+        registrar_baixa(&mut conn, 1, baixa(1, OrigemBaixa::Prateleira)).unwrap();
+
+        let filtro = |motivo, de: &str, ate: &str| FiltroBaixas {
+            motivo,
+            de: Some(de.to_string()),
+            ate: Some(ate.to_string()),
+        };
+        let datas = |filtro: FiltroBaixas| -> Vec<String> {
+            listar_baixas(&conn, &filtro).unwrap().into_iter().map(|b| b.data).collect()
+        };
+
+        // O último dia do período entra inteiro
+        assert_eq!(
+            datas(filtro(None, "2026-01-10", "2026-01-20")),
+            ["2026-01-20 23:59:00", "2026-01-10 08:00:00"]
+        );
+        assert_eq!(
+            datas(filtro(Some(MotivoBaixa::Vencido), "2026-01-01", "2026-02-28")),
+            ["2026-02-01 09:00:00", "2026-01-10 08:00:00"]
+        );
+    }
+
+    #[test]
+    fn datas_fora_do_formato_sao_recusadas() {
+        let filtro = |de: &str| FiltroBaixas { motivo: None, de: Some(de.to_string()), ate: None };
+
+        assert!(filtro("2026-01-31").validar().is_ok());
+        assert_eq!(filtro("31/01/2026").validar().unwrap_err().codigo(), "data_invalida");
+        assert_eq!(filtro("2026-02-30").validar().unwrap_err().codigo(), "data_invalida");
+        assert!(FiltroBaixas { motivo: None, de: None, ate: Some("ontem".into()) }.validar().is_err());
+    }
+}
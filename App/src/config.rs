@@ -0,0 +1,408 @@
+use clap::Parser;
+use serde::Deserialize;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::faixas_validade;
+
+/// Arquivo lido quando nem `--config` nem `VALIDADE_CONFIG` são informados.
+const ARQUIVO_PADRAO: &str = "validade.toml";
+const NIVEIS_LOG: [&str; 5] = ["error", "warn", "info", "debug", "trace"];
+const FORMATOS_LOG: [&str; 2] = ["texto", "json"];
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+// ===========================================
+// ESTRUTURAS DE DADOS
+// ===========================================
+
+/// Configuração do servidor. Cada campo vem, em ordem de prioridade, da linha
+/// de comando, de uma variável de ambiente, do arquivo TOML ou do padrão.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Endereço e porta onde o servidor escuta
+    pub endereco: String,
+    /// Pasta com `produtos.db`
+    pub dados: PathBuf,
+    /// Pasta com o front-end
+    pub estaticos: PathBuf,
+    /// Origens liberadas no CORS; `["*"]` libera todas
+    pub cors_origens: Vec<String>,
+    /// error, warn, info, debug ou trace
+    pub log: String,
+    /// `texto` (legível) ou `json` (uma linha por evento)
+    pub log_formato: String,
+    /// Quantos arquivos diários de log manter em `dados/logs`; 0 desliga
+    pub log_arquivos: usize,
+    /// Serve a página de documentação da API em `/api/docs`
+    pub documentacao: bool,
+    /// Por quantas horas a resposta de uma escrita com `Idempotency-Key` fica guardada
+    pub idempotencia_horas: u32,
+    /// Tamanho máximo, em MB, de uma planilha ou backup enviado ao servidor
+    pub limite_envio_mb: usize,
+    pub alertas: ConfigAlertas,
+    pub copias: ConfigCopias,
+    pub tls: ConfigTls,
+}
+
+/// Valores usados quando a requisição não informa o parâmetro.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigAlertas {
+    /// Lotes que vencem em até tantos dias contam como "em risco"
+    pub dias_risco: i32,
+    /// Quantos dias de vendas entram na média diária
+    pub janela_vendas_dias: i32,
+    /// Quantos dias de venda o pedido de reposição deve cobrir
+    pub dias_cobertura: i32,
+    /// Limites das faixas de validade
+    pub limites_faixas: Vec<i32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigCopias {
+    pub diretorio: PathBuf,
+    /// Quantas cópias diárias manter
+    pub diarias: usize,
+    /// Quantas semanas manter uma cópia
+    pub semanais: usize,
+    /// Hora do dia (0 a 23) em que a cópia é feita
+    pub hora: u32,
+}
+
+/// HTTPS opcional. Com TLS ligado, `endereco` passa a só redirecionar para `tls.endereco`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigTls {
+    pub ativo: bool,
+    /// Endereço e porta do HTTPS
+    pub endereco: String,
+    /// Certificado PEM (padrão: dados/tls/certificado.pem)
+    pub certificado: Option<PathBuf>,
+    /// Chave privada PEM (padrão: dados/tls/chave.pem)
+    pub chave: Option<PathBuf>,
+    /// Gera um certificado autoassinado se os arquivos não existirem
+    pub autoassinado: bool,
+    /// Nomes ou IPs extras para o certificado autoassinado
+    pub nomes: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            endereco: "0.0.0.0:3000".to_string(),
+            dados: PathBuf::from("./dados"),
+            estaticos: PathBuf::from("./dist"),
+            cors_origens: vec!["*".to_string()],
+            log: "info".to_string(),
+            log_formato: "texto".to_string(),
+            log_arquivos: 14,
+            documentacao: true,
+            idempotencia_horas: 24,
+            limite_envio_mb: 50,
+            alertas: ConfigAlertas::default(),
+            copias: ConfigCopias::default(),
+            tls: ConfigTls::default(),
+        }
+    }
+}
+
+impl Default for ConfigAlertas {
+    fn default() -> Self {
+        ConfigAlertas {
+            dias_risco: 7,
+            janela_vendas_dias: 14,
+            dias_cobertura: 7,
+            limites_faixas: vec![7, 15, 30, 60],
+        }
+    }
+}
+
+impl Default for ConfigCopias {
+    fn default() -> Self {
+        ConfigCopias {
+            diretorio: PathBuf::from("./copias"),
+            diarias: 7,
+            semanais: 4,
+            hora: 2,
+        }
+    }
+}
+
+impl Default for ConfigTls {
+    fn default() -> Self {
+        ConfigTls {
+            ativo: false,
+            endereco: "0.0.0.0:3443".to_string(),
+            certificado: None,
+            chave: None,
+            autoassinado: false,
+            nomes: Vec::new(),
+        }
+    }
+}
+
+// ===========================================
+// LINHA DE COMANDO E AMBIENTE
+// ===========================================
+
+#[derive(Debug, Parser)]
+#[command(name = "validade-server", about = "Servidor de controle de validade")]
+pub struct Argumentos {
+    /// Arquivo de configuração TOML (padrão: validade.toml, se existir)
+    #[arg(long, env = "VALIDADE_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    #[arg(long, env = "VALIDADE_ENDERECO")]
+    pub endereco: Option<String>,
+
+    #[arg(long, env = "VALIDADE_DADOS")]
+    pub dados: Option<PathBuf>,
+
+    #[arg(long, env = "VALIDADE_ESTATICOS")]
+    pub estaticos: Option<PathBuf>,
+
+    /// Origens separadas por vírgula, ou * para todas
+    #[arg(long, env = "VALIDADE_CORS_ORIGENS", value_delimiter = ',')]
+    pub cors_origens: Option<Vec<String>>,
+
+    #[arg(long, env = "VALIDADE_LOG")]
+    pub log: Option<String>,
+
+    #[arg(long, env = "VALIDADE_LOG_FORMATO")]
+    pub log_formato: Option<String>,
+
+    #[arg(long, env = "VALIDADE_LOG_ARQUIVOS")]
+    pub log_arquivos: Option<usize>,
+
+    #[arg(long, env = "VALIDADE_DOCUMENTACAO")]
+    pub documentacao: Option<bool>,
+
+    #[arg(long, env = "VALIDADE_IDEMPOTENCIA_HORAS")]
+    pub idempotencia_horas: Option<u32>,
+
+    #[arg(long, env = "VALIDADE_LIMITE_ENVIO_MB")]
+    pub limite_envio_mb: Option<usize>,
+
+    #[arg(long, env = "VALIDADE_DIAS_RISCO")]
+    pub dias_risco: Option<i32>,
+
+    #[arg(long, env = "VALIDADE_JANELA_VENDAS_DIAS")]
+    pub janela_vendas_dias: Option<i32>,
+
+    #[arg(long, env = "VALIDADE_DIAS_COBERTURA")]
+    pub dias_cobertura: Option<i32>,
+
+    #[arg(long, env = "VALIDADE_LIMITES_FAIXAS", value_delimiter = ',')]
+    pub limites_faixas: Option<Vec<i32>>,
+
+    #[arg(long, env = "VALIDADE_COPIAS_DIR")]
+    pub copias_dir: Option<PathBuf>,
+
+    #[arg(long, env = "VALIDADE_COPIAS_DIARIAS")]
+    pub copias_diarias: Option<usize>,
+
+    #[arg(long, env = "VALIDADE_COPIAS_SEMANAIS")]
+    pub copias_semanais: Option<usize>,
+
+    #[arg(long, env = "VALIDADE_COPIAS_HORA")]
+    pub copias_hora: Option<u32>,
+
+    /// Liga o HTTPS
+    #[arg(long, env = "VALIDADE_TLS")]
+    pub tls: Option<bool>,
+
+    #[arg(long, env = "VALIDADE_TLS_ENDERECO")]
+    pub tls_endereco: Option<String>,
+
+    #[arg(long, env = "VALIDADE_TLS_CERTIFICADO")]
+    pub tls_certificado: Option<PathBuf>,
+
+    #[arg(long, env = "VALIDADE_TLS_CHAVE")]
+    pub tls_chave: Option<PathBuf>,
+
+    /// Gera um certificado autoassinado se não houver um
+    #[arg(long, env = "VALIDADE_TLS_AUTOASSINADO")]
+    pub tls_autoassinado: Option<bool>,
+
+    /// Cria este admin se ainda não houver usuários
+    #[arg(long, env = "ADMIN_USUARIO")]
+    pub admin_usuario: Option<String>,
+
+    #[arg(long, env = "ADMIN_SENHA", hide_env_values = true)]
+    pub admin_senha: Option<String>,
+}
+
+// ===========================================
+// CARREGAMENTO E VALIDAÇÃO
+// ===========================================
+
+/// Caminhos relativos no arquivo valem a partir da pasta do arquivo, não da pasta atual.
+fn relativo_ao_arquivo(caminho: &mut PathBuf, pasta: &Path) {
+    if caminho.is_relative() {
+        *caminho = pasta.join(&*caminho);
+    }
+}
+
+fn ler_arquivo(caminho: &Path) -> Result<Config, String> {
+    let texto = fs::read_to_string(caminho)
+        .map_err(|e| format!("não foi possível ler {}: {}", caminho.display(), e))?;
+    let mut config: Config = toml::from_str(&texto)
+        .map_err(|e| format!("{} é inválido: {}", caminho.display(), e))?;
+
+    let pasta = caminho.parent().unwrap_or(Path::new("."));
+    relativo_ao_arquivo(&mut config.dados, pasta);
+    relativo_ao_arquivo(&mut config.estaticos, pasta);
+    relativo_ao_arquivo(&mut config.copias.diretorio, pasta);
+    if let Some(certificado) = &mut config.tls.certificado {
+        relativo_ao_arquivo(certificado, pasta);
+    }
+    if let Some(chave) = &mut config.tls.chave {
+        relativo_ao_arquivo(chave, pasta);
+    }
+
+    Ok(config)
+}
+
+impl Config {
+    /// Junta padrão, arquivo, ambiente e linha de comando.
+    pub fn carregar(argumentos: &Argumentos) -> Result<Config, Vec<String>> {
+        let mut config = match &argumentos.config {
+            Some(caminho) => ler_arquivo(caminho).map_err(|e| vec![e])?,
+            None if Path::new(ARQUIVO_PADRAO).exists() => ler_arquivo(Path::new(ARQUIVO_PADRAO)).map_err(|e| vec![e])?,
+            None => Config::default(),
+        };
+
+        let a = argumentos;
+        if let Some(v) = &a.endereco { config.endereco = v.clone(); }
+        if let Some(v) = &a.dados { config.dados = v.clone(); }
+        if let Some(v) = &a.estaticos { config.estaticos = v.clone(); }
+        if let Some(v) = &a.cors_origens { config.cors_origens = v.clone(); }
+        if let Some(v) = &a.log { config.log = v.clone(); }
+        if let Some(v) = &a.log_formato { config.log_formato = v.clone(); }
+        if let Some(v) = a.log_arquivos { config.log_arquivos = v; }
+        if let Some(v) = a.documentacao { config.documentacao = v; }
+        if let Some(v) = a.idempotencia_horas { config.idempotencia_horas = v; }
+        if let Some(v) = a.limite_envio_mb { config.limite_envio_mb = v; }
+        if let Some(v) = a.dias_risco { config.alertas.dias_risco = v; }
+        if let Some(v) = a.janela_vendas_dias { config.alertas.janela_vendas_dias = v; }
+        if let Some(v) = a.dias_cobertura { config.alertas.dias_cobertura = v; }
+        if let Some(v) = &a.limites_faixas { config.alertas.limites_faixas = v.clone(); }
+        if let Some(v) = &a.copias_dir { config.copias.diretorio = v.clone(); }
+        if let Some(v) = a.copias_diarias { config.copias.diarias = v; }
+        if let Some(v) = a.copias_semanais { config.copias.semanais = v; }
+        if let Some(v) = a.copias_hora { config.copias.hora = v; }
+        if let Some(v) = a.tls { config.tls.ativo = v; }
+        if let Some(v) = &a.tls_endereco { config.tls.endereco = v.clone(); }
+        if let Some(v) = &a.tls_certificado { config.tls.certificado = Some(v.clone()); }
+        if let Some(v) = &a.tls_chave { config.tls.chave = Some(v.clone()); }
+        if let Some(v) = a.tls_autoassinado { config.tls.autoassinado = v; }
+
+        config.validar()?;
+        Ok(config)
+    }
+
+    /// Confere tudo de uma vez, para mostrar todos os problemas juntos.
+    pub fn validar(&self) -> Result<(), Vec<String>> {
+        let mut erros = Vec::new();
+
+        if self.endereco.parse::<SocketAddr>().is_err() {
+            erros.push(format!("endereco \"{}\" deve ser IP:porta, por exemplo 0.0.0.0:3000", self.endereco));
+        }
+        if let Err(e) = fs::create_dir_all(&self.dados) {
+            erros.push(format!("dados: não foi possível criar a pasta {}: {}", self.dados.display(), e));
+        }
+        if !self.estaticos.is_dir() {
+            erros.push(format!("estaticos: a pasta {} não existe", self.estaticos.display()));
+        }
+        let todas = self.cors_origens.iter().any(|origem| origem == "*");
+        if todas && self.cors_origens.len() > 1 {
+            erros.push("cors_origens: \"*\" não pode ser combinado com outras origens".to_string());
+        }
+        for origem in self.cors_origens.iter().filter(|origem| *origem != "*") {
+            let valida = (origem.starts_with("http://") || origem.starts_with("https://"))
+                && !origem.ends_with('/')
+                && origem.parse::<axum::http::HeaderValue>().is_ok();
+            if !valida {
+                erros.push(format!("cors_origens: \"{}\" deve ser como http://192.168.0.10:3000, sem barra no fim", origem));
+            }
+        }
+        if !NIVEIS_LOG.contains(&self.log.as_str()) {
+            erros.push(format!("log \"{}\" deve ser um de: {}", self.log, NIVEIS_LOG.join(", ")));
+        }
+        if !FORMATOS_LOG.contains(&self.log_formato.as_str()) {
+            erros.push(format!("log_formato \"{}\" deve ser um de: {}", self.log_formato, FORMATOS_LOG.join(", ")));
+        }
+        if self.idempotencia_horas == 0 {
+            erros.push("idempotencia_horas deve ser maior que zero".to_string());
+        }
+        if self.limite_envio_mb == 0 || self.limite_envio_mb > 1024 {
+            erros.push("limite_envio_mb deve estar entre 1 e 1024".to_string());
+        }
+        if self.alertas.dias_risco < 0 {
+            erros.push("alertas.dias_risco não pode ser negativo".to_string());
+        }
+        if self.alertas.janela_vendas_dias <= 0 {
+            erros.push("alertas.janela_vendas_dias deve ser maior que zero".to_string());
+        }
+        if self.alertas.dias_cobertura < 0 {
+            erros.push("alertas.dias_cobertura não pode ser negativo".to_string());
+        }
+        if !faixas_validade::limites_validos(&self.alertas.limites_faixas) {
+            erros.push(format!("alertas.limites_faixas deve ter números crescentes, de 0 a {}", i32::MAX - 1));
+        }
+        if self.copias.diarias == 0 && self.copias.semanais == 0 {
+            erros.push("copias.diarias e copias.semanais não podem ser ambos zero: nenhuma cópia seria mantida".to_string());
+        }
+        if self.copias.hora > 23 {
+            erros.push("copias.hora deve estar entre 0 e 23".to_string());
+        }
+        if self.tls.ativo {
+            match self.tls.endereco.parse::<SocketAddr>() {
+                Err(_) => erros.push(format!("tls.endereco \"{}\" deve ser IP:porta, por exemplo 0.0.0.0:3443", self.tls.endereco)),
+                Ok(https) if Ok(https) == self.endereco.parse::<SocketAddr>() => {
+                    erros.push("tls.endereco deve ser diferente de endereco, que passa a redirecionar para o HTTPS".to_string());
+                }
+                Ok(_) => {}
+            }
+            if self.tls.certificado.is_some() != self.tls.chave.is_some() {
+                erros.push("tls.certificado e tls.chave devem ser informados juntos".to_string());
+            }
+            if !self.tls.autoassinado {
+                for (campo, caminho) in [("certificado", &self.tls.certificado), ("chave", &self.tls.chave)] {
+                    match caminho {
+                        Some(caminho) if !caminho.is_file() => {
+                            erros.push(format!("tls.{}: o arquivo {} não existe", campo, caminho.display()));
+                        }
+                        None => erros.push(format!("tls.{} é obrigatório quando tls.autoassinado = false", campo)),
+                        Some(_) => {}
+                    }
+                }
+            }
+        }
+
+        if erros.is_empty() { Ok(()) } else { Err(erros) }
+    }
+
+    pub fn caminho_banco(&self) -> PathBuf {
+        self.dados.join("produtos.db")
+    }
+
+    pub fn limite_envio_bytes(&self) -> usize {
+        self.limite_envio_mb * 1024 * 1024
+    }
+}
+
+/// Define a configuração do processo; só pode ser chamada uma vez, ao subir.
+pub fn definir(config: Config) {
+    let _ = CONFIG.set(config);
+}
+
+/// Configuração atual; antes de `definir` (nos testes, por exemplo) vale o padrão.
+pub fn atual() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
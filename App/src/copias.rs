@@ -0,0 +1,274 @@
+use axum::{
+    http::header,
+    response::{IntoResponse, Json},
+};
+use chrono::{Datelike, Duration, Local, NaiveDate};
+use rusqlite::{Connection, MAIN_DB};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use utoipa::ToSchema;
+
+use crate::config::{self, ConfigCopias};
+use crate::erro::{ErroApp, Path as AxumPath};
+
+/// Bancos copiados, relativos à pasta de dados.
+const BANCOS: [&str; 1] = ["produtos.db"];
+
+// ===========================================
+// ESTRUTURAS DE DADOS
+// ===========================================
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ArquivoCopia {
+    pub nome: String,
+    pub tamanho_bytes: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Copia {
+    pub data: String,
+    pub arquivos: Vec<ArquivoCopia>,
+}
+
+// ===========================================
+// CÓPIA E RETENÇÃO
+// ===========================================
+
+pub type ErroCopia = Box<dyn std::error::Error + Send + Sync>;
+
+/// Copia os bancos com a API de backup online do SQLite, que gera uma cópia
+/// consistente mesmo com o servidor gravando. Cada dia fica numa pasta
+/// AAAA-MM-DD; repetir no mesmo dia substitui a cópia do dia.
+pub fn copiar_bancos(config: &ConfigCopias, data: NaiveDate) -> Result<PathBuf, ErroCopia> {
+    let pasta = config.diretorio.join(data.format("%Y-%m-%d").to_string());
+    fs::create_dir_all(&pasta)?;
+
+    for banco in BANCOS {
+        let origem = crate::config::atual().dados.join(banco);
+        if !origem.exists() {
+            continue;
+        }
+
+        // Escreve num arquivo temporário e renomeia, para nunca deixar meia cópia no lugar
+        let temporario = pasta.join(format!("{}.parcial", banco));
+        let _ = fs::remove_file(&temporario);
+        Connection::open(&origem)?.backup(MAIN_DB, &temporario, None)?;
+        fs::rename(&temporario, pasta.join(banco))?;
+    }
+
+    Ok(pasta)
+}
+
+fn datas_das_copias(diretorio: &Path) -> Vec<NaiveDate> {
+    let mut datas: Vec<NaiveDate> = fs::read_dir(diretorio)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entrada| entrada.path().is_dir())
+        .filter_map(|entrada| NaiveDate::parse_from_str(&entrada.file_name().to_string_lossy(), "%Y-%m-%d").ok())
+        .collect();
+    datas.sort_unstable_by(|a, b| b.cmp(a));
+    datas
+}
+
+/// Datas a manter: as `diarias` mais recentes e, das `semanais` semanas mais
+/// recentes que têm cópia, a última cópia de cada semana.
+fn datas_a_manter(datas: &[NaiveDate], diarias: usize, semanais: usize) -> HashSet<NaiveDate> {
+    let mut manter: HashSet<NaiveDate> = datas.iter().take(diarias).copied().collect();
+
+    let mut semanas = HashSet::new();
+    for data in datas {
+        if semanas.len() == semanais {
+            break;
+        }
+        let semana = data.iso_week();
+        if semanas.insert((semana.year(), semana.week())) {
+            manter.insert(*data);
+        }
+    }
+
+    manter
+}
+
+/// Apaga as pastas de cópia que saíram da janela de retenção.
+pub fn aplicar_retencao(config: &ConfigCopias) -> usize {
+    let datas = datas_das_copias(&config.diretorio);
+    let manter = datas_a_manter(&datas, config.diarias, config.semanais);
+
+    datas.iter()
+        .filter(|data| !manter.contains(data))
+        .filter(|data| fs::remove_dir_all(config.diretorio.join(data.format("%Y-%m-%d").to_string())).is_ok())
+        .count()
+}
+
+fn copia_diaria(config: &ConfigCopias) {
+    let hoje = Local::now().date_naive();
+    match copiar_bancos(config, hoje) {
+        Ok(pasta) => tracing::info!("💾 Cópia dos bancos gravada em {}", pasta.display()),
+        Err(e) => tracing::error!("❌ Erro ao copiar os bancos: {}", e),
+    }
+    let apagadas = aplicar_retencao(config);
+    if apagadas > 0 {
+        tracing::info!("🧹 {} cópias antigas apagadas", apagadas);
+    }
+}
+
+/// Faz a cópia de hoje ao subir (se ainda não existir) e depois todo dia na hora configurada.
+pub fn iniciar_agendador() {
+    tokio::spawn(async {
+        let config = config::atual().copias.clone();
+        let hoje = Local::now().date_naive();
+        if !datas_das_copias(&config.diretorio).contains(&hoje) {
+            let config = config.clone();
+            let _ = tokio::task::spawn_blocking(move || copia_diaria(&config)).await;
+        }
+
+        loop {
+            let agora = Local::now();
+            let mut proxima = agora.date_naive().and_hms_opt(config.hora, 0, 0)
+                .and_then(|d| d.and_local_timezone(Local).earliest());
+            if proxima.is_none_or(|p| p <= agora) {
+                proxima = (agora.date_naive() + Duration::days(1)).and_hms_opt(config.hora, 0, 0)
+                    .and_then(|d| d.and_local_timezone(Local).earliest());
+            }
+            let espera = proxima
+                .and_then(|p| (p - Local::now()).to_std().ok())
+                .unwrap_or(std::time::Duration::from_secs(60 * 60));
+            tokio::time::sleep(espera).await;
+
+            let config = config.clone();
+            let _ = tokio::task::spawn_blocking(move || copia_diaria(&config)).await;
+        }
+    });
+}
+
+// ===========================================
+// HANDLERS DE CÓPIAS (ADMIN)
+// ===========================================
+
+// As cópias têm a tabela de usuários com os hashes das senhas: as rotas
+// passam por `usuarios::exigir_admin` (cookie `sessao` de um admin).
+
+#[utoipa::path(get, path = "/admin/copias", tag = "admin",
+    responses((status = 200, body = Vec<Copia>),
+        (status = 401, description = "Sem sessão: entre pelo /login"),
+        (status = 403, description = "O usuário da sessão não é admin")))]
+pub async fn listar_copias_handler() -> Result<Json<Vec<Copia>>, ErroApp> {
+    let config = config::atual().copias.clone();
+
+    let mut copias = Vec::new();
+    for data in datas_das_copias(&config.diretorio) {
+        let nome_pasta = data.format("%Y-%m-%d").to_string();
+        let arquivos = BANCOS.iter()
+            .filter_map(|banco| {
+                let metadados = fs::metadata(config.diretorio.join(&nome_pasta).join(banco)).ok()?;
+                Some(ArquivoCopia { nome: banco.to_string(), tamanho_bytes: metadados.len() })
+            })
+            .collect();
+        copias.push(Copia { data: nome_pasta, arquivos });
+    }
+
+    Ok(Json(copias))
+}
+
+#[utoipa::path(post, path = "/admin/copias", tag = "admin",
+    responses((status = 200, body = String),
+        (status = 401, description = "Sem sessão: entre pelo /login"),
+        (status = 403, description = "O usuário da sessão não é admin")))]
+pub async fn criar_copia_handler() -> Result<String, ErroApp> {
+    let config = config::atual().copias.clone();
+
+    let resultado = tokio::task::spawn_blocking(move || {
+        let pasta = copiar_bancos(&config, Local::now().date_naive())?;
+        aplicar_retencao(&config);
+        Ok::<_, ErroCopia>(pasta)
+    }).await.map_err(ErroApp::interno)?;
+
+    let pasta = resultado.map_err(ErroApp::interno)?;
+    Ok(format!("Cópia gravada em {}", pasta.display()))
+}
+
+/// Só aceita uma data AAAA-MM-DD e um dos bancos conhecidos, para não servir outros arquivos.
+#[utoipa::path(get, path = "/admin/copias/{data}/{banco}", tag = "admin",
+    params(
+        ("data" = String, Path, description = "Data da cópia, AAAA-MM-DD"),
+        ("banco" = String, Path, description = "Arquivo do banco, ex.: produtos.db"),
+    ),
+    responses((status = 200, content_type = "application/vnd.sqlite3", body = [u8]),
+        (status = 401, description = "Sem sessão: entre pelo /login"),
+        (status = 403, description = "O usuário da sessão não é admin")))]
+pub async fn baixar_copia_handler(AxumPath((data, banco)): AxumPath<(String, String)>) -> Result<impl IntoResponse, ErroApp> {
+    const NAO_ENCONTRADA: ErroApp = ErroApp::NaoEncontrado("Cópia não encontrada");
+
+    let data = NaiveDate::parse_from_str(&data, "%Y-%m-%d").map_err(|_| NAO_ENCONTRADA)?;
+    if !BANCOS.contains(&banco.as_str()) {
+        return Err(NAO_ENCONTRADA);
+    }
+
+    let config = config::atual().copias.clone();
+    let caminho = config.diretorio.join(data.format("%Y-%m-%d").to_string()).join(&banco);
+    let conteudo = fs::read(caminho).map_err(|_| NAO_ENCONTRADA)?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/vnd.sqlite3".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}-{}\"", data.format("%Y-%m-%d"), banco)),
+        ],
+        conteudo,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(texto: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(texto, "%Y-%m-%d").unwrap()
+    }
+
+    /// Como `datas_das_copias`: da mais recente para a mais antiga.
+    fn dias_seguidos(ultima: &str, quantidade: i64) -> Vec<NaiveDate> {
+        (0..quantidade).map(|i| data(ultima) - Duration::days(i)).collect()
+    }
+
+    fn ordenadas(manter: HashSet<NaiveDate>) -> Vec<String> {
+        let mut datas: Vec<NaiveDate> = manter.into_iter().collect();
+        datas.sort_unstable();
+        datas.iter().map(|data| data.to_string()).collect()
+    }
+
+    #[test]
+    fn mantem_as_diarias_e_a_ultima_de_cada_semana() {
+        // 2026-03-01 é domingo: as semanas ISO terminam em 01/03, 22/02, 15/02...
+        let datas = dias_seguidos("2026-03-03", 30);
+
+        assert_eq!(ordenadas(datas_a_manter(&datas, 3, 0)), ["2026-03-01", "2026-03-02", "2026-03-03"]);
+        assert_eq!(
+            ordenadas(datas_a_manter(&datas, 0, 4)),
+            ["2026-02-15", "2026-02-22", "2026-03-01", "2026-03-03"]
+        );
+        // As duas janelas se sobrepõem na semana atual
+        assert_eq!(
+            ordenadas(datas_a_manter(&datas, 3, 2)),
+            ["2026-03-01", "2026-03-02", "2026-03-03"]
+        );
+    }
+
+    #[test]
+    fn semana_que_atravessa_o_ano() {
+        // 2025-12-29 a 2026-01-04 é a semana 1 de 2026; 2025-12-28 fecha a 52 de 2025
+        let datas = [data("2026-01-02"), data("2025-12-30"), data("2025-12-28"), data("2025-12-22")];
+
+        assert_eq!(ordenadas(datas_a_manter(&datas, 0, 2)), ["2025-12-28", "2026-01-02"]);
+    }
+
+    #[test]
+    fn menos_copias_que_os_limites_mantem_todas() {
+        let datas = [data("2026-03-03"), data("2026-02-10")];
+
+        assert_eq!(ordenadas(datas_a_manter(&datas, 7, 4)), ["2026-02-10", "2026-03-03"]);
+        assert!(datas_a_manter(&[], 7, 4).is_empty());
+    }
+}
@@ -0,0 +1,297 @@
+use axum::{
+    async_trait,
+    extract::{
+        rejection::{FormRejection, JsonRejection, PathRejection, QueryRejection},
+        FromRequest, FromRequestParts, Request,
+    },
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use rusqlite::ErrorCode;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt::Display;
+use utoipa::ToSchema;
+
+// ===========================================
+// ERRO DA APLICAÇÃO
+// ===========================================
+
+/// Erro devolvido pelos handlers. Vira uma resposta JSON
+/// `{"codigo": "...", "mensagem": "..."}` com o status HTTP correspondente.
+/// Os códigos são estáveis (o front-end pode depender deles); as mensagens
+/// são para mostrar ao usuário.
+#[derive(Debug)]
+pub enum ErroApp {
+    /// 401 — sem sessão válida; a mensagem diz o que fazer
+    NaoAutenticado(&'static str),
+    /// 403 — a sessão é válida, mas o usuário não tem permissão
+    Proibido(&'static str),
+    /// 404 — com a mensagem, ex.: "Lote não encontrado"
+    NaoEncontrado(&'static str),
+    /// 400 — entrada inválida. `detalhe` é o erro de origem (do SQLite, por
+    /// exemplo), que vai só para o log
+    Invalido { codigo: &'static str, mensagem: String, detalhe: Option<String> },
+    /// 409 — conflito com o que já está no banco
+    Conflito { codigo: &'static str, mensagem: String, detalhe: Option<String> },
+    /// 500 — o detalhe vai para o log, nunca para o cliente
+    Interno(String),
+}
+
+/// Corpo JSON de toda resposta de erro.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CorpoErro<'a> {
+    /// Estável, ex.: `nao_encontrado`, `duplicado`, `estoque_insuficiente`
+    codigo: &'a str,
+    mensagem: &'a str,
+}
+
+impl ErroApp {
+    pub fn invalido(codigo: &'static str, mensagem: impl Into<String>) -> Self {
+        ErroApp::Invalido { codigo, mensagem: mensagem.into(), detalhe: None }
+    }
+
+    pub fn conflito(codigo: &'static str, mensagem: impl Into<String>) -> Self {
+        ErroApp::Conflito { codigo, mensagem: mensagem.into(), detalhe: None }
+    }
+
+    /// Guarda o erro de origem para o log, sem mudar a resposta.
+    fn com_detalhe(mut self, origem: impl Display) -> Self {
+        if let ErroApp::Invalido { detalhe, .. } | ErroApp::Conflito { detalhe, .. } = &mut self {
+            *detalhe = Some(origem.to_string());
+        }
+        self
+    }
+
+    /// Para `map_err` em erros que não são culpa do cliente (E/S, geração de arquivos...).
+    pub fn interno<E: Display>(erro: E) -> Self {
+        ErroApp::Interno(erro.to_string())
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErroApp::NaoAutenticado(_) => StatusCode::UNAUTHORIZED,
+            ErroApp::Proibido(_) => StatusCode::FORBIDDEN,
+            ErroApp::NaoEncontrado(_) => StatusCode::NOT_FOUND,
+            ErroApp::Invalido { .. } => StatusCode::BAD_REQUEST,
+            ErroApp::Conflito { .. } => StatusCode::CONFLICT,
+            ErroApp::Interno(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn codigo(&self) -> &'static str {
+        match self {
+            ErroApp::NaoAutenticado(_) => "nao_autenticado",
+            ErroApp::Proibido(_) => "proibido",
+            ErroApp::NaoEncontrado(_) => "nao_encontrado",
+            ErroApp::Invalido { codigo, .. } | ErroApp::Conflito { codigo, .. } => codigo,
+            ErroApp::Interno(_) => "erro_interno",
+        }
+    }
+
+    pub fn mensagem(&self) -> &str {
+        match self {
+            ErroApp::NaoAutenticado(mensagem) | ErroApp::Proibido(mensagem) | ErroApp::NaoEncontrado(mensagem) => mensagem,
+            ErroApp::Invalido { mensagem, .. } | ErroApp::Conflito { mensagem, .. } => mensagem,
+            ErroApp::Interno(_) => "Erro interno do servidor",
+        }
+    }
+}
+
+impl IntoResponse for ErroApp {
+    fn into_response(self) -> Response {
+        match &self {
+            ErroApp::Interno(detalhe) => tracing::error!(erro = %detalhe, "❌ Erro interno"),
+            ErroApp::Invalido { detalhe: Some(detalhe), .. } | ErroApp::Conflito { detalhe: Some(detalhe), .. } => {
+                tracing::warn!(codigo = self.codigo(), erro = %detalhe, "{}", self.mensagem())
+            }
+            _ => tracing::warn!(codigo = self.codigo(), "{}", self.mensagem()),
+        }
+
+        let corpo = CorpoErro { codigo: self.codigo(), mensagem: self.mensagem() };
+        (self.status(), axum::Json(corpo)).into_response()
+    }
+}
+
+// ===========================================
+// EXTRATORES
+// ===========================================
+
+// Os mesmos extratores do axum, mas uma entrada que não pode ser lida
+// também vira `ErroApp`, em vez de texto puro com status 422.
+
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(ErroApp))]
+pub struct Json<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(ErroApp))]
+pub struct Query<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(ErroApp))]
+pub struct Path<T>(pub T);
+
+/// Corpo de escrita: JSON se o `Content-Type` for `application/json`,
+/// senão formulário (`application/x-www-form-urlencoded`).
+pub struct Entrada<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for Entrada<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ErroApp;
+
+    async fn from_request(requisicao: Request, estado: &S) -> Result<Self, Self::Rejection> {
+        let json = requisicao.headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|tipo| tipo.to_str().ok())
+            .and_then(|tipo| tipo.split(';').next())
+            .map(|tipo| tipo.trim().to_ascii_lowercase())
+            .is_some_and(|tipo| tipo == "application/json" || tipo.ends_with("+json"));
+
+        if json {
+            let Json(valor) = Json::<T>::from_request(requisicao, estado).await?;
+            Ok(Entrada(valor))
+        } else {
+            let axum::Form(valor) = axum::Form::<T>::from_request(requisicao, estado).await?;
+            Ok(Entrada(valor))
+        }
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+impl From<FormRejection> for ErroApp {
+    fn from(erro: FormRejection) -> Self {
+        ErroApp::invalido("formulario_invalido", format!("Formulário inválido: {}", erro.body_text()))
+    }
+}
+
+impl From<JsonRejection> for ErroApp {
+    fn from(erro: JsonRejection) -> Self {
+        ErroApp::invalido("json_invalido", format!("JSON inválido: {}", erro.body_text()))
+    }
+}
+
+impl From<QueryRejection> for ErroApp {
+    fn from(erro: QueryRejection) -> Self {
+        ErroApp::invalido("parametro_invalido", format!("Parâmetro inválido: {}", erro.body_text()))
+    }
+}
+
+impl From<PathRejection> for ErroApp {
+    fn from(erro: PathRejection) -> Self {
+        ErroApp::invalido("caminho_invalido", format!("Caminho inválido: {}", erro.body_text()))
+    }
+}
+
+// ===========================================
+// ERROS DO SQLITE
+// ===========================================
+
+/// Mensagem de nome repetido para cada tabela com UNIQUE.
+fn mensagem_duplicado(detalhe: &str) -> &'static str {
+    let tabela = detalhe.rsplit(": ").next().and_then(|coluna| coluna.split('.').next()).unwrap_or("");
+    match tabela {
+        "secoes" => "Já existe uma seção com esse nome",
+        "tipos" => "Já existe um tipo com esse nome nesta seção",
+        "produtos" => "Já existe um produto com esse nome neste tipo",
+        "usuarios" => "Já existe um usuário com esse nome",
+        _ => "Registro duplicado",
+    }
+}
+
+/// Violações de restrição viram erros do cliente; o resto é erro interno.
+impl From<rusqlite::Error> for ErroApp {
+    fn from(erro: rusqlite::Error) -> Self {
+        let rusqlite::Error::SqliteFailure(falha, detalhe) = &erro else {
+            return match erro {
+                rusqlite::Error::QueryReturnedNoRows => ErroApp::NaoEncontrado("Registro não encontrado"),
+                outro => ErroApp::Interno(outro.to_string()),
+            };
+        };
+        if falha.code != ErrorCode::ConstraintViolation {
+            return ErroApp::Interno(erro.to_string());
+        }
+
+        let detalhe = detalhe.as_deref().unwrap_or("");
+        let convertido = match falha.extended_code {
+            rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE | rusqlite::ffi::SQLITE_CONSTRAINT_PRIMARYKEY => {
+                ErroApp::conflito("duplicado", mensagem_duplicado(detalhe))
+            }
+            rusqlite::ffi::SQLITE_CONSTRAINT_FOREIGNKEY => {
+                ErroApp::invalido("referencia_invalida", "O registro relacionado não existe")
+            }
+            rusqlite::ffi::SQLITE_CONSTRAINT_NOTNULL => {
+                let campo = detalhe.rsplit('.').next().unwrap_or(detalhe);
+                ErroApp::invalido("campo_obrigatorio", format!("O campo {} é obrigatório", campo))
+            }
+            rusqlite::ffi::SQLITE_CONSTRAINT_CHECK => {
+                let regra = detalhe.trim_start_matches("CHECK constraint failed: ");
+                ErroApp::invalido("valor_invalido", format!("Valor fora do permitido ({})", regra))
+            }
+            _ => return ErroApp::Interno(erro.to_string()),
+        };
+        convertido.com_detalhe(&erro)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    fn banco() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "PRAGMA foreign_keys = ON;
+             CREATE TABLE secoes (id INTEGER PRIMARY KEY, nome TEXT NOT NULL UNIQUE);
+             CREATE TABLE tipos (id INTEGER PRIMARY KEY, nome TEXT NOT NULL,
+                 id_secao INTEGER NOT NULL REFERENCES secoes(id),
+                 quantidade INTEGER CHECK (quantidade > 0));
+             INSERT INTO secoes (nome) VALUES ('Frios');",
+        ).unwrap();
+        conn
+    }
+
+    fn erro_de(sql: &str) -> ErroApp {
+        banco().execute(sql, []).unwrap_err().into()
+    }
+
+    #[test]
+    fn restricoes_viram_erros_do_cliente() {
+        let duplicado = erro_de("INSERT INTO secoes (nome) VALUES ('Frios')");
+        assert_eq!(duplicado.status(), StatusCode::CONFLICT);
+        assert_eq!(duplicado.codigo(), "duplicado");
+        assert_eq!(duplicado.mensagem(), "Já existe uma seção com esse nome");
+        // O erro do SQLite fica guardado para o log
+        match &duplicado {
+            ErroApp::Conflito { detalhe: Some(detalhe), .. } => assert!(detalhe.contains("secoes.nome"), "{}", detalhe),
+            outro => panic!("esperava o detalhe do SQLite, veio {:?}", outro),
+        }
+
+        let sem_secao = erro_de("INSERT INTO tipos (nome, id_secao) VALUES ('Queijos', 99)");
+        assert_eq!(sem_secao.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(sem_secao.codigo(), "referencia_invalida");
+
+        let sem_nome = erro_de("INSERT INTO secoes (nome) VALUES (NULL)");
+        assert_eq!(sem_nome.codigo(), "campo_obrigatorio");
+        assert_eq!(sem_nome.mensagem(), "O campo nome é obrigatório");
+
+        let negativo = erro_de("INSERT INTO tipos (nome, id_secao, quantidade) VALUES ('Queijos', 1, 0)");
+        assert_eq!(negativo.codigo(), "valor_invalido");
+    }
+
+    #[test]
+    fn outros_erros_sao_internos() {
+        let erro = erro_de("SELECT * FROM tabela_que_nao_existe");
+        assert_eq!(erro.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(erro.mensagem(), "Erro interno do servidor");
+    }
+}
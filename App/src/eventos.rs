@@ -0,0 +1,69 @@
+use std::sync::LazyLock;
+
+use axum::response::sse::{Event, KeepAlive, Sse};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+use tokio_stream::{Stream, StreamExt};
+use utoipa::ToSchema;
+
+/// Quantos eventos ficam guardados para um cliente lento antes de ele
+/// começar a perder eventos (e receber `eventos_perdidos`).
+const CAPACIDADE: usize = 256;
+
+static CANAL: LazyLock<broadcast::Sender<Evento>> = LazyLock::new(|| broadcast::channel(CAPACIDADE).0);
+
+// ===========================================
+// ESTRUTURAS DE DADOS
+// ===========================================
+
+/// Mudança no estoque, com os ids necessários para o cliente recarregar só
+/// a parte da tela afetada. Apagar uma seção, tipo ou produto apaga também
+/// o que está dentro dele, sem eventos separados para cada item.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "evento", rename_all = "snake_case")]
+pub enum Evento {
+    SecaoCriada { id: i32 },
+    SecaoApagada { id: i32 },
+    TipoCriado { id: i32, id_secao: i32 },
+    TipoApagado { id: i32, id_secao: i32 },
+    ProdutoCriado { id: i32, id_tipo: i32 },
+    ProdutoApagado { id: i32, id_tipo: i32 },
+    LoteCriado { id: i32, id_produto: i32 },
+    /// Quantidades mudaram: venda, abastecimento ou baixa
+    LoteAlterado { id: i32, id_produto: i32 },
+    LoteApagado { id: i32, id_produto: i32 },
+    /// Importação ou restauração de backup: todo o estoque foi trocado
+    EstoqueSubstituido,
+    /// O cliente ficou para trás e perdeu eventos; deve recarregar tudo
+    EventosPerdidos { quantidade: u64 },
+}
+
+// ===========================================
+// CANAL
+// ===========================================
+
+/// Avisa os clientes conectados em `/eventos`. Chamado depois que a mudança
+/// foi gravada; sem ninguém ouvindo, o evento é descartado.
+pub fn publicar(evento: Evento) {
+    let _ = CANAL.send(evento);
+}
+
+// ===========================================
+// HANDLERS
+// ===========================================
+
+/// Server-Sent Events: cada mensagem é um `Evento` em JSON no campo `data`.
+/// No navegador, `new EventSource("/api/v1/eventos")` reconecta sozinho.
+#[utoipa::path(get, path = "/eventos", tag = "eventos",
+    responses((status = 200, description = "Fluxo text/event-stream; cada `data` é um Evento", content_type = "text/event-stream", body = Evento)))]
+pub async fn eventos_handler() -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    let eventos = BroadcastStream::new(CANAL.subscribe()).map(|recebido| {
+        let evento = recebido.unwrap_or_else(|BroadcastStreamRecvError::Lagged(quantidade)| {
+            Evento::EventosPerdidos { quantidade }
+        });
+        Event::default().json_data(evento)
+    });
+
+    Sse::new(eventos).keep_alive(KeepAlive::default())
+}
@@ -0,0 +1,248 @@
+use axum::response::Json;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::config;
+use crate::erro::{ErroApp, Query};
+use crate::init_db;
+
+// ===========================================
+// ESTRUTURAS DE DADOS
+// ===========================================
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Faixa {
+    pub nome: String,
+    /// Dias até vencer, inclusivo; ausente na faixa de vencidos
+    pub de: Option<i32>,
+    /// Dias até vencer, inclusivo; ausente na última faixa
+    pub ate: Option<i32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct TotalFaixa {
+    pub quantidade: i32,
+    /// Unidades de produtos sem preço cadastrado, que não entram nos valores
+    pub quantidade_sem_preco: i32,
+    pub valor_custo_centavos: i64,
+    pub valor_venda_centavos: i64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FaixasTipo {
+    pub id: i32,
+    pub nome: String,
+    pub faixas: Vec<TotalFaixa>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FaixasSecao {
+    pub id: i32,
+    pub nome: String,
+    pub faixas: Vec<TotalFaixa>,
+    pub tipos: Vec<FaixasTipo>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RelatorioFaixas {
+    pub faixas: Vec<Faixa>,
+    pub total: Vec<TotalFaixa>,
+    pub secoes: Vec<FaixasSecao>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ParametrosFaixas {
+    /// Limites superiores separados por vírgula, por exemplo "7,15,30,60"
+    pub limites: Option<String>,
+}
+
+// ===========================================
+// CÁLCULO
+// ===========================================
+
+/// Lê limites crescentes e não negativos, como "7,15,30,60".
+pub fn interpretar_limites(texto: &str) -> Option<Vec<i32>> {
+    let limites: Vec<i32> = texto.split(',')
+        .map(|parte| parte.trim().parse().ok())
+        .collect::<Option<_>>()?;
+
+    limites_validos(&limites).then_some(limites)
+}
+
+/// Crescentes, não negativos e abaixo de `i32::MAX`, porque a última faixa
+/// começa no dia seguinte ao último limite.
+pub fn limites_validos(limites: &[i32]) -> bool {
+    let crescentes = limites.windows(2).all(|par| par[0] < par[1]);
+    !limites.is_empty() && limites[0] >= 0 && crescentes && limites[limites.len() - 1] < i32::MAX
+}
+
+pub fn montar_faixas(limites: &[i32]) -> Vec<Faixa> {
+    let mut faixas = vec![Faixa { nome: "vencido".to_string(), de: None, ate: Some(-1) }];
+    let mut de = 0;
+    for &ate in limites {
+        faixas.push(Faixa { nome: format!("{}-{}", de, ate), de: Some(de), ate: Some(ate) });
+        de = ate + 1;
+    }
+    faixas.push(Faixa { nome: format!(">{}", de - 1), de: Some(de), ate: None });
+    faixas
+}
+
+/// Índice da faixa para um lote que vence em `dias` dias (negativo se já venceu).
+fn indice_faixa(limites: &[i32], dias: i32) -> usize {
+    if dias < 0 {
+        return 0;
+    }
+    1 + limites.iter().take_while(|&&ate| dias > ate).count()
+}
+
+fn acumular(faixas: &mut [TotalFaixa], indice: usize, quantidade: i32, custo: Option<i64>, venda: Option<i64>) {
+    let faixa = &mut faixas[indice];
+    faixa.quantidade += quantidade;
+    match (custo, venda) {
+        (Some(custo), Some(venda)) => {
+            faixa.valor_custo_centavos += custo * quantidade as i64;
+            faixa.valor_venda_centavos += venda * quantidade as i64;
+        }
+        _ => faixa.quantidade_sem_preco += quantidade,
+    }
+}
+
+pub fn relatorio_faixas(conn: &Connection, limites: &[i32]) -> Result<RelatorioFaixas, rusqlite::Error> {
+    let faixas = montar_faixas(limites);
+    let vazias = vec![TotalFaixa::default(); faixas.len()];
+
+    // Posição de cada seção e de cada tipo (seção, tipo) nas listas do resultado
+    let mut secoes: Vec<FaixasSecao> = Vec::new();
+    let mut indice_secao: HashMap<i32, usize> = HashMap::new();
+    let mut indice_tipo: HashMap<i32, (usize, usize)> = HashMap::new();
+    {
+        let mut stmt = conn.prepare(
+            "SELECT s.id, s.nome, t.id, t.nome FROM secoes s
+             LEFT JOIN tipos t ON t.id_secao = s.id
+             ORDER BY s.nome, s.id, t.nome"
+        )?;
+        let mut linhas = stmt.query([])?;
+        while let Some(row) = linhas.next()? {
+            let id_secao: i32 = row.get(0)?;
+            let i = match indice_secao.get(&id_secao) {
+                Some(&i) => i,
+                None => {
+                    secoes.push(FaixasSecao { id: id_secao, nome: row.get(1)?, faixas: vazias.clone(), tipos: Vec::new() });
+                    indice_secao.insert(id_secao, secoes.len() - 1);
+                    secoes.len() - 1
+                }
+            };
+
+            if let Some(id_tipo) = row.get::<_, Option<i32>>(2)? {
+                secoes[i].tipos.push(FaixasTipo { id: id_tipo, nome: row.get(3)?, faixas: vazias.clone() });
+                indice_tipo.insert(id_tipo, (i, secoes[i].tipos.len() - 1));
+            }
+        }
+    }
+
+    let mut total = vazias.clone();
+    {
+        let mut stmt = conn.prepare(
+            "SELECT p.id_tipo,
+                    CAST(julianday(l.validade) - julianday(date('now', 'localtime')) AS INTEGER),
+                    l.quantidade_total, pa.custo_centavos, COALESCE(ra.preco_centavos, pa.preco_venda_centavos)
+             FROM lotes l
+             JOIN produtos p ON p.id = l.id_produto
+             LEFT JOIN precos_atuais pa ON pa.id_produto = l.id_produto
+             LEFT JOIN rebaixas_atuais ra ON ra.id_lote = l.id
+             WHERE l.quantidade_total > 0
+               AND julianday(l.validade) IS NOT NULL"
+        )?;
+        let mut linhas = stmt.query([])?;
+        while let Some(row) = linhas.next()? {
+            let id_tipo: i32 = row.get(0)?;
+            let quantidade: i32 = row.get(2)?;
+            let custo: Option<i64> = row.get(3)?;
+            let venda: Option<i64> = row.get(4)?;
+            let indice = indice_faixa(limites, row.get(1)?);
+
+            acumular(&mut total, indice, quantidade, custo, venda);
+            if let Some(&(i, j)) = indice_tipo.get(&id_tipo) {
+                acumular(&mut secoes[i].faixas, indice, quantidade, custo, venda);
+                acumular(&mut secoes[i].tipos[j].faixas, indice, quantidade, custo, venda);
+            }
+        }
+    }
+
+    Ok(RelatorioFaixas { faixas, total, secoes })
+}
+
+// ===========================================
+// HANDLER DAS FAIXAS DE VALIDADE
+// ===========================================
+
+#[utoipa::path(get, path = "/relatorio/faixas", tag = "relatorios", params(ParametrosFaixas),
+    responses((status = 200, body = RelatorioFaixas)))]
+pub async fn faixas_validade_handler(Query(parametros): Query<ParametrosFaixas>) -> Result<Json<RelatorioFaixas>, ErroApp> {
+    let limites = match parametros.limites {
+        Some(texto) => interpretar_limites(&texto).ok_or_else(|| ErroApp::invalido(
+            "limites_invalidos",
+            format!("Os limites devem ser números crescentes, de 0 a {}, separados por vírgula", i32::MAX - 1),
+        ))?,
+        None => config::atual().alertas.limites_faixas.clone(),
+    };
+
+    let conn = init_db()?;
+    let relatorio = relatorio_faixas(&conn, &limites)?;
+
+    Ok(Json(relatorio))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nomes(limites: &[i32]) -> Vec<String> {
+        montar_faixas(limites).into_iter().map(|faixa| faixa.nome).collect()
+    }
+
+    #[test]
+    fn faixas_cobrem_todos_os_dias() {
+        assert_eq!(nomes(&[7, 15]), ["vencido", "0-7", "8-15", ">15"]);
+        assert_eq!(nomes(&[0]), ["vencido", "0-0", ">0"]);
+
+        let limites = [7, 15];
+        assert_eq!(indice_faixa(&limites, -1), 0);
+        assert_eq!(indice_faixa(&limites, 0), 1);
+        assert_eq!(indice_faixa(&limites, 7), 1);
+        assert_eq!(indice_faixa(&limites, 8), 2);
+        assert_eq!(indice_faixa(&limites, 16), 3);
+    }
+
+    #[test]
+    fn limites_invalidos_sao_recusados() {
+        assert_eq!(interpretar_limites("7, 15,30"), Some(vec![7, 15, 30]));
+        assert_eq!(interpretar_limites(&format!("7,{}", i32::MAX - 1)), Some(vec![7, i32::MAX - 1]));
+        assert_eq!(nomes(&[i32::MAX - 1]).last().unwrap(), &format!(">{}", i32::MAX - 1));
+
+        for texto in ["", "7,,15", "15,7", "7,7", "-1,7", "a", &format!("7,{}", i32::MAX), "7,2147483648"] {
+            assert_eq!(interpretar_limites(texto), None, "{:?}", texto);
+        }
+    }
+
+    #[test]
+    fn lote_com_validade_malformada_fica_de_fora() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::criar_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO secoes (id, nome) VALUES (1, 'Laticínios');
+             INSERT INTO tipos (id, nome, id_secao) VALUES (1, 'Iogurte', 1);
+             INSERT INTO produtos (id, nome, id_tipo) VALUES (1, 'Natural', 1);
+             INSERT INTO lotes (id_produto, validade, quantidade_total) VALUES
+                 (1, 'sem data', 5),
+                 (1, date('now', 'localtime', '+3 days'), 4);"
+        ).unwrap();
+
+        let relatorio = relatorio_faixas(&conn, &[7, 15]).unwrap();
+        let quantidades: Vec<i32> = relatorio.total.iter().map(|faixa| faixa.quantidade).collect();
+        assert_eq!(quantidades, [0, 4, 0, 0]);
+        assert_eq!(relatorio.secoes[0].tipos[0].faixas[1].quantidade, 4);
+    }
+}
@@ -0,0 +1,199 @@
+use axum::response::Json;
+use chrono::{Duration, Local, NaiveDate};
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::config;
+use crate::erro::{ErroApp, Query};
+use crate::init_db;
+
+const DIAS_HISTORICO_PADRAO: i64 = 90;
+
+// ===========================================
+// ESTRUTURAS DE DADOS
+// ===========================================
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PontoHistorico {
+    pub data: String,
+    pub total: i32,
+    pub prateleira: i32,
+    pub estoque: i32,
+    /// Unidades que venciam dentro de `alertas.dias_risco` na data do snapshot
+    pub vencendo: i32,
+    pub vencido: i32,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ParametrosHistorico {
+    pub secao: Option<i32>,
+    pub tipo: Option<i32>,
+    pub produto: Option<i32>,
+    /// Quantos dias para trás (padrão 90)
+    pub dias: Option<i64>,
+}
+
+// ===========================================
+// SNAPSHOTS
+// ===========================================
+
+/// Grava os totais de cada produto na data informada. Chamar de novo no mesmo
+/// dia não altera nada: vale o primeiro snapshot do dia.
+pub fn tirar_snapshot(conn: &Connection, data: NaiveDate) -> Result<usize, rusqlite::Error> {
+    conn.execute(
+        "INSERT OR IGNORE INTO historico_estoque
+             (data, id_produto, id_tipo, id_secao, total, prateleira, vencendo, vencido)
+         SELECT ?1, p.id, p.id_tipo, t.id_secao,
+                COALESCE(SUM(l.quantidade_total), 0),
+                COALESCE(SUM(l.quantidade_prateleira), 0),
+                COALESCE(SUM(CASE WHEN julianday(l.validade) - julianday(?1) BETWEEN 0 AND ?2
+                                  THEN l.quantidade_total END), 0),
+                COALESCE(SUM(CASE WHEN julianday(l.validade) < julianday(?1)
+                                  THEN l.quantidade_total END), 0)
+         FROM produtos p
+         JOIN tipos t ON t.id = p.id_tipo
+         LEFT JOIN lotes l ON l.id_produto = p.id
+         GROUP BY p.id",
+        params![data.format("%Y-%m-%d").to_string(), config::atual().alertas.dias_risco],
+    )
+}
+
+/// Tira o snapshot de hoje agora (se ainda não existir) e depois logo após cada meia-noite.
+pub fn iniciar_agendador() {
+    tokio::spawn(async {
+        loop {
+            let hoje = Local::now().date_naive();
+            match init_db().and_then(|conn| tirar_snapshot(&conn, hoje)) {
+                Ok(0) => {}
+                Ok(produtos) => tracing::info!("📸 Snapshot de {} gravado ({} produtos)", hoje, produtos),
+                Err(e) => tracing::error!("❌ Erro ao gravar snapshot de {}: {}", hoje, e),
+            }
+
+            let proxima = (hoje + Duration::days(1))
+                .and_hms_opt(0, 1, 0)
+                .and_then(|d| d.and_local_timezone(Local).earliest());
+            let espera = proxima
+                .and_then(|p| (p - Local::now()).to_std().ok())
+                .unwrap_or(std::time::Duration::from_secs(60 * 60));
+            tokio::time::sleep(espera).await;
+        }
+    });
+}
+
+// ===========================================
+// HANDLERS DE HISTÓRICO
+// ===========================================
+
+#[utoipa::path(post, path = "/historico/snapshot", tag = "historico",
+    responses((status = 200, body = String)))]
+pub async fn snapshot_handler() -> Result<String, ErroApp> {
+    let conn = init_db()?;
+
+    let gravados = tirar_snapshot(&conn, Local::now().date_naive())
+        ?;
+
+    if gravados == 0 {
+        Ok("Snapshot de hoje já existia".to_string())
+    } else {
+        Ok(format!("Snapshot gravado: {} produtos", gravados))
+    }
+}
+
+/// Série diária da loja inteira ou de uma seção, tipo ou produto.
+#[utoipa::path(get, path = "/historico", tag = "historico", params(ParametrosHistorico),
+    responses((status = 200, body = Vec<PontoHistorico>)))]
+pub async fn historico_handler(Query(parametros): Query<ParametrosHistorico>) -> Result<Json<Vec<PontoHistorico>>, ErroApp> {
+    let dias = parametros.dias.unwrap_or(DIAS_HISTORICO_PADRAO);
+    if dias <= 0 {
+        return Err(ErroApp::invalido("dias_invalido", "O número de dias deve ser maior que zero"));
+    }
+    let inicio = Duration::try_days(dias)
+        .and_then(|duracao| Local::now().date_naive().checked_sub_signed(duracao))
+        .ok_or_else(|| ErroApp::invalido(
+            "dias_invalido",
+            format!("O número de dias vai além do intervalo de datas suportado: {}", dias),
+        ))?
+        .format("%Y-%m-%d")
+        .to_string();
+
+    let conn = init_db()?;
+    Ok(Json(serie(&conn, &inicio, &parametros)?))
+}
+
+/// Soma os snapshots gravados a partir de `inicio`, um ponto por dia.
+fn serie(conn: &Connection, inicio: &str, parametros: &ParametrosHistorico) -> Result<Vec<PontoHistorico>, rusqlite::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT data, SUM(total), SUM(prateleira), SUM(vencendo), SUM(vencido)
+         FROM historico_estoque
+         WHERE data >= ?1
+           AND (?2 IS NULL OR id_secao = ?2)
+           AND (?3 IS NULL OR id_tipo = ?3)
+           AND (?4 IS NULL OR id_produto = ?4)
+         GROUP BY data
+         ORDER BY data"
+    )?;
+
+    let pontos = stmt.query_map(
+        params![inicio, parametros.secao, parametros.tipo, parametros.produto],
+        |row| {
+            Ok(PontoHistorico {
+                data: row.get(0)?,
+                total: row.get(1)?,
+                prateleira: row.get(2)?,
+                estoque: row.get::<_, i32>(1)? - row.get::<_, i32>(2)?,
+                vencendo: row.get(3)?,
+                vencido: row.get(4)?,
+            })
+        }
+    )?;
+
+    let mut resultado = Vec::new();
+    for ponto in pontos {
+        resultado.push(ponto?);
+    }
+
+    Ok(resultado)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_repetido_no_dia_mantem_o_primeiro() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::criar_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO secoes (id, nome) VALUES (1, 'Mercearia'), (2, 'Padaria');
+             INSERT INTO tipos (id, nome, id_secao) VALUES (1, 'Grãos', 1), (2, 'Pães', 2);
+             INSERT INTO produtos (id, nome, id_tipo) VALUES (1, 'ARROZ', 1), (2, 'FEIJÃO', 1), (3, 'FRANCÊS', 2);
+             INSERT INTO lotes (id_produto, validade, quantidade_total, quantidade_prateleira) VALUES
+                 (1, '2026-03-03', 10, 4),
+                 (1, '2026-02-20', 3, 0),
+                 (3, '2026-03-30', 6, 6);"
+        ).unwrap();
+        let dia = |texto| NaiveDate::parse_from_str(texto, "%Y-%m-%d").unwrap();
+
+        assert_eq!(tirar_snapshot(&conn, dia("2026-03-01")).unwrap(), 3);
+        conn.execute("UPDATE lotes SET quantidade_total = 99", []).unwrap();
+        assert_eq!(tirar_snapshot(&conn, dia("2026-03-01")).unwrap(), 0);
+        assert_eq!(tirar_snapshot(&conn, dia("2026-03-02")).unwrap(), 3);
+
+        let linhas: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM historico_estoque WHERE data = '2026-03-01'", [], |row| row.get(0)
+        ).unwrap();
+        assert_eq!(linhas, 3);
+
+        let mercearia = ParametrosHistorico { secao: Some(1), tipo: None, produto: None, dias: None };
+        let pontos: Vec<(String, i32, i32, i32, i32, i32)> = serie(&conn, "2026-03-01", &mercearia).unwrap()
+            .into_iter()
+            .map(|p| (p.data, p.total, p.prateleira, p.estoque, p.vencendo, p.vencido))
+            .collect();
+        assert_eq!(pontos, [
+            // Vale o primeiro snapshot do dia: 10 vencendo em 2 dias e 3 já vencidas
+            ("2026-03-01".to_string(), 13, 4, 9, 10, 3),
+            ("2026-03-02".to_string(), 198, 4, 194, 99, 99),
+        ]);
+    }
+}
@@ -0,0 +1,270 @@
+use axum::{
+    body::{to_bytes, Body, HttpBody},
+    extract::Request,
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use blake2::{Blake2s256, Digest};
+use rusqlite::{params, Connection};
+
+use crate::config;
+use crate::erro::ErroApp;
+use crate::init_db;
+
+/// Cabeçalho com a chave escolhida pelo cliente, uma por ação do usuário.
+pub const CABECALHO: &str = "idempotency-key";
+/// Marca as respostas repetidas de uma requisição já processada.
+const CABECALHO_REPETIDA: &str = "idempotent-replayed";
+const TAMANHO_MAXIMO_CHAVE: usize = 255;
+/// Respostas maiores que isso não são guardadas: a chave é liberada e repetir
+/// a requisição a aplica de novo.
+const LIMITE_RESPOSTA_GUARDADA: usize = 1024 * 1024;
+
+// ===========================================
+// ESTRUTURAS DE DADOS
+// ===========================================
+
+#[derive(Debug, PartialEq)]
+struct RespostaGuardada {
+    status: u16,
+    content_type: Option<String>,
+    corpo: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Reserva {
+    /// Primeira vez: a requisição segue para o handler
+    Nova,
+    /// Já processada: devolve a resposta daquela vez
+    Concluida(RespostaGuardada),
+    /// A primeira requisição com a chave ainda não terminou
+    EmAndamento,
+    /// A chave já foi usada com outro método, caminho ou corpo
+    Divergente,
+}
+
+/// Libera a chave se a requisição for abandonada antes de a resposta ser
+/// guardada (cliente desconectou, por exemplo), para o cliente poder tentar de novo.
+struct ChaveReservada {
+    chave: String,
+    guardada: bool,
+}
+
+impl Drop for ChaveReservada {
+    fn drop(&mut self) {
+        if self.guardada {
+            return;
+        }
+        // O drop roda dentro do runtime; o SQLite bloqueia, então vai para outra thread
+        let chave = std::mem::take(&mut self.chave);
+        tokio::task::spawn_blocking(move || {
+            if let Err(erro) = init_db().and_then(|conn| liberar(&conn, &chave)) {
+                tracing::error!(%erro, "❌ Não foi possível liberar a Idempotency-Key");
+            }
+        });
+    }
+}
+
+// ===========================================
+// BANCO DE DADOS
+// ===========================================
+
+/// `status` fica nulo enquanto a primeira requisição está em andamento.
+pub fn criar_tabela(conn: &Connection) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS chaves_idempotencia (
+            chave TEXT PRIMARY KEY,
+            metodo TEXT NOT NULL,
+            caminho TEXT NOT NULL,
+            impressao BLOB NOT NULL,
+            status INTEGER,
+            content_type TEXT,
+            corpo BLOB,
+            criada_em TEXT NOT NULL DEFAULT (datetime('now', 'localtime'))
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Apaga as chaves vencidas e tenta reservar `chave` para esta requisição.
+/// `impressao` identifica o corpo, para recusar a mesma chave com outros dados.
+fn reservar(
+    conn: &Connection,
+    chave: &str,
+    metodo: &str,
+    caminho: &str,
+    impressao: &[u8],
+    horas: u32,
+) -> Result<Reserva, rusqlite::Error> {
+    conn.execute(
+        "DELETE FROM chaves_idempotencia WHERE criada_em < datetime('now', 'localtime', ?1)",
+        [format!("-{} hours", horas)],
+    )?;
+
+    let inserida = conn.execute(
+        "INSERT OR IGNORE INTO chaves_idempotencia (chave, metodo, caminho, impressao) VALUES (?1, ?2, ?3, ?4)",
+        params![chave, metodo, caminho, impressao],
+    )?;
+    if inserida == 1 {
+        return Ok(Reserva::Nova);
+    }
+
+    conn.query_row(
+        "SELECT metodo = ?2 AND caminho = ?3 AND impressao = ?4, status, content_type, corpo
+         FROM chaves_idempotencia WHERE chave = ?1",
+        params![chave, metodo, caminho, impressao],
+        |row| {
+            if !row.get::<_, bool>(0)? {
+                return Ok(Reserva::Divergente);
+            }
+            Ok(match row.get::<_, Option<u16>>(1)? {
+                None => Reserva::EmAndamento,
+                Some(status) => Reserva::Concluida(RespostaGuardada {
+                    status,
+                    content_type: row.get(2)?,
+                    corpo: row.get::<_, Option<Vec<u8>>>(3)?.unwrap_or_default(),
+                }),
+            })
+        },
+    )
+}
+
+fn guardar(conn: &Connection, chave: &str, resposta: &RespostaGuardada) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "UPDATE chaves_idempotencia SET status = ?2, content_type = ?3, corpo = ?4 WHERE chave = ?1",
+        params![chave, resposta.status, resposta.content_type, resposta.corpo],
+    )?;
+
+    Ok(())
+}
+
+fn liberar(conn: &Connection, chave: &str) -> Result<(), rusqlite::Error> {
+    conn.execute("DELETE FROM chaves_idempotencia WHERE chave = ?1 AND status IS NULL", [chave])?;
+
+    Ok(())
+}
+
+// ===========================================
+// MIDDLEWARE
+// ===========================================
+
+/// Escritas (POST, PUT, PATCH, DELETE) com `Idempotency-Key` são aplicadas
+/// uma vez só: repetir a chave devolve a resposta guardada, com
+/// `Idempotent-Replayed: true`, por `idempotencia_horas`. Respostas 5xx não
+/// são guardadas, para a nova tentativa poder dar certo, nem as maiores que
+/// `LIMITE_RESPOSTA_GUARDADA`.
+pub async fn idempotencia(requisicao: Request, proximo: Next) -> Result<Response, ErroApp> {
+    let escrita = matches!(*requisicao.method(), Method::POST | Method::PUT | Method::PATCH | Method::DELETE);
+    let Some(chave) = requisicao.headers().get(CABECALHO).filter(|_| escrita) else {
+        return Ok(proximo.run(requisicao).await);
+    };
+    let chave = chave.to_str().ok()
+        .map(str::trim)
+        .filter(|chave| !chave.is_empty() && chave.len() <= TAMANHO_MAXIMO_CHAVE)
+        .ok_or_else(|| ErroApp::invalido(
+            "chave_idempotencia_invalida",
+            format!("A Idempotency-Key deve ter de 1 a {} caracteres ASCII", TAMANHO_MAXIMO_CHAVE),
+        ))?
+        .to_string();
+
+    let (partes, corpo) = requisicao.into_parts();
+    // O limite das rotas que recebem arquivos; nas demais, o extrator do handler
+    // ainda recusa o que passar dos 2 MB padrão do axum
+    let corpo = to_bytes(corpo, config::atual().limite_envio_bytes()).await
+        .map_err(|_| ErroApp::invalido("corpo_grande_demais", "O corpo da requisição é grande demais"))?;
+
+    // Sob `/api` e `/api/v1` o caminho chega igual, sem o prefixo
+    let reserva = reservar(
+        &init_db()?,
+        &chave,
+        partes.method.as_str(),
+        partes.uri.path(),
+        &Blake2s256::digest(&corpo),
+        config::atual().idempotencia_horas,
+    )?;
+    match reserva {
+        Reserva::Nova => {}
+        Reserva::Concluida(guardada) => return Ok(repetir(guardada)),
+        Reserva::EmAndamento => {
+            return Err(ErroApp::conflito(
+                "requisicao_em_andamento",
+                "Uma requisição com esta Idempotency-Key ainda está sendo processada",
+            ));
+        }
+        Reserva::Divergente => {
+            return Err(ErroApp::conflito(
+                "chave_idempotencia_reutilizada",
+                "Esta Idempotency-Key já foi usada em outra requisição",
+            ));
+        }
+    }
+
+    let mut reservada = ChaveReservada { chave, guardada: false };
+    let resposta = proximo.run(Request::from_parts(partes, Body::from(corpo))).await;
+    if resposta.status().is_server_error() {
+        return Ok(resposta);
+    }
+
+    // Tamanho desconhecido (streaming) ou grande demais: a resposta segue sem ser guardada
+    let tamanho = resposta.body().size_hint().upper();
+    if tamanho.is_none_or(|tamanho| tamanho > LIMITE_RESPOSTA_GUARDADA as u64) {
+        tracing::warn!(chave = %reservada.chave, "Resposta grande demais para guardar; a Idempotency-Key foi liberada");
+        return Ok(resposta);
+    }
+
+    let (partes, corpo) = resposta.into_parts();
+    let corpo = to_bytes(corpo, LIMITE_RESPOSTA_GUARDADA).await.map_err(ErroApp::interno)?;
+    let guardada = RespostaGuardada {
+        status: partes.status.as_u16(),
+        content_type: partes.headers.get(header::CONTENT_TYPE)
+            .and_then(|valor| valor.to_str().ok())
+            .map(str::to_string),
+        corpo: corpo.to_vec(),
+    };
+    guardar(&init_db()?, &reservada.chave, &guardada)?;
+    reservada.guardada = true;
+
+    Ok(Response::from_parts(partes, Body::from(corpo)))
+}
+
+fn repetir(guardada: RespostaGuardada) -> Response {
+    let mut resposta = Response::new(Body::from(guardada.corpo));
+    *resposta.status_mut() = StatusCode::from_u16(guardada.status).unwrap_or(StatusCode::OK);
+    if let Some(valor) = guardada.content_type.and_then(|tipo| HeaderValue::from_str(&tipo).ok()) {
+        resposta.headers_mut().insert(header::CONTENT_TYPE, valor);
+    }
+    resposta.headers_mut().insert(CABECALHO_REPETIDA, HeaderValue::from_static("true"));
+    resposta
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserva_repete_e_recusa_outros_dados() {
+        let conn = Connection::open_in_memory().unwrap();
+        criar_tabela(&conn).unwrap();
+        let reservar = |caminho: &str, corpo: &[u8]| reservar(&conn, "k1", "POST", caminho, corpo, 24).unwrap();
+
+        assert_eq!(reservar("/vender/1", b"{}"), Reserva::Nova);
+        assert_eq!(reservar("/vender/1", b"{}"), Reserva::EmAndamento);
+
+        let resposta = RespostaGuardada { status: 200, content_type: Some("application/json".into()), corpo: b"{\"id\":1}".to_vec() };
+        guardar(&conn, "k1", &resposta).unwrap();
+        assert_eq!(reservar("/vender/1", b"{}"), Reserva::Concluida(resposta));
+        assert_eq!(reservar("/vender/2", b"{}"), Reserva::Divergente);
+        assert_eq!(reservar("/vender/1", b"{\"quantidade\":2}"), Reserva::Divergente);
+
+        // Vencida, a chave pode ser usada de novo
+        conn.execute("UPDATE chaves_idempotencia SET criada_em = datetime('now', 'localtime', '-25 hours')", []).unwrap();
+        assert_eq!(reservar("/vender/2", b"{}"), Reserva::Nova);
+
+        // Liberada (requisição abandonada ou 5xx), também
+        liberar(&conn, "k1").unwrap();
+        assert_eq!(reservar("/vender/2", b"{}"), Reserva::Nova);
+    }
+}
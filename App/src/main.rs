@@ -1,83 +1,133 @@
 use axum::{
-    extract::{Path as AxumPath, Query},
-    http::StatusCode,
-    response::Json,
+    body::Bytes,
+    extract::DefaultBodyLimit,
+    http::{header, HeaderValue, StatusCode},
+    middleware,
+    response::{IntoResponse, Json, Response},
     routing::{get, post, delete},
     Router,
-    Form,
 };
-use rusqlite::{Connection, params};
+use rusqlite::{Connection, OptionalExtension, ToSql, params};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
-use std::path::Path;
-use tower_http::{services::ServeDir, cors::CorsLayer};
+use tower_http::{
+    services::ServeDir,
+    cors::{Any, CorsLayer},
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::{DefaultOnResponse, TraceLayer},
+};
+use axum_server::tls_rustls::RustlsConfig;
+use clap::Parser;
 use std::net::SocketAddr;
+use utoipa::ToSchema;
+use erro::{Entrada, ErroApp, Path as AxumPath, Query};
+use eventos::Evento;
+use paginacao::{FiltroLista, Ordem, Pagina, ParametrosLista};
+
+mod backup;
+mod baixas;
+mod config;
+mod copias;
+mod erro;
+mod eventos;
+mod faixas_validade;
+mod historico;
+mod idempotencia;
+mod openapi;
+mod paginacao;
+mod pesquisa;
+mod pdf;
+mod planilha;
+mod precos;
+mod previsao;
+mod rebaixas;
+mod registro;
+mod relatorio;
+mod sincronizacao;
+mod tls;
+mod reposicao;
+mod usuarios;
 
 // ===========================================
 // ESTRUTURAS DE DADOS
 // ===========================================
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, ToSchema)]
 struct Secao {
     id: i32,
     nome: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, ToSchema)]
 struct Tipo {
     id: i32,
     nome: String,
     id_secao: i32,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, ToSchema)]
 struct Produto {
     id: i32,
     nome: String,
     id_tipo: i32,
+    codigo_barras: Option<String>,
+    custo_centavos: Option<i64>,
+    preco_venda_centavos: Option<i64>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, ToSchema)]
 struct Lote {
     id: i32,
     id_produto: i32,
     validade: String,
     quantidade_total: i32,
     quantidade_prateleira: i32,
+    /// Código do lote do fornecedor
+    codigo: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct RelatorioItem {
-    id: i32,
+#[derive(Debug, Deserialize, ToSchema)]
+struct LoginData {
     nome: String,
-    tipo: String,
-    total: i32,
-    prateleira: i32,
-    estoque: i32,
-    filhos: Vec<RelatorioItem>,
+    password: String,
 }
 
-#[derive(Debug, Deserialize)]
-struct LoginData {
+// Entradas de escrita: só o que o cliente informa, sem o id gerado pelo banco.
+// Chegam em JSON ou formulário (ver `erro::Entrada`).
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct SecaoData {
     nome: String,
-    password: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
+struct TipoData {
+    nome: String,
+    id_secao: i32,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
 struct ProdutoData {
     nome: String,
+    /// Também aceito como `id_tipo`
+    #[serde(alias = "id_tipo")]
     tipo_id: i32,
+    codigo_barras: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 struct LoteData {
+    /// Também aceito como `id_produto`
+    #[serde(alias = "id_produto")]
     produto_id: i32,
     validade: String,
     quantidade_total: i32,
     quantidade_prateleira: i32,
+    codigo: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 struct VendaData {
     quantidade: i32,
 }
@@ -87,16 +137,34 @@ struct VendaData {
 // ===========================================
 
 fn init_db() -> Result<Connection, rusqlite::Error> {
-    let db_path = "./dados";
-    if !Path::new(db_path).exists() {
-        fs::create_dir_all(db_path).expect("Erro ao criar pasta dados");
+    let config = config::atual();
+    if !config.dados.exists() {
+        fs::create_dir_all(&config.dados).expect("Erro ao criar pasta dados");
     }
 
-    let conn = Connection::open("./dados/produtos.db")?;
-    
+    let conn = Connection::open(config.caminho_banco())?;
+    conn.pragma_update(None, "foreign_keys", true)?;
+    criar_schema(&conn)?;
+
+    Ok(conn)
+}
+
+/// Cria as tabelas, índices e views que ainda não existirem.
+fn criar_schema(conn: &Connection) -> Result<(), rusqlite::Error> {
     conn.execute(
-        "CREATE TABLE IF NOT EXISTS secoes (
+        "CREATE TABLE IF NOT EXISTS usuarios (
             id INTEGER PRIMARY KEY,
+            nome TEXT NOT NULL UNIQUE,
+            senha_hash TEXT NOT NULL,
+            admin INTEGER NOT NULL DEFAULT 0,
+            criado_em TEXT NOT NULL DEFAULT (datetime('now', 'localtime'))
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS secoes (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
             nome TEXT NOT NULL UNIQUE
         )",
         [],
@@ -104,7 +172,7 @@ fn init_db() -> Result<Connection, rusqlite::Error> {
 
     conn.execute(
         "CREATE TABLE IF NOT EXISTS tipos (
-            id INTEGER PRIMARY KEY,
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
             nome TEXT NOT NULL,
             id_secao INTEGER NOT NULL,
             FOREIGN KEY (id_secao) REFERENCES secoes(id) ON DELETE CASCADE,
@@ -115,7 +183,7 @@ fn init_db() -> Result<Connection, rusqlite::Error> {
 
     conn.execute(
         "CREATE TABLE IF NOT EXISTS produtos (
-            id INTEGER PRIMARY KEY,
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
             nome TEXT NOT NULL,
             id_tipo INTEGER NOT NULL,
             FOREIGN KEY (id_tipo) REFERENCES tipos(id) ON DELETE CASCADE,
@@ -126,7 +194,7 @@ fn init_db() -> Result<Connection, rusqlite::Error> {
 
     conn.execute(
         "CREATE TABLE IF NOT EXISTS lotes (
-            id INTEGER PRIMARY KEY,
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
             id_produto INTEGER NOT NULL,
             validade DATE NOT NULL,
             quantidade_total INTEGER NOT NULL,
@@ -136,85 +204,475 @@ fn init_db() -> Result<Connection, rusqlite::Error> {
         [],
     )?;
 
-    Ok(conn)
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS baixas (
+            id INTEGER PRIMARY KEY,
+            id_lote INTEGER NOT NULL,
+            id_produto INTEGER NOT NULL,
+            motivo TEXT NOT NULL CHECK (motivo IN ('vencido', 'avariado', 'furto', 'consumo_interno', 'doacao')),
+            quantidade INTEGER NOT NULL CHECK (quantidade > 0),
+            observacao TEXT,
+            data TEXT NOT NULL DEFAULT (datetime('now', 'localtime'))
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS precos (
+            id INTEGER PRIMARY KEY,
+            id_produto INTEGER NOT NULL,
+            custo_centavos INTEGER NOT NULL CHECK (custo_centavos >= 0),
+            preco_venda_centavos INTEGER NOT NULL CHECK (preco_venda_centavos >= 0),
+            vigente_desde TEXT NOT NULL DEFAULT (datetime('now', 'localtime')),
+            FOREIGN KEY (id_produto) REFERENCES produtos(id) ON DELETE CASCADE
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS vendas (
+            id INTEGER PRIMARY KEY,
+            id_lote INTEGER NOT NULL,
+            id_produto INTEGER NOT NULL,
+            quantidade INTEGER NOT NULL CHECK (quantidade > 0),
+            data TEXT NOT NULL DEFAULT (datetime('now', 'localtime'))
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_vendas_produto_data ON vendas (id_produto, data)",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS estoque_minimo (
+            id_produto INTEGER PRIMARY KEY,
+            minimo_prateleira INTEGER NOT NULL CHECK (minimo_prateleira >= 0),
+            minimo_total INTEGER NOT NULL CHECK (minimo_total >= 0),
+            vida_util_dias INTEGER CHECK (vida_util_dias > 0),
+            FOREIGN KEY (id_produto) REFERENCES produtos(id) ON DELETE CASCADE
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS regras_rebaixa (
+            id INTEGER PRIMARY KEY,
+            dias_restantes INTEGER NOT NULL CHECK (dias_restantes >= 0),
+            sobra_minima_percentual INTEGER NOT NULL DEFAULT 0
+                CHECK (sobra_minima_percentual BETWEEN 0 AND 100),
+            desconto_percentual INTEGER NOT NULL CHECK (desconto_percentual BETWEEN 1 AND 99)
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS limites_rebaixa (
+            id_secao INTEGER PRIMARY KEY,
+            desconto_maximo INTEGER NOT NULL CHECK (desconto_maximo BETWEEN 0 AND 99),
+            FOREIGN KEY (id_secao) REFERENCES secoes(id) ON DELETE CASCADE
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS rebaixas (
+            id INTEGER PRIMARY KEY,
+            id_lote INTEGER NOT NULL,
+            desconto_percentual INTEGER NOT NULL,
+            preco_original_centavos INTEGER NOT NULL,
+            preco_centavos INTEGER NOT NULL,
+            data TEXT NOT NULL DEFAULT (datetime('now', 'localtime')),
+            FOREIGN KEY (id_lote) REFERENCES lotes(id) ON DELETE CASCADE
+        )",
+        [],
+    )?;
+
+    // Preço em vigor hoje para cada produto
+    conn.execute(
+        "CREATE VIEW IF NOT EXISTS precos_atuais AS
+         SELECT pr.id_produto, pr.custo_centavos, pr.preco_venda_centavos
+         FROM precos pr
+         WHERE pr.id = (
+             SELECT p2.id FROM precos p2
+             WHERE p2.id_produto = pr.id_produto
+               AND p2.vigente_desde <= datetime('now', 'localtime')
+             ORDER BY p2.vigente_desde DESC, p2.id DESC
+             LIMIT 1
+         )",
+        [],
+    )?;
+
+    // Última rebaixa aceita de cada lote
+    conn.execute(
+        "CREATE VIEW IF NOT EXISTS rebaixas_atuais AS
+         SELECT r.id_lote, r.desconto_percentual, r.preco_original_centavos, r.preco_centavos, r.data
+         FROM rebaixas r
+         WHERE r.id = (SELECT MAX(r2.id) FROM rebaixas r2 WHERE r2.id_lote = r.id_lote)",
+        [],
+    )?;
+
+    // Baixas valoradas pelo custo vigente na data de cada baixa
+    conn.execute(
+        "CREATE VIEW IF NOT EXISTS baixas_valoradas AS
+         SELECT b.id, b.id_lote, b.id_produto, b.quantidade,
+                b.quantidade * COALESCE((
+                    SELECT pr.custo_centavos FROM precos pr
+                    WHERE pr.id_produto = b.id_produto AND pr.vigente_desde <= b.data
+                    ORDER BY pr.vigente_desde DESC, pr.id DESC
+                    LIMIT 1
+                ), 0) AS valor_centavos
+         FROM baixas b",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS historico_estoque (
+            data TEXT NOT NULL,
+            id_produto INTEGER NOT NULL,
+            id_tipo INTEGER NOT NULL,
+            id_secao INTEGER NOT NULL,
+            total INTEGER NOT NULL,
+            prateleira INTEGER NOT NULL,
+            vencendo INTEGER NOT NULL,
+            vencido INTEGER NOT NULL,
+            PRIMARY KEY (data, id_produto)
+        )",
+        [],
+    )?;
+
+    // Colunas que vieram depois da criação das tabelas
+    adicionar_coluna(conn, "produtos", "codigo_barras", "TEXT")?;
+    adicionar_coluna(conn, "lotes", "codigo", "TEXT")?;
+
+    // Baixas, vendas e o histórico guardam ids, que não podem ser reaproveitados
+    migrar_para_autoincrement(conn, "secoes", &[("historico_estoque", "id_secao")])?;
+    migrar_para_autoincrement(conn, "tipos", &[("historico_estoque", "id_tipo")])?;
+    migrar_para_autoincrement(conn, "produtos", &[
+        ("baixas", "id_produto"),
+        ("vendas", "id_produto"),
+        ("historico_estoque", "id_produto"),
+    ])?;
+    migrar_para_autoincrement(conn, "lotes", &[("baixas", "id_lote"), ("vendas", "id_lote")])?;
+
+    conn.execute("CREATE INDEX IF NOT EXISTS idx_lotes_produto ON lotes (id_produto)", [])?;
+    conn.execute("CREATE INDEX IF NOT EXISTS idx_precos_produto ON precos (id_produto, vigente_desde)", [])?;
+    conn.execute("CREATE INDEX IF NOT EXISTS idx_baixas_produto ON baixas (id_produto)", [])?;
+    conn.execute("CREATE INDEX IF NOT EXISTS idx_rebaixas_lote ON rebaixas (id_lote)", [])?;
+
+    pesquisa::criar_indice(conn)?;
+    sincronizacao::criar_tabelas(conn)?;
+    idempotencia::criar_tabela(conn)?;
+    usuarios::criar_tabelas(conn)?;
+
+    Ok(())
+}
+
+/// `ALTER TABLE ... ADD COLUMN` só se a coluna ainda não existir.
+fn adicionar_coluna(conn: &Connection, tabela: &str, coluna: &str, definicao: &str) -> Result<(), rusqlite::Error> {
+    let existe: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name = ?2",
+        params![tabela, coluna],
+        |row| row.get(0)
+    )?;
+    if !existe {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", tabela, coluna, definicao), [])?;
+    }
+    Ok(())
+}
+
+/// Sem `AUTOINCREMENT` o SQLite reaproveita o id do último registro apagado,
+/// e o histórico que guarda esse id passa a apontar para outro registro.
+/// Recria a tabela antiga com os mesmos dados e começa a sequência depois do
+/// maior id já usado nela ou guardado nas colunas de `referencias`.
+fn migrar_para_autoincrement(conn: &Connection, tabela: &str, referencias: &[(&str, &str)]) -> Result<(), rusqlite::Error> {
+    let chaves_estrangeiras: bool = conn.pragma_query_value(None, "foreign_keys", |row| row.get(0))?;
+    // Sem isso o DROP TABLE apagaria em cascata e o RENAME reescreveria as
+    // referências das outras tabelas para a tabela antiga
+    conn.pragma_update(None, "foreign_keys", false)?;
+    conn.pragma_update(None, "legacy_alter_table", true)?;
+
+    let resultado = (|| {
+        let tx = rusqlite::Transaction::new_unchecked(conn, rusqlite::TransactionBehavior::Immediate)?;
+        let sql: String = tx.query_row(
+            "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            [tabela],
+            |row| row.get(0)
+        )?;
+        if sql.contains("AUTOINCREMENT") {
+            return Ok(());
+        }
+
+        let nova = sql
+            .replacen(&format!("CREATE TABLE {}", tabela), &format!("CREATE TABLE {}_nova", tabela), 1)
+            .replacen("id INTEGER PRIMARY KEY", "id INTEGER PRIMARY KEY AUTOINCREMENT", 1);
+        let maiores_ids: Vec<String> = referencias.iter()
+            .map(|(outra, coluna)| format!("COALESCE((SELECT MAX({}) FROM {}), 0)", coluna, outra))
+            .chain([format!("COALESCE((SELECT MAX(id) FROM {}), 0)", tabela)])
+            .collect();
+
+        tx.execute_batch(&format!(
+            "{nova};
+             INSERT INTO {tabela}_nova SELECT * FROM {tabela};
+             DROP TABLE {tabela};
+             ALTER TABLE {tabela}_nova RENAME TO {tabela};
+             DELETE FROM sqlite_sequence WHERE name IN ('{tabela}', '{tabela}_nova');
+             INSERT INTO sqlite_sequence (name, seq) SELECT '{tabela}', MAX({maiores}, 0);",
+            maiores = maiores_ids.join(", "),
+        ))?;
+        tx.commit()?;
+
+        tracing::info!("🔧 Tabela {} migrada para AUTOINCREMENT", tabela);
+        Ok(())
+    })();
+
+    conn.pragma_update(None, "legacy_alter_table", false)?;
+    conn.pragma_update(None, "foreign_keys", chaves_estrangeiras)?;
+    resultado
+}
+
+/// Nome sem espaços nas pontas; vazio é erro.
+fn nome_obrigatorio(nome: &str) -> Result<String, ErroApp> {
+    let nome = nome.trim();
+    if nome.is_empty() {
+        return Err(ErroApp::invalido("campo_obrigatorio", "O campo nome é obrigatório"));
+    }
+    Ok(nome.to_string())
+}
+
+/// Texto opcional sem espaços nas pontas; vazio vira `None`.
+fn texto_opcional(texto: Option<String>) -> Option<String> {
+    texto.map(|texto| texto.trim().to_string()).filter(|texto| !texto.is_empty())
+}
+
+/// Validade no formato AAAA-MM-DD. Fora dele o SQLite não consegue fazer conta
+/// com a data e o lote some dos relatórios.
+fn validade_valida(validade: &str) -> bool {
+    chrono::NaiveDate::parse_from_str(validade, "%Y-%m-%d").is_ok()
+}
+
+const COLUNAS_LOTE: &str = "id, id_produto, validade, quantidade_total, quantidade_prateleira, codigo";
+
+fn lote_da_linha(row: &rusqlite::Row) -> rusqlite::Result<Lote> {
+    Ok(Lote {
+        id: row.get(0)?,
+        id_produto: row.get(1)?,
+        validade: row.get(2)?,
+        quantidade_total: row.get(3)?,
+        quantidade_prateleira: row.get(4)?,
+        codigo: row.get(5)?,
+    })
 }
 
 // ===========================================
-// HANDLER DE LOGIN
+// LISTAS PAGINADAS
 // ===========================================
 
-async fn login_handler(Form(login): Form<LoginData>) -> Json<bool> {
-    let conn = match Connection::open("./dados/logins.db") {
-        Ok(c) => c,
-        Err(_) => return Json(false)
-    };
+/// Produtos com preço atual e os totais dos lotes, usados para filtrar e ordenar.
+const CONSULTA_PRODUTOS: &str =
+    "FROM produtos p
+     JOIN tipos t ON t.id = p.id_tipo
+     LEFT JOIN precos_atuais pa ON pa.id_produto = p.id
+     LEFT JOIN (
+         SELECT id_produto,
+                SUM(quantidade_total) AS quantidade,
+                SUM(quantidade_prateleira) AS prateleira,
+                MIN(CASE WHEN quantidade_total > 0 THEN validade END) AS validade
+         FROM lotes GROUP BY id_produto
+     ) e ON e.id_produto = p.id";
+
+/// Filtros de `FiltroLista` sobre `CONSULTA_PRODUTOS`, de `?2` a `?5`.
+/// O filtro de validade pega produtos com algum lote com unidades dentro da janela.
+const FILTROS_PRODUTOS: &str =
+    "(?2 IS NULL OR t.id_secao = ?2)
+     AND (?3 IS NULL OR (COALESCE(e.prateleira, 0) > 0) = ?3)
+     AND ((?4 IS NULL AND ?5 IS NULL) OR EXISTS (
+         SELECT 1 FROM lotes l
+         WHERE l.id_produto = p.id AND l.quantidade_total > 0
+           AND (?4 IS NULL OR l.validade >= ?4)
+           AND (?5 IS NULL OR l.validade <= ?5)))";
+
+/// Coluna de ordenação de produtos para cada `Ordem`.
+fn coluna_produto(ordem: Ordem) -> &'static str {
+    match ordem {
+        Ordem::Nome => "p.nome",
+        Ordem::Validade => "e.validade",
+        Ordem::Quantidade => "COALESCE(e.quantidade, 0)",
+    }
+}
+
+/// Página dos produtos que atendem `condicao` (onde `?1` vale `valor`) e os filtros da lista.
+fn pagina_de_produtos(conn: &Connection, condicao: &str, valor: &dyn ToSql, filtro: &FiltroLista) -> Result<Pagina<Produto>, ErroApp> {
+    let de_onde = format!("{} WHERE {} AND {}", CONSULTA_PRODUTOS, condicao, FILTROS_PRODUTOS);
+    let ordem = filtro.ordenacao(Ordem::Nome, coluna_produto, "p.id");
+    let valores: [&dyn ToSql; 7] = [
+        valor, &filtro.secao, &filtro.com_prateleira, &filtro.validade_de, &filtro.validade_ate,
+        &filtro.limite, &filtro.deslocamento,
+    ];
+    
+    let total = conn.query_row(&format!("SELECT COUNT(*) {}", de_onde), &valores[..5], |row| row.get(0))?;
+    
+    let mut stmt = conn.prepare(&format!(
+        "SELECT p.id, p.nome, p.id_tipo, p.codigo_barras, pa.custo_centavos, pa.preco_venda_centavos
+         {} {} LIMIT ?6 OFFSET ?7",
+        de_onde, ordem
+    ))?;
+    let produtos = stmt.query_map(&valores[..], |row| {
+        Ok(Produto {
+            id: row.get(0)?,
+            nome: row.get(1)?,
+            id_tipo: row.get(2)?,
+            codigo_barras: row.get(3)?,
+            custo_centavos: row.get(4)?,
+            preco_venda_centavos: row.get(5)?,
+        })
+    })?;
     
-    let existe: bool = conn.query_row(
-        "SELECT 1 FROM usuarios WHERE nome = ?1 AND password = ?2",
-        [&login.nome, &login.password],
-        |_| Ok(true)
-    ).unwrap_or(false);
+    let mut itens = Vec::new();
+    for produto in produtos {
+        itens.push(produto?);
+    }
+    
+    Ok(Pagina { itens, total, limite: filtro.limite, deslocamento: filtro.deslocamento })
+}
+
+/// Página dos lotes que atendem `condicao` (onde `?1` vale `valor`) e os filtros da lista.
+fn pagina_de_lotes(conn: &Connection, condicao: &str, valor: &dyn ToSql, filtro: &FiltroLista) -> Result<Pagina<Lote>, ErroApp> {
+    let de_onde = format!(
+        "FROM lotes l
+         JOIN produtos p ON p.id = l.id_produto
+         JOIN tipos t ON t.id = p.id_tipo
+         WHERE {}
+           AND (?2 IS NULL OR t.id_secao = ?2)
+           AND (?3 IS NULL OR (l.quantidade_prateleira > 0) = ?3)
+           AND (?4 IS NULL OR l.validade >= ?4)
+           AND (?5 IS NULL OR l.validade <= ?5)",
+        condicao
+    );
+    let ordem = filtro.ordenacao(Ordem::Validade, |ordem| match ordem {
+        Ordem::Nome => "p.nome",
+        Ordem::Validade => "l.validade",
+        Ordem::Quantidade => "l.quantidade_total",
+    }, "l.validade, l.id");
+    let valores: [&dyn ToSql; 7] = [
+        valor, &filtro.secao, &filtro.com_prateleira, &filtro.validade_de, &filtro.validade_ate,
+        &filtro.limite, &filtro.deslocamento,
+    ];
+    
+    let total = conn.query_row(&format!("SELECT COUNT(*) {}", de_onde), &valores[..5], |row| row.get(0))?;
+    
+    let mut stmt = conn.prepare(&format!(
+        "SELECT l.id, l.id_produto, l.validade, l.quantidade_total, l.quantidade_prateleira, l.codigo
+         {} {} LIMIT ?6 OFFSET ?7",
+        de_onde, ordem
+    ))?;
+    let lotes = stmt.query_map(&valores[..], lote_da_linha)?;
+    
+    let mut itens = Vec::new();
+    for lote in lotes {
+        itens.push(lote?);
+    }
     
-    Json(existe)
+    Ok(Pagina { itens, total, limite: filtro.limite, deslocamento: filtro.deslocamento })
+}
+
+// ===========================================
+// HANDLER DE LOGIN
+// ===========================================
+
+#[utoipa::path(post, path = "/login", tag = "usuarios", request_body(content((LoginData = "application/json"), (LoginData = "application/x-www-form-urlencoded"))),
+    responses((status = 200, description = "Se o usuário e a senha conferem. Quando conferem, abre a sessão no cookie `sessao`", body = bool)))]
+async fn login_handler(Entrada(login): Entrada<LoginData>) -> Result<Response, ErroApp> {
+    let conn = init_db()?;
+    
+    let usuario: Option<(i32, String)> = conn.query_row(
+        "SELECT id, senha_hash FROM usuarios WHERE nome = ?1",
+        [&login.nome],
+        |row| Ok((row.get(0)?, row.get(1)?))
+    ).optional()?;
+    
+    match usuario.filter(|(_, hash)| usuarios::senha_confere(&login.password, hash)) {
+        Some((id, _)) => {
+            let token = usuarios::abrir_sessao(&conn, id)?;
+            Ok(([(header::SET_COOKIE, usuarios::cookie_da_sessao(&token))], Json(true)).into_response())
+        }
+        None => Ok(Json(false).into_response()),
+    }
 }
 
 // ===========================================
 // HANDLERS DE SEÇÕES
 // ===========================================
 
-async fn listar_secoes_handler() -> Result<Json<Vec<Secao>>, StatusCode> {
-    let conn = init_db().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+#[utoipa::path(get, path = "/secoes", tag = "secoes",
+    responses((status = 200, body = Vec<Secao>)))]
+async fn listar_secoes_handler() -> Result<Json<Vec<Secao>>, ErroApp> {
+    let conn = init_db()?;
     
     let mut stmt = conn.prepare("SELECT id, nome FROM secoes ORDER BY nome")
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        ?;
     
     let secoes = stmt.query_map([], |row| {
         Ok(Secao {
             id: row.get(0)?,
             nome: row.get(1)?,
         })
-    }).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    })?;
     
     let mut resultado = Vec::new();
     for secao in secoes {
-        resultado.push(secao.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?);
+        resultado.push(secao?);
     }
     
     Ok(Json(resultado))
 }
 
-async fn criar_secao_handler(Form(secao): Form<Secao>) -> Result<String, StatusCode> {
-    let conn = init_db().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    conn.execute(
-        "INSERT INTO secoes (nome) VALUES (?1)",
-        [&secao.nome],
-    ).map_err(|_| StatusCode::BAD_REQUEST)?;
+#[utoipa::path(post, path = "/secoes", tag = "secoes", request_body(content((SecaoData = "application/json"), (SecaoData = "application/x-www-form-urlencoded"))),
+    responses((status = 201, body = Secao)))]
+async fn criar_secao_handler(Entrada(secao): Entrada<SecaoData>) -> Result<(StatusCode, Json<Secao>), ErroApp> {
+    let nome = nome_obrigatorio(&secao.nome)?;
+    let conn = init_db()?;
+    
+    let secao = conn.query_row(
+        "INSERT INTO secoes (nome) VALUES (?1) RETURNING id, nome",
+        [&nome],
+        |row| Ok(Secao { id: row.get(0)?, nome: row.get(1)? })
+    )?;
+    eventos::publicar(Evento::SecaoCriada { id: secao.id });
     
-    Ok("Seção criada".to_string())
+    Ok((StatusCode::CREATED, Json(secao)))
 }
 
-async fn deletar_secao_handler(AxumPath(id): AxumPath<i32>) -> Result<String, StatusCode> {
-    let conn = init_db().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+#[utoipa::path(delete, path = "/secoes/{id}", tag = "secoes", params(("id" = i32, Path, description = "Id da seção")),
+    responses((status = 204, description = "Seção apagada, com seus tipos, produtos e lotes")))]
+async fn deletar_secao_handler(AxumPath(id): AxumPath<i32>) -> Result<StatusCode, ErroApp> {
+    let conn = init_db()?;
     
-    conn.execute(
+    let apagados = conn.execute(
         "DELETE FROM secoes WHERE id = ?1",
         [id],
-    ).map_err(|_| StatusCode::BAD_REQUEST)?;
+    )?;
+    if apagados == 0 {
+        return Err(ErroApp::NaoEncontrado("Seção não encontrada"));
+    }
+    eventos::publicar(Evento::SecaoApagada { id });
     
-    Ok("Seção deletada".to_string())
+    Ok(StatusCode::NO_CONTENT)
 }
 
 // ===========================================
 // HANDLERS DE TIPOS
 // ===========================================
 
-async fn listar_tipos_handler(AxumPath(secao_id): AxumPath<i32>) -> Result<Json<Vec<Tipo>>, StatusCode> {
-    let conn = init_db().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+#[utoipa::path(get, path = "/tipos/secao/{secao_id}", tag = "tipos", params(("secao_id" = i32, Path, description = "Id da seção")),
+    responses((status = 200, body = Vec<Tipo>)))]
+async fn listar_tipos_handler(AxumPath(secao_id): AxumPath<i32>) -> Result<Json<Vec<Tipo>>, ErroApp> {
+    let conn = init_db()?;
     
     let mut stmt = conn.prepare(
         "SELECT id, nome, id_secao FROM tipos WHERE id_secao = ?1 ORDER BY nome"
-    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    )?;
     
     let tipos = stmt.query_map([secao_id], |row| {
         Ok(Tipo {
@@ -222,367 +680,291 @@ async fn listar_tipos_handler(AxumPath(secao_id): AxumPath<i32>) -> Result<Json<
             nome: row.get(1)?,
             id_secao: row.get(2)?,
         })
-    }).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    })?;
     
     let mut resultado = Vec::new();
     for tipo in tipos {
-        resultado.push(tipo.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?);
+        resultado.push(tipo?);
     }
     
     Ok(Json(resultado))
 }
 
-async fn criar_tipo_handler(Form(tipo): Form<Tipo>) -> Result<String, StatusCode> {
-    let conn = init_db().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    conn.execute(
-        "INSERT INTO tipos (nome, id_secao) VALUES (?1, ?2)",
-        params![&tipo.nome, &tipo.id_secao],
-    ).map_err(|_| StatusCode::BAD_REQUEST)?;
+#[utoipa::path(post, path = "/tipos", tag = "tipos", request_body(content((TipoData = "application/json"), (TipoData = "application/x-www-form-urlencoded"))),
+    responses((status = 201, body = Tipo)))]
+async fn criar_tipo_handler(Entrada(tipo): Entrada<TipoData>) -> Result<(StatusCode, Json<Tipo>), ErroApp> {
+    let nome = nome_obrigatorio(&tipo.nome)?;
+    let conn = init_db()?;
+    
+    let tipo = conn.query_row(
+        "INSERT INTO tipos (nome, id_secao) VALUES (?1, ?2) RETURNING id, nome, id_secao",
+        params![nome, tipo.id_secao],
+        |row| Ok(Tipo { id: row.get(0)?, nome: row.get(1)?, id_secao: row.get(2)? })
+    )?;
+    eventos::publicar(Evento::TipoCriado { id: tipo.id, id_secao: tipo.id_secao });
     
-    Ok("Tipo criado".to_string())
+    Ok((StatusCode::CREATED, Json(tipo)))
 }
 
-async fn deletar_tipo_handler(AxumPath(id): AxumPath<i32>) -> Result<String, StatusCode> {
-    let conn = init_db().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+#[utoipa::path(delete, path = "/tipos/{id}", tag = "tipos", params(("id" = i32, Path, description = "Id do tipo")),
+    responses((status = 204, description = "Tipo apagado, com seus produtos e lotes")))]
+async fn deletar_tipo_handler(AxumPath(id): AxumPath<i32>) -> Result<StatusCode, ErroApp> {
+    let conn = init_db()?;
     
-    conn.execute(
-        "DELETE FROM tipos WHERE id = ?1",
+    let id_secao: i32 = conn.query_row(
+        "DELETE FROM tipos WHERE id = ?1 RETURNING id_secao",
         [id],
-    ).map_err(|_| StatusCode::BAD_REQUEST)?;
+        |row| row.get(0)
+    ).optional()?.ok_or(ErroApp::NaoEncontrado("Tipo não encontrado"))?;
+    eventos::publicar(Evento::TipoApagado { id, id_secao });
     
-    Ok("Tipo deletado".to_string())
+    Ok(StatusCode::NO_CONTENT)
 }
 
 // ===========================================
 // HANDLERS DE PRODUTOS
 // ===========================================
 
-async fn listar_produtos_handler(AxumPath(tipo_id): AxumPath<i32>) -> Result<Json<Vec<Produto>>, StatusCode> {
-    let conn = init_db().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    let mut stmt = conn.prepare(
-        "SELECT id, nome, id_tipo FROM produtos WHERE id_tipo = ?1 ORDER BY nome"
-    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    let produtos = stmt.query_map([tipo_id], |row| {
-        Ok(Produto {
+#[utoipa::path(get, path = "/produtos/tipo/{tipo_id}", tag = "produtos",
+    params(("tipo_id" = i32, Path, description = "Id do tipo"), ParametrosLista),
+    responses((status = 200, body = Pagina<Produto>)))]
+async fn listar_produtos_handler(
+    AxumPath(tipo_id): AxumPath<i32>,
+    Query(parametros): Query<ParametrosLista>
+) -> Result<Json<Pagina<Produto>>, ErroApp> {
+    let filtro = parametros.filtro()?;
+    let conn = init_db()?;
+    
+    Ok(Json(pagina_de_produtos(&conn, "p.id_tipo = ?1", &tipo_id, &filtro)?))
+}
+
+#[utoipa::path(post, path = "/produtos", tag = "produtos", request_body(content((ProdutoData = "application/json"), (ProdutoData = "application/x-www-form-urlencoded"))),
+    responses((status = 201, body = Produto)))]
+async fn criar_produto_handler(Entrada(produto): Entrada<ProdutoData>) -> Result<(StatusCode, Json<Produto>), ErroApp> {
+    let nome_maiusculo = nome_obrigatorio(&produto.nome)?.to_uppercase();
+    let conn = init_db()?;
+    
+    // Produto novo ainda não tem preço
+    let produto = conn.query_row(
+        "INSERT INTO produtos (nome, id_tipo, codigo_barras) VALUES (?1, ?2, ?3)
+         RETURNING id, nome, id_tipo, codigo_barras",
+        params![nome_maiusculo, produto.tipo_id, texto_opcional(produto.codigo_barras)],
+        |row| Ok(Produto {
             id: row.get(0)?,
             nome: row.get(1)?,
             id_tipo: row.get(2)?,
+            codigo_barras: row.get(3)?,
+            custo_centavos: None,
+            preco_venda_centavos: None,
         })
-    }).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    let mut resultado = Vec::new();
-    for produto in produtos {
-        resultado.push(produto.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?);
-    }
-    
-    Ok(Json(resultado))
-}
-
-async fn criar_produto_handler(Form(produto): Form<ProdutoData>) -> Result<String, StatusCode> {
-    let conn = init_db().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    let nome_maiusculo = produto.nome.to_uppercase();
-    
-    conn.execute(
-        "INSERT INTO produtos (nome, id_tipo) VALUES (?1, ?2)",
-        params![nome_maiusculo, produto.tipo_id],
-    ).map_err(|_| StatusCode::BAD_REQUEST)?;
+    )?;
+    eventos::publicar(Evento::ProdutoCriado { id: produto.id, id_tipo: produto.id_tipo });
     
-    Ok("Produto criado".to_string())
+    Ok((StatusCode::CREATED, Json(produto)))
 }
 
-async fn deletar_produto_handler(AxumPath(id): AxumPath<i32>) -> Result<String, StatusCode> {
-    let conn = init_db().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+#[utoipa::path(delete, path = "/produtos/{id}", tag = "produtos", params(("id" = i32, Path, description = "Id do produto")),
+    responses((status = 204, description = "Produto apagado, com seus lotes")))]
+async fn deletar_produto_handler(AxumPath(id): AxumPath<i32>) -> Result<StatusCode, ErroApp> {
+    let conn = init_db()?;
     
-    conn.execute(
-        "DELETE FROM produtos WHERE id = ?1",
+    let id_tipo: i32 = conn.query_row(
+        "DELETE FROM produtos WHERE id = ?1 RETURNING id_tipo",
         [id],
-    ).map_err(|_| StatusCode::BAD_REQUEST)?;
+        |row| row.get(0)
+    ).optional()?.ok_or(ErroApp::NaoEncontrado("Produto não encontrado"))?;
+    eventos::publicar(Evento::ProdutoApagado { id, id_tipo });
     
-    Ok("Produto deletado".to_string())
+    Ok(StatusCode::NO_CONTENT)
 }
 
 // ===========================================
 // HANDLERS DE LOTES
 // ===========================================
 
-async fn listar_lotes_handler(AxumPath(produto_id): AxumPath<i32>) -> Result<Json<Vec<Lote>>, StatusCode> {
-    let conn = init_db().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    let mut stmt = conn.prepare(
-        "SELECT id, id_produto, validade, quantidade_total, quantidade_prateleira 
-         FROM lotes WHERE id_produto = ?1 ORDER BY validade"
-    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    let lotes = stmt.query_map([produto_id], |row| {
-        Ok(Lote {
-            id: row.get(0)?,
-            id_produto: row.get(1)?,
-            validade: row.get(2)?,
-            quantidade_total: row.get(3)?,
-            quantidade_prateleira: row.get(4)?,
-        })
-    }).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+#[utoipa::path(get, path = "/lotes/produto/{produto_id}", tag = "lotes",
+    params(("produto_id" = i32, Path, description = "Id do produto"), ParametrosLista),
+    responses((status = 200, body = Pagina<Lote>)))]
+async fn listar_lotes_handler(
+    AxumPath(produto_id): AxumPath<i32>,
+    Query(parametros): Query<ParametrosLista>
+) -> Result<Json<Pagina<Lote>>, ErroApp> {
+    let filtro = parametros.filtro()?;
+    let conn = init_db()?;
+    
+    Ok(Json(pagina_de_lotes(&conn, "l.id_produto = ?1", &produto_id, &filtro)?))
+}
+
+#[utoipa::path(post, path = "/lotes", tag = "lotes", request_body(content((LoteData = "application/json"), (LoteData = "application/x-www-form-urlencoded"))),
+    responses((status = 201, body = Lote)))]
+async fn criar_lote_handler(Entrada(lote): Entrada<LoteData>) -> Result<(StatusCode, Json<Lote>), ErroApp> {
+    let conn = init_db()?;
     
-    let mut resultado = Vec::new();
-    for lote in lotes {
-        resultado.push(lote.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?);
-    }
+    let lote = criar_lote(&conn, lote)?;
+    eventos::publicar(Evento::LoteCriado { id: lote.id, id_produto: lote.id_produto });
     
-    Ok(Json(resultado))
+    Ok((StatusCode::CREATED, Json(lote)))
 }
 
-async fn criar_lote_handler(Form(lote): Form<LoteData>) -> Result<String, StatusCode> {
-    let conn = init_db().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
+fn criar_lote(conn: &Connection, lote: LoteData) -> Result<Lote, ErroApp> {
+    if !validade_valida(&lote.validade) {
+        return Err(ErroApp::invalido("validade_invalida", "A validade deve estar no formato AAAA-MM-DD"));
+    }
+    if lote.quantidade_total < 0 || lote.quantidade_prateleira < 0 {
+        return Err(ErroApp::invalido("quantidade_invalida", "As quantidades não podem ser negativas"));
+    }
     if lote.quantidade_prateleira > lote.quantidade_total {
-        return Err(StatusCode::BAD_REQUEST);
+        return Err(ErroApp::invalido(
+            "prateleira_maior_que_total",
+            "A quantidade na prateleira não pode ser maior que o total do lote",
+        ));
     }
     
-    conn.execute(
-        "INSERT INTO lotes (id_produto, validade, quantidade_total, quantidade_prateleira) 
-         VALUES (?1, ?2, ?3, ?4)",
+    let lote = conn.query_row(
+        &format!(
+            "INSERT INTO lotes (id_produto, validade, quantidade_total, quantidade_prateleira, codigo) 
+             VALUES (?1, ?2, ?3, ?4, ?5) RETURNING {}",
+            COLUNAS_LOTE
+        ),
         params![
             lote.produto_id,
             lote.validade,
             lote.quantidade_total,
-            lote.quantidade_prateleira
+            lote.quantidade_prateleira,
+            texto_opcional(lote.codigo)
         ],
-    ).map_err(|_| StatusCode::BAD_REQUEST)?;
+        lote_da_linha
+    )?;
     
-    Ok("Lote criado".to_string())
+    Ok(lote)
 }
 
-async fn deletar_lote_handler(AxumPath(id): AxumPath<i32>) -> Result<String, StatusCode> {
-    let conn = init_db().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+#[utoipa::path(delete, path = "/lotes/{id}", tag = "lotes", params(("id" = i32, Path, description = "Id do lote")),
+    responses((status = 204, description = "Lote apagado")))]
+async fn deletar_lote_handler(AxumPath(id): AxumPath<i32>) -> Result<StatusCode, ErroApp> {
+    let conn = init_db()?;
     
-    conn.execute(
-        "DELETE FROM lotes WHERE id = ?1",
+    let id_produto: i32 = conn.query_row(
+        "DELETE FROM lotes WHERE id = ?1 RETURNING id_produto",
         [id],
-    ).map_err(|_| StatusCode::BAD_REQUEST)?;
+        |row| row.get(0)
+    ).optional()?.ok_or(ErroApp::NaoEncontrado("Lote não encontrado"))?;
+    eventos::publicar(Evento::LoteApagado { id, id_produto });
     
-    Ok("Lote deletado".to_string())
+    Ok(StatusCode::NO_CONTENT)
 }
 
 // ===========================================
 // FUNÇÕES DE NEGÓCIO
 // ===========================================
 
+#[utoipa::path(post, path = "/vender/{id}", tag = "lotes", params(("id" = i32, Path, description = "Id do lote")), request_body(content((VendaData = "application/json"), (VendaData = "application/x-www-form-urlencoded"))),
+    responses((status = 200, description = "O lote depois da venda", body = Lote)))]
 async fn vender_lote_handler(
     AxumPath(id): AxumPath<i32>,
-    Form(venda): Form<VendaData>
-) -> Result<String, StatusCode> {
-    let conn = init_db().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    let (na_prateleira,): (i32,) = conn.query_row(
-        "SELECT quantidade_prateleira FROM lotes WHERE id = ?1",
-        [id],
-        |row| Ok((row.get(0)?,))
-    ).map_err(|_| StatusCode::NOT_FOUND)?;
-    
-    if venda.quantidade > na_prateleira {
-        return Err(StatusCode::BAD_REQUEST);
-    }
-    
-    let nova_prateleira = na_prateleira - venda.quantidade;
+    Entrada(venda): Entrada<VendaData>
+) -> Result<Json<Lote>, ErroApp> {
+    let mut conn = init_db()?;
     
-    conn.execute(
-        "UPDATE lotes SET quantidade_prateleira = ?1 WHERE id = ?2",
-        params![nova_prateleira, id],
-    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let tx = conn.transaction()?;
+    let lote = vender(&tx, id, venda.quantidade, None)?;
+    tx.commit()?;
+    eventos::publicar(Evento::LoteAlterado { id, id_produto: lote.id_produto });
     
-    Ok(format!("Vendido: {} unidades", venda.quantidade))
+    Ok(Json(lote))
 }
 
-async fn abastecer_handler(
-    AxumPath(id): AxumPath<i32>,
-    Form(abastecimento): Form<VendaData>
-) -> Result<String, StatusCode> {
-    let conn = init_db().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+/// Tira unidades da prateleira do lote e registra a venda em `data` (ou
+/// agora). Deve rodar dentro de uma transação.
+fn vender(conn: &Connection, id: i32, quantidade: i32, data: Option<&str>) -> Result<Lote, ErroApp> {
+    if quantidade <= 0 {
+        return Err(ErroApp::invalido("quantidade_invalida", "A quantidade deve ser maior que zero"));
+    }
     
-    let (na_prateleira, total): (i32, i32) = conn.query_row(
-        "SELECT quantidade_prateleira, quantidade_total FROM lotes WHERE id = ?1",
+    let (id_produto, na_prateleira): (i32, i32) = conn.query_row(
+        "SELECT id_produto, quantidade_prateleira FROM lotes WHERE id = ?1",
         [id],
         |row| Ok((row.get(0)?, row.get(1)?))
-    ).map_err(|_| StatusCode::NOT_FOUND)?;
+    ).optional()?.ok_or(ErroApp::NaoEncontrado("Lote não encontrado"))?;
     
-    if na_prateleira + abastecimento.quantidade > total {
-        return Err(StatusCode::BAD_REQUEST);
+    if quantidade > na_prateleira {
+        return Err(ErroApp::invalido(
+            "estoque_insuficiente",
+            format!("Só há {} unidades na prateleira deste lote", na_prateleira),
+        ));
     }
     
-    let nova_prateleira = na_prateleira + abastecimento.quantidade;
+    let lote = conn.query_row(
+        &format!(
+            "UPDATE lotes SET quantidade_prateleira = quantidade_prateleira - ?1 WHERE id = ?2 RETURNING {}",
+            COLUNAS_LOTE
+        ),
+        params![quantidade, id],
+        lote_da_linha
+    )?;
     
+    // Histórico usado para calcular a velocidade de vendas
     conn.execute(
-        "UPDATE lotes SET quantidade_prateleira = ?1 WHERE id = ?2",
-        params![nova_prateleira, id],
-    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        "INSERT INTO vendas (id_lote, id_produto, quantidade, data)
+         VALUES (?1, ?2, ?3, COALESCE(?4, datetime('now', 'localtime')))",
+        params![id, id_produto, quantidade, data],
+    )?;
     
-    Ok(format!("Abastecido: {} unidades", abastecimento.quantidade))
+    Ok(lote)
 }
 
-// ===========================================
-// PESQUISA
-// ===========================================
-
-async fn pesquisar_handler(Query(params): Query<Vec<(String, String)>>) -> Result<Json<Vec<Produto>>, StatusCode> {
-    let termo = params.iter()
-        .find(|(k, _)| k == "q")
-        .map(|(_, v)| v)
-        .unwrap_or(&String::new())
-        .to_uppercase();
-    
-    let conn = init_db().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    let termo_busca = format!("%{}%", termo);
-    
-    let mut stmt = conn.prepare(
-        "SELECT id, nome, id_tipo FROM produtos WHERE UPPER(nome) LIKE ?1 ORDER BY nome"
-    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    let produtos = stmt.query_map([termo_busca], |row| {
-        Ok(Produto {
-            id: row.get(0)?,
-            nome: row.get(1)?,
-            id_tipo: row.get(2)?,
-        })
-    }).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+#[utoipa::path(post, path = "/abastecer/{id}", tag = "lotes", params(("id" = i32, Path, description = "Id do lote")), request_body(content((VendaData = "application/json"), (VendaData = "application/x-www-form-urlencoded"))),
+    responses((status = 200, description = "O lote depois de levar as unidades para a prateleira", body = Lote)))]
+async fn abastecer_handler(
+    AxumPath(id): AxumPath<i32>,
+    Entrada(abastecimento): Entrada<VendaData>
+) -> Result<Json<Lote>, ErroApp> {
+    let conn = init_db()?;
     
-    let mut resultado = Vec::new();
-    for produto in produtos {
-        resultado.push(produto.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?);
-    }
+    let lote = abastecer(&conn, id, abastecimento.quantidade)?;
+    eventos::publicar(Evento::LoteAlterado { id, id_produto: lote.id_produto });
     
-    Ok(Json(resultado))
+    Ok(Json(lote))
 }
 
-// ===========================================
-// RELATÓRIO COMPLETO
-// ===========================================
-
-async fn relatorio_handler() -> Result<Json<Vec<RelatorioItem>>, StatusCode> {
-    let conn = init_db().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    let mut stmt = conn.prepare(
-        "SELECT s.id, s.nome, 
-                COALESCE(SUM(l.quantidade_total), 0) as total,
-                COALESCE(SUM(l.quantidade_prateleira), 0) as prateleira
-         FROM secoes s
-         LEFT JOIN tipos t ON s.id = t.id_secao
-         LEFT JOIN produtos p ON t.id = p.id_tipo
-         LEFT JOIN lotes l ON p.id = l.id_produto
-         GROUP BY s.id"
-    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+/// Leva unidades do estoque do lote para a prateleira.
+fn abastecer(conn: &Connection, id: i32, quantidade: i32) -> Result<Lote, ErroApp> {
+    if quantidade <= 0 {
+        return Err(ErroApp::invalido("quantidade_invalida", "A quantidade deve ser maior que zero"));
+    }
     
-    let mut resultado = Vec::new();
-    let secoes = stmt.query_map([], |row| {
-        Ok(RelatorioItem {
-            id: row.get(0)?,
-            nome: row.get(1)?,
-            tipo: "secao".to_string(),
-            total: row.get(2)?,
-            prateleira: row.get(3)?,
-            estoque: row.get::<_, i32>(2)? - row.get::<_, i32>(3)?,
-            filhos: Vec::new(),
-        })
-    }).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let (na_prateleira, total): (i32, i32) = conn.query_row(
+        "SELECT quantidade_prateleira, quantidade_total FROM lotes WHERE id = ?1",
+        [id],
+        |row| Ok((row.get(0)?, row.get(1)?))
+    ).optional()?.ok_or(ErroApp::NaoEncontrado("Lote não encontrado"))?;
     
-    for secao in secoes {
-        let mut secao_item = secao.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        
-        let mut stmt_tipos = conn.prepare(
-            "SELECT t.id, t.nome,
-                    COALESCE(SUM(l.quantidade_total), 0) as total,
-                    COALESCE(SUM(l.quantidade_prateleira), 0) as prateleira
-             FROM tipos t
-             LEFT JOIN produtos p ON t.id = p.id_tipo
-             LEFT JOIN lotes l ON p.id = l.id_produto
-             WHERE t.id_secao = ?1
-             GROUP BY t.id"
-        ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        
-        let tipos = stmt_tipos.query_map([secao_item.id], |row| {
-            Ok(RelatorioItem {
-                id: row.get(0)?,
-                nome: row.get(1)?,
-                tipo: "tipo".to_string(),
-                total: row.get(2)?,
-                prateleira: row.get(3)?,
-                estoque: row.get::<_, i32>(2)? - row.get::<_, i32>(3)?,
-                filhos: Vec::new(),
-            })
-        }).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        
-        for tipo in tipos {
-            let mut tipo_item = tipo.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-            
-            let mut stmt_produtos = conn.prepare(
-                "SELECT p.id, p.nome,
-                        COALESCE(SUM(l.quantidade_total), 0) as total,
-                        COALESCE(SUM(l.quantidade_prateleira), 0) as prateleira
-                 FROM produtos p
-                 LEFT JOIN lotes l ON p.id = l.id_produto
-                 WHERE p.id_tipo = ?1
-                 GROUP BY p.id"
-            ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-            
-            let produtos = stmt_produtos.query_map([tipo_item.id], |row| {
-                Ok(RelatorioItem {
-                    id: row.get(0)?,
-                    nome: row.get(1)?,
-                    tipo: "produto".to_string(),
-                    total: row.get(2)?,
-                    prateleira: row.get(3)?,
-                    estoque: row.get::<_, i32>(2)? - row.get::<_, i32>(3)?,
-                    filhos: Vec::new(),
-                })
-            }).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-            
-            for produto in produtos {
-                let mut produto_item = produto.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-                
-                let mut stmt_lotes = conn.prepare(
-                    "SELECT l.id, l.validade, l.quantidade_total, l.quantidade_prateleira
-                     FROM lotes l
-                     WHERE l.id_produto = ?1"
-                ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-                
-                let lotes = stmt_lotes.query_map([produto_item.id], |row| {
-                    Ok(RelatorioItem {
-                        id: row.get(0)?,
-                        nome: format!("Lote {}", row.get::<_, String>(1)?),
-                        tipo: "lote".to_string(),
-                        total: row.get(2)?,
-                        prateleira: row.get(3)?,
-                        estoque: row.get::<_, i32>(2)? - row.get::<_, i32>(3)?,
-                        filhos: Vec::new(),
-                    })
-                }).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-                
-                for lote in lotes {
-                    produto_item.filhos.push(lote.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?);
-                }
-                
-                tipo_item.filhos.push(produto_item);
-            }
-            
-            secao_item.filhos.push(tipo_item);
-        }
-        
-        resultado.push(secao_item);
+    if na_prateleira + quantidade > total {
+        return Err(ErroApp::invalido(
+            "estoque_insuficiente",
+            format!("Só há {} unidades fora da prateleira neste lote", total - na_prateleira),
+        ));
     }
     
-    Ok(Json(resultado))
+    let nova_prateleira = na_prateleira + quantidade;
+    
+    let lote = conn.query_row(
+        &format!("UPDATE lotes SET quantidade_prateleira = ?1 WHERE id = ?2 RETURNING {}", COLUNAS_LOTE),
+        params![nova_prateleira, id],
+        lote_da_linha
+    )?;
+    
+    Ok(lote)
 }
 
 // ===========================================
 // CSV
 // ===========================================
 
-async fn exportar_csv_handler() -> Result<String, StatusCode> {
-    let conn = init_db().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+#[utoipa::path(get, path = "/exportar", tag = "dados",
+    responses((status = 200, description = "Seção,Tipo,Produto,Validade,Total,Prateleira", body = String)))]
+async fn exportar_csv_handler() -> Result<String, ErroApp> {
+    let conn = init_db()?;
     
     let mut stmt = conn.prepare(
         "SELECT s.nome, t.nome, p.nome, l.validade, l.quantidade_total, l.quantidade_prateleira
@@ -591,7 +973,7 @@ async fn exportar_csv_handler() -> Result<String, StatusCode> {
          JOIN produtos p ON t.id = p.id_tipo
          JOIN lotes l ON p.id = l.id_produto
          ORDER BY s.nome, t.nome, p.nome"
-    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    )?;
     
     let mut csv = String::from("Seção,Tipo,Produto,Validade,Total,Prateleira\n");
     let linhas = stmt.query_map([], |row| {
@@ -604,33 +986,71 @@ async fn exportar_csv_handler() -> Result<String, StatusCode> {
             row.get::<_, i32>(4)?,
             row.get::<_, i32>(5)?
         ))
-    }).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    })?;
     
     for linha in linhas {
-        csv += &linha.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        csv += &linha?;
     }
     
     Ok(csv)
 }
 
-async fn importar_csv_handler(csv_data: String) -> Result<String, StatusCode> {
-    let conn = init_db().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+/// Aceita o CSV exportado ou uma planilha .xlsx com as mesmas colunas.
+/// As duas entradas viram linhas de texto e passam pela mesma validação.
+#[utoipa::path(post, path = "/importar", tag = "dados",
+    request_body(description = "Substitui todo o estoque; o que continua na planilha mantém preços, mínimos e rebaixas", content(
+        ("text/csv"),
+        ("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+    )),
+    responses((status = 200, description = "Quantos lotes foram importados", body = String)))]
+async fn importar_handler(corpo: Bytes) -> Result<String, ErroApp> {
+    let linhas: Vec<Vec<String>> = if planilha::parece_xlsx(&corpo) {
+        planilha::linhas_xlsx(corpo.to_vec())
+            .map_err(|e| ErroApp::invalido("arquivo_invalido", format!("Não foi possível ler a planilha: {}", e)))?
+    } else {
+        let csv_data = String::from_utf8(corpo.to_vec())
+            .map_err(|_| ErroApp::invalido("arquivo_invalido", "O CSV precisa estar em UTF-8"))?;
+        csv_data.lines()
+            .skip(1)
+            .map(|line| line.split(',').map(|col| col.to_string()).collect())
+            .collect()
+    };
+
+    let mut conn = init_db()?;
+    let tx = conn.transaction()?;
+    let (linhas_importadas, erros) = importar_linhas(&tx, &linhas)?;
+    tx.commit()?;
+    eventos::publicar(Evento::EstoqueSubstituido);
     
-    conn.execute("DELETE FROM lotes", []).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    conn.execute("DELETE FROM produtos", []).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    conn.execute("DELETE FROM tipos", []).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    conn.execute("DELETE FROM secoes", []).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(format!("Importados {} lotes, {} erros", linhas_importadas, erros))
+}
+
+/// Troca o estoque pelo das linhas sem recriar o que continua nelas: seções,
+/// tipos e produtos são casados pelo nome e mantêm o id, e com ele preços,
+/// estoques mínimos e limites de rebaixa; lotes são casados por produto e
+/// validade e só recebem as quantidades novas, mantendo código e rebaixas.
+/// O que não aparece nas linhas é apagado. Deve rodar dentro de uma transação.
+fn importar_linhas(conn: &Connection, linhas: &[Vec<String>]) -> Result<(usize, usize), rusqlite::Error> {
+    // Lotes de mesmo produto e validade são casados um a um, do mais antigo ao mais novo
+    let mut lotes_existentes: HashMap<(i32, String), VecDeque<i32>> = HashMap::new();
+    {
+        let mut stmt = conn.prepare("SELECT id, id_produto, validade FROM lotes ORDER BY id")?;
+        let mut existentes = stmt.query([])?;
+        while let Some(row) = existentes.next()? {
+            lotes_existentes.entry((row.get(1)?, row.get(2)?)).or_default().push_back(row.get(0)?);
+        }
+    }
     
+    let mut secoes = HashSet::new();
+    let mut tipos = HashSet::new();
+    let mut produtos = HashSet::new();
+    let mut lotes = HashSet::new();
     let mut linhas_importadas = 0;
     let mut erros = 0;
     
-    for (i, line) in csv_data.lines().enumerate() {
-        if i == 0 { continue; }
+    for cols in linhas {
+        if cols.iter().all(|col| col.trim().is_empty()) { continue; }
         
-        let line = line.trim();
-        if line.is_empty() { continue; }
-        
-        let cols: Vec<&str> = line.split(',').collect();
         if cols.len() < 6 {
             erros += 1;
             continue;
@@ -640,139 +1060,438 @@ async fn importar_csv_handler(csv_data: String) -> Result<String, StatusCode> {
         let tipo_nome = cols[1].trim();
         let produto_nome = cols[2].trim().to_uppercase();
         let validade = cols[3].trim();
+        if !validade_valida(validade) {
+            erros += 1;
+            continue;
+        }
         let quantidade_total = cols[4].trim().parse::<i32>().unwrap_or(0);
         let quantidade_prateleira = cols[5].trim().parse::<i32>().unwrap_or(0);
         
         conn.execute(
-            "INSERT INTO secoes (nome) VALUES (?1)",
+            "INSERT OR IGNORE INTO secoes (nome) VALUES (?1)",
             [secao_nome],
-        ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        )?;
         
         let secao_id: i32 = conn.query_row(
             "SELECT id FROM secoes WHERE nome = ?1",
             [secao_nome],
             |row| row.get(0)
-        ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        )?;
+        secoes.insert(secao_id);
         
         conn.execute(
-            "INSERT INTO tipos (nome, id_secao) VALUES (?1, ?2)",
+            "INSERT OR IGNORE INTO tipos (nome, id_secao) VALUES (?1, ?2)",
             params![tipo_nome, secao_id],
-        ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        )?;
         
         let tipo_id: i32 = conn.query_row(
             "SELECT id FROM tipos WHERE nome = ?1 AND id_secao = ?2",
             params![tipo_nome, secao_id],
             |row| row.get(0)
-        ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        )?;
+        tipos.insert(tipo_id);
         
         conn.execute(
-            "INSERT INTO produtos (nome, id_tipo) VALUES (?1, ?2)",
+            "INSERT OR IGNORE INTO produtos (nome, id_tipo) VALUES (?1, ?2)",
             params![produto_nome, tipo_id],
-        ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        )?;
         
         let produto_id: i32 = conn.query_row(
             "SELECT id FROM produtos WHERE nome = ?1 AND id_tipo = ?2",
             params![produto_nome, tipo_id],
             |row| row.get(0)
-        ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        )?;
+        produtos.insert(produto_id);
         
-        conn.execute(
-            "INSERT INTO lotes (id_produto, validade, quantidade_total, quantidade_prateleira) 
-             VALUES (?1, ?2, ?3, ?4)",
-            params![produto_id, validade, quantidade_total, quantidade_prateleira],
-        ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let existente = lotes_existentes.get_mut(&(produto_id, validade.to_string())).and_then(VecDeque::pop_front);
+        let lote_id: i32 = match existente {
+            Some(lote_id) => {
+                conn.execute(
+                    "UPDATE lotes SET quantidade_total = ?1, quantidade_prateleira = ?2 WHERE id = ?3",
+                    params![quantidade_total, quantidade_prateleira, lote_id],
+                )?;
+                lote_id
+            }
+            None => conn.query_row(
+                "INSERT INTO lotes (id_produto, validade, quantidade_total, quantidade_prateleira) 
+                 VALUES (?1, ?2, ?3, ?4) RETURNING id",
+                params![produto_id, validade, quantidade_total, quantidade_prateleira],
+                |row| row.get(0)
+            )?,
+        };
+        lotes.insert(lote_id);
         
         linhas_importadas += 1;
     }
     
-    Ok(format!("Importados {} lotes, {} erros", linhas_importadas, erros))
+    apagar_os_demais(conn, "lotes", &lotes)?;
+    apagar_os_demais(conn, "produtos", &produtos)?;
+    apagar_os_demais(conn, "tipos", &tipos)?;
+    apagar_os_demais(conn, "secoes", &secoes)?;
+    
+    Ok((linhas_importadas, erros))
+}
+
+/// Apaga (em cascata) os registros de `tabela` cujo id não está em `manter`.
+fn apagar_os_demais(conn: &Connection, tabela: &str, manter: &HashSet<i32>) -> Result<(), rusqlite::Error> {
+    let ids: Vec<i32> = conn.prepare(&format!("SELECT id FROM {}", tabela))?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    
+    let mut apagar = conn.prepare(&format!("DELETE FROM {} WHERE id = ?1", tabela))?;
+    for id in ids.into_iter().filter(|id| !manter.contains(id)) {
+        apagar.execute([id])?;
+    }
+    
+    Ok(())
 }
 
 // ===========================================
 // PRODUTOS A VENCER
 // ===========================================
 
-async fn produtos_a_vencer_handler(AxumPath(dias): AxumPath<i32>) -> Result<Json<Vec<Lote>>, StatusCode> {
-    let conn = init_db().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    let mut stmt = conn.prepare(
-        "SELECT l.id, l.id_produto, l.validade, l.quantidade_total, l.quantidade_prateleira
-         FROM lotes l
-         WHERE julianday(l.validade) - julianday('now') <= ?1"
-    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    let lotes = stmt.query_map([dias], |row| {
-        Ok(Lote {
-            id: row.get(0)?,
-            id_produto: row.get(1)?,
-            validade: row.get(2)?,
-            quantidade_total: row.get(3)?,
-            quantidade_prateleira: row.get(4)?,
-        })
-    }).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    let mut resultado = Vec::new();
-    for lote in lotes {
-        resultado.push(lote.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?);
+fn dias_de_texto(dias: &str) -> Result<i32, ErroApp> {
+    dias.parse().map_err(|_| ErroApp::invalido("dias_invalido", format!("\"{}\" não é um número de dias", dias)))
+}
+
+/// `/api/vencer/:dias` devolve JSON; `/api/vencer/:dias.pdf` devolve a mesma lista em PDF.
+#[utoipa::path(get, path = "/vencer/{dias}", tag = "relatorios",
+    params(("dias" = String, Path, description = "Número de dias; com `.pdf` no fim (ex.: `7.pdf`) devolve PDF"), ParametrosLista),
+    responses((status = 200, description = "Em JSON, paginado; o PDF traz a lista inteira", content(
+        (Pagina<Lote> = "application/json"),
+        ([u8] = "application/pdf"),
+    ))))]
+async fn produtos_a_vencer_handler(
+    AxumPath(dias): AxumPath<String>,
+    Query(parametros): Query<ParametrosLista>
+) -> Result<Response, ErroApp> {
+    if let Some(dias) = dias.strip_suffix(".pdf") {
+        let dias = dias_de_texto(dias)?;
+        return pdf::vencer_pdf_handler(dias).await.map(IntoResponse::into_response);
     }
+    let dias = dias_de_texto(&dias)?;
+    let filtro = parametros.filtro()?;
+
+    let conn = init_db()?;
     
-    Ok(Json(resultado))
+    let pagina = pagina_de_lotes(&conn, "julianday(l.validade) - julianday('now') <= ?1", &dias, &filtro)?;
+    
+    Ok(Json(pagina).into_response())
 }
 
 // ===========================================
-// MAIN
+// ROTAS
 // ===========================================
 
-#[tokio::main]
-async fn main() {
-    let app = Router::new()
+/// Rotas da API, sem prefixo. São servidas em `/api/v1` e, como apelido
+/// obsoleto, em `/api`. Toda rota nova precisa de `#[utoipa::path]` e de
+/// entrar em `openapi::DocumentacaoApi`.
+fn rotas_api() -> Router {
+    Router::new()
         // Login
-        .route("/api/login", post(login_handler))
+        .route("/login", post(login_handler))
         
         // Seções
-        .route("/api/secoes", get(listar_secoes_handler))
-        .route("/api/secoes", post(criar_secao_handler))
-        .route("/api/secoes/:id", delete(deletar_secao_handler))
+        .route("/secoes", get(listar_secoes_handler))
+        .route("/secoes", post(criar_secao_handler))
+        .route("/secoes/:id", delete(deletar_secao_handler))
         
         // Tipos
-        .route("/api/tipos/secao/:secao_id", get(listar_tipos_handler))
-        .route("/api/tipos", post(criar_tipo_handler))
-        .route("/api/tipos/:id", delete(deletar_tipo_handler))
+        .route("/tipos/secao/:secao_id", get(listar_tipos_handler))
+        .route("/tipos", post(criar_tipo_handler))
+        .route("/tipos/:id", delete(deletar_tipo_handler))
         
         // Produtos
-        .route("/api/produtos/tipo/:tipo_id", get(listar_produtos_handler))
-        .route("/api/produtos", post(criar_produto_handler))
-        .route("/api/produtos/:id", delete(deletar_produto_handler))
+        .route("/produtos/tipo/:tipo_id", get(listar_produtos_handler))
+        .route("/produtos", post(criar_produto_handler))
+        .route("/produtos/:id", delete(deletar_produto_handler))
+        .route("/produtos/:id/precos", get(precos::listar_precos_handler))
+        .route("/produtos/:id/precos", post(precos::definir_preco_handler))
+        .route("/produtos/:id/minimos", get(reposicao::obter_minimo_handler))
+        .route("/produtos/:id/minimos", post(reposicao::definir_minimo_handler))
         
         // Lotes
-        .route("/api/lotes/produto/:produto_id", get(listar_lotes_handler))
-        .route("/api/lotes", post(criar_lote_handler))
-        .route("/api/lotes/:id", delete(deletar_lote_handler))
+        .route("/lotes/produto/:produto_id", get(listar_lotes_handler))
+        .route("/lotes", post(criar_lote_handler))
+        .route("/lotes/:id", delete(deletar_lote_handler))
         
         // Negócio
-        .route("/api/vender/:id", post(vender_lote_handler))
-        .route("/api/abastecer/:id", post(abastecer_handler))
+        .route("/vender/:id", post(vender_lote_handler))
+        .route("/abastecer/:id", post(abastecer_handler))
+        
+        // Baixas (quebras/perdas)
+        .route("/lotes/:id/baixa", post(baixas::registrar_baixa_handler))
+        .route("/baixas", get(baixas::listar_baixas_handler))
+        .route("/baixas/resumo", get(baixas::resumo_baixas_handler))
+        
+        // Rebaixas
+        .route("/rebaixas", get(rebaixas::listar_rebaixas_handler))
+        .route("/rebaixas/sugestoes", get(rebaixas::sugestoes_rebaixa_handler))
+        .route("/rebaixas/regras", get(rebaixas::listar_regras_handler))
+        .route("/rebaixas/regras", post(rebaixas::criar_regra_handler))
+        .route("/rebaixas/regras/:id", delete(rebaixas::deletar_regra_handler))
+        .route("/rebaixas/limites", get(rebaixas::listar_limites_handler))
+        .route("/rebaixas/limites", post(rebaixas::definir_limite_handler))
+        .route("/lotes/:id/rebaixa", post(rebaixas::aceitar_rebaixa_handler))
+        
+        // Reposição
+        .route("/alertas/estoque", get(reposicao::alertas_estoque_handler))
+        
+        // Previsão de vendas
+        .route("/previsao/vendas", get(previsao::velocidade_vendas_handler))
+        .route("/previsao/encalhe", get(previsao::nao_vendera_a_tempo_handler))
         
         // Pesquisa
-        .route("/api/pesquisar", get(pesquisar_handler))
+        .route("/pesquisar", get(pesquisa::pesquisar_handler))
         
         // Relatório
-        .route("/api/relatorio", get(relatorio_handler))
+        .route("/relatorio", get(relatorio::relatorio_handler))
+        .route("/relatorio.pdf", get(pdf::relatorio_pdf_handler))
+        .route("/relatorio/faixas", get(faixas_validade::faixas_validade_handler))
+        
+        // Histórico
+        .route("/historico", get(historico::historico_handler))
+        .route("/historico/snapshot", post(historico::snapshot_handler))
         
         // CSV
-        .route("/api/exportar", get(exportar_csv_handler))
-        .route("/api/importar", post(importar_csv_handler))
+        .route("/exportar", get(exportar_csv_handler))
+        .route("/exportar.xlsx", get(planilha::exportar_xlsx_handler))
+        // Planilhas passam fácil do limite padrão de 2 MB do axum
+        .merge(
+            Router::new()
+                .route("/importar", post(importar_handler))
+                .layer(DefaultBodyLimit::max(config::atual().limite_envio_bytes()))
+        )
+        
+        // Backup: leva e substitui o estoque inteiro, só para admins logados
+        .merge(
+            Router::new()
+                .route("/backup", get(backup::gerar_backup_handler))
+                .route("/backup/restaurar", post(backup::restaurar_backup_handler))
+                .layer(DefaultBodyLimit::max(config::atual().limite_envio_bytes()))
+                .route_layer(middleware::from_fn(usuarios::exigir_admin))
+        )
+        
+        // Cópias dos bancos: os arquivos têm os hashes das senhas, só para admins logados
+        .merge(
+            Router::new()
+                .route("/admin/copias", get(copias::listar_copias_handler))
+                .route("/admin/copias", post(copias::criar_copia_handler))
+                .route("/admin/copias/:data/:banco", get(copias::baixar_copia_handler))
+                .route_layer(middleware::from_fn(usuarios::exigir_admin))
+        )
         
         // Validade
-        .route("/api/vencer/:dias", get(produtos_a_vencer_handler))
+        .route("/vencer/:dias", get(produtos_a_vencer_handler))
         
+        // Atualizações ao vivo e sincronização dos aparelhos
+        .route("/eventos", get(eventos::eventos_handler))
+        .route("/sincronizar", post(sincronizacao::sincronizar_handler))
+        
+        .layer(middleware::from_fn(idempotencia::idempotencia))
+}
+
+/// Marca as respostas dos caminhos antigos (`/api/...`) para quem integra
+/// saber que deve migrar para `/api/v1`.
+async fn marcar_obsoleta(mut resposta: Response) -> Response {
+    resposta.headers_mut().insert("deprecation", HeaderValue::from_static("true"));
+    resposta
+}
+
+// ===========================================
+// MAIN
+// ===========================================
+
+#[tokio::main]
+async fn main() {
+    let argumentos = config::Argumentos::parse();
+    let config = match config::Config::carregar(&argumentos) {
+        Ok(config) => config,
+        Err(erros) => {
+            eprintln!("❌ Configuração inválida:");
+            for erro in erros {
+                eprintln!("   - {}", erro);
+            }
+            std::process::exit(1);
+        }
+    };
+    config::definir(config.clone());
+
+    let _guarda_log = registro::iniciar(&config);
+
+    let cors = if config.cors_origens.iter().any(|origem| origem == "*") {
+        CorsLayer::permissive()
+    } else {
+        let origens: Vec<HeaderValue> = config.cors_origens.iter()
+            .filter_map(|origem| origem.parse().ok())
+            .collect();
+        CorsLayer::new().allow_origin(origens).allow_methods(Any).allow_headers(Any)
+    };
+
+    let mut app = Router::new()
+        .nest(openapi::PREFIXO_API, rotas_api())
+        .nest("/api", rotas_api().layer(middleware::map_response(marcar_obsoleta)))
+        .route("/api/openapi.json", get(openapi::especificacao_handler));
+    if config.documentacao {
+        app = app.route("/api/docs", get(openapi::documentacao_handler));
+    }
+
+    let app = app
         // Arquivos estáticos
-        .fallback_service(ServeDir::new("dist"))
-        .layer(CorsLayer::permissive());
+        .fallback_service(ServeDir::new(&config.estaticos))
+        .layer(cors)
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(registro::span_requisicao)
+                .on_response(DefaultOnResponse::new().level(tracing::Level::INFO)),
+        )
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid));
+
+    let conn = init_db().expect("Erro ao abrir o banco de dados");
+    let admin = argumentos.admin_usuario.clone().zip(argumentos.admin_senha.clone());
+    if let Err(e) = usuarios::preparar_usuarios(&conn, admin) {
+        tracing::error!("❌ Erro ao preparar usuários: {}", e);
+    }
+    drop(conn);
+
+    historico::iniciar_agendador();
+    copias::iniciar_agendador();
+
+    let addr: SocketAddr = config.endereco.parse().expect("endereço já validado");
+
+    if config.tls.ativo {
+        let (certificado, chave) = tls::arquivos_certificado(&config).unwrap_or_else(|erro| {
+            tracing::error!("❌ TLS: {}", erro);
+            std::process::exit(1);
+        });
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let rustls = RustlsConfig::from_pem_file(&certificado, &chave).await.unwrap_or_else(|erro| {
+            tracing::error!("❌ TLS: não foi possível carregar {} e {}: {}", certificado.display(), chave.display(), erro);
+            std::process::exit(1);
+        });
+
+        let addr_https: SocketAddr = config.tls.endereco.parse().expect("endereço já validado");
+        tokio::spawn(tls::servir_redirecionamento(addr, addr_https.port()));
+
+        tracing::info!("🚀 Servidor completo rodando em https://{}", addr_https);
+        axum_server::bind_rustls(addr_https, rustls)
+            .serve(app.into_make_service())
+            .await
+            .unwrap();
+    } else {
+        tracing::info!("🚀 Servidor completo rodando em http://{}", addr);
+
+        let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+        axum::serve(listener, app).await.unwrap();
+    }
+}
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
-    println!("🚀 Servidor completo rodando em http://{}", addr);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    #[test]
+    fn tabelas_antigas_deixam_de_reaproveitar_ids() {
+        let conn = Connection::open_in_memory().unwrap();
+        // Schema de antes do AUTOINCREMENT, com a baixa de um produto já apagado
+        conn.execute_batch(
+            "CREATE TABLE secoes (id INTEGER PRIMARY KEY, nome TEXT NOT NULL UNIQUE);
+             CREATE TABLE tipos (id INTEGER PRIMARY KEY, nome TEXT NOT NULL, id_secao INTEGER NOT NULL,
+                 FOREIGN KEY (id_secao) REFERENCES secoes(id) ON DELETE CASCADE, UNIQUE(nome, id_secao));
+             CREATE TABLE produtos (id INTEGER PRIMARY KEY, nome TEXT NOT NULL, id_tipo INTEGER NOT NULL,
+                 FOREIGN KEY (id_tipo) REFERENCES tipos(id) ON DELETE CASCADE, UNIQUE(nome, id_tipo));
+             CREATE TABLE lotes (id INTEGER PRIMARY KEY, id_produto INTEGER NOT NULL, validade DATE NOT NULL,
+                 quantidade_total INTEGER NOT NULL, quantidade_prateleira INTEGER NOT NULL DEFAULT 0,
+                 FOREIGN KEY (id_produto) REFERENCES produtos(id) ON DELETE CASCADE);
+             CREATE TABLE baixas (id INTEGER PRIMARY KEY, id_lote INTEGER NOT NULL, id_produto INTEGER NOT NULL,
+                 motivo TEXT NOT NULL, quantidade INTEGER NOT NULL, observacao TEXT,
+                 data TEXT NOT NULL DEFAULT (datetime('now', 'localtime')));
+             CREATE TABLE vendas (id INTEGER PRIMARY KEY, id_lote INTEGER NOT NULL, id_produto INTEGER NOT NULL,
+                 quantidade INTEGER NOT NULL, data TEXT NOT NULL DEFAULT (datetime('now', 'localtime')));
+             CREATE TABLE historico_estoque (data TEXT NOT NULL, id_produto INTEGER NOT NULL, id_tipo INTEGER NOT NULL,
+                 id_secao INTEGER NOT NULL, total INTEGER NOT NULL, prateleira INTEGER NOT NULL,
+                 vencendo INTEGER NOT NULL, vencido INTEGER NOT NULL, PRIMARY KEY (data, id_produto));
+             INSERT INTO secoes VALUES (1, 'Mercearia');
+             INSERT INTO tipos VALUES (1, 'Grãos', 1);
+             INSERT INTO produtos VALUES (1, 'Arroz', 1), (2, 'Feijão', 1);
+             INSERT INTO lotes VALUES (1, 1, '2030-01-01', 10, 2), (2, 2, '2030-02-01', 5, 0);
+             INSERT INTO baixas (id_lote, id_produto, motivo, quantidade) VALUES (3, 3, 'vencido', 1);
+             INSERT INTO vendas (id_lote, id_produto, quantidade) VALUES (5, 4, 1);
+             INSERT INTO historico_estoque VALUES ('2026-01-01', 6, 3, 2, 1, 0, 0, 0);"
+        ).unwrap();
+
+        criar_schema(&conn).unwrap();
+
+        let sql: String = conn.query_row("SELECT sql FROM sqlite_master WHERE name = 'produtos'", [], |row| row.get(0)).unwrap();
+        assert!(sql.contains("AUTOINCREMENT"));
+        let nomes: Vec<String> = conn.prepare("SELECT nome FROM produtos ORDER BY id").unwrap()
+            .query_map([], |row| row.get(0)).unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(nomes, ["Arroz", "Feijão"]);
+
+        // O lote continua ligado ao produto, e apagar o produto ainda apaga o lote
+        let lotes_do_arroz: i32 = conn.query_row("SELECT COUNT(*) FROM lotes WHERE id_produto = 1", [], |row| row.get(0)).unwrap();
+        assert_eq!(lotes_do_arroz, 1);
+        conn.execute("DELETE FROM produtos WHERE id = 2", []).unwrap();
+        let lotes: i32 = conn.query_row("SELECT COUNT(*) FROM lotes", [], |row| row.get(0)).unwrap();
+        assert_eq!(lotes, 1);
+
+        // Nem o id apagado nem os ids guardados em baixas, vendas e histórico voltam a ser usados
+        let nova_secao: i32 = conn.query_row("INSERT INTO secoes (nome) VALUES ('Bebidas') RETURNING id", [], |row| row.get(0)).unwrap();
+        assert_eq!(nova_secao, 3);
+        let novo_tipo: i32 = conn.query_row("INSERT INTO tipos (nome, id_secao) VALUES ('Sucos', 3) RETURNING id", [], |row| row.get(0)).unwrap();
+        assert_eq!(novo_tipo, 4);
+        let novo: i32 = conn.query_row("INSERT INTO produtos (nome, id_tipo) VALUES ('Milho', 1) RETURNING id", [], |row| row.get(0)).unwrap();
+        assert_eq!(novo, 7);
+        let novo_lote: i32 = conn.query_row(
+            "INSERT INTO lotes (id_produto, validade, quantidade_total) VALUES (7, '2030-03-01', 1) RETURNING id", [], |row| row.get(0)
+        ).unwrap();
+        assert_eq!(novo_lote, 6);
+    }
+
+    #[test]
+    fn importar_mantem_precos_minimos_e_rebaixas() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        criar_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO secoes (id, nome) VALUES (1, 'Mercearia'), (2, 'Padaria');
+             INSERT INTO tipos (id, nome, id_secao) VALUES (1, 'Grãos', 1), (2, 'Pães', 2);
+             INSERT INTO produtos (id, nome, id_tipo, codigo_barras) VALUES (1, 'ARROZ', 1, '789'), (2, 'FRANCÊS', 2, NULL);
+             INSERT INTO lotes (id, id_produto, validade, quantidade_total, quantidade_prateleira, codigo)
+                 VALUES (1, 1, '2030-01-01', 10, 2, 'L1'), (2, 1, '2030-06-01', 4, 0, NULL), (3, 2, '2030-01-01', 9, 9, NULL);
+             INSERT INTO precos (id_produto, custo_centavos, preco_venda_centavos) VALUES (1, 400, 699), (2, 50, 90);
+             INSERT INTO estoque_minimo VALUES (1, 5, 20, NULL);
+             INSERT INTO limites_rebaixa VALUES (1, 30);
+             INSERT INTO rebaixas (id_lote, desconto_percentual, preco_original_centavos, preco_centavos) VALUES (1, 20, 699, 559);"
+        ).unwrap();
+
+        let linhas: Vec<Vec<String>> = [
+            "Mercearia,Grãos,arroz,2030-01-01,8,1",
+            "Mercearia,Grãos,arroz,2030-02-01,5,0",
+            "Bebidas,Sucos,uva,2030-03-01,3,3",
+            "Mercearia,Grãos,feijão,31/12/2030,2,0",
+            "incompleta",
+        ].iter().map(|linha| linha.split(',').map(str::to_string).collect()).collect();
+
+        let tx = conn.transaction().unwrap();
+        assert_eq!(importar_linhas(&tx, &linhas).unwrap(), (3, 2));
+        tx.commit().unwrap();
+
+        let contar = |sql: &str| -> i64 { conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+        // O arroz continua o mesmo produto, com preço, mínimo e código de barras
+        assert_eq!(contar("SELECT id FROM produtos WHERE nome = 'ARROZ' AND codigo_barras = '789'"), 1);
+        assert_eq!(contar("SELECT COUNT(*) FROM precos WHERE id_produto = 1"), 1);
+        assert_eq!(contar("SELECT minimo_total FROM estoque_minimo WHERE id_produto = 1"), 20);
+        assert_eq!(contar("SELECT desconto_maximo FROM limites_rebaixa WHERE id_secao = 1"), 30);
+        // O lote de mesma validade recebe as quantidades novas e mantém código e rebaixa
+        assert_eq!(contar("SELECT quantidade_total * 100 + quantidade_prateleira FROM lotes WHERE id = 1 AND codigo = 'L1'"), 801);
+        assert_eq!(contar("SELECT COUNT(*) FROM rebaixas WHERE id_lote = 1"), 1);
+        // Fora da planilha: o lote de junho, a padaria e o preço do pão
+        assert_eq!(contar("SELECT COUNT(*) FROM lotes WHERE id IN (2, 3)"), 0);
+        assert_eq!(contar("SELECT COUNT(*) FROM secoes WHERE nome = 'Padaria'"), 0);
+        assert_eq!(contar("SELECT COUNT(*) FROM precos WHERE id_produto = 2"), 0);
+        // E o que é novo foi criado
+        assert_eq!(contar("SELECT COUNT(*) FROM lotes"), 3);
+        assert_eq!(contar("SELECT COUNT(*) FROM produtos WHERE nome = 'UVA'"), 1);
+    }
 }
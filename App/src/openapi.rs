@@ -0,0 +1,246 @@
+use axum::response::{Html, Json};
+use utoipa::openapi::{
+    path::{ParameterBuilder, ParameterIn},
+    response::ResponseBuilder,
+    ContentBuilder, ObjectBuilder, OpenApi as EspecificacaoOpenApi, Ref, Required, Type,
+};
+use utoipa::{Modify, OpenApi};
+use utoipa_scalar::Scalar;
+
+use crate::erro::CorpoErro;
+use crate::{
+    backup, baixas, copias, eventos, faixas_validade, historico, pdf, pesquisa, planilha, precos, previsao,
+    rebaixas, relatorio, reposicao, sincronizacao,
+};
+
+/// Prefixo atual da API. `/api` sem versão continua respondendo, como apelido obsoleto.
+pub const PREFIXO_API: &str = "/api/v1";
+
+// ===========================================
+// ESPECIFICAÇÃO
+// ===========================================
+
+/// Especificação OpenAPI 3 gerada a partir dos handlers e das estruturas de dados.
+/// Toda rota de `rotas_api` precisa estar em `paths` (o teste abaixo confere).
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Controle de validade",
+        description = "Seções, tipos, produtos e lotes com validade, vendas, baixas, rebaixas e relatórios. \
+                       Os corpos de escrita podem ir em JSON ou como formulário."
+    ),
+    servers((url = "/api/v1")),
+    paths(
+        crate::login_handler,
+        crate::listar_secoes_handler,
+        crate::criar_secao_handler,
+        crate::deletar_secao_handler,
+        crate::listar_tipos_handler,
+        crate::criar_tipo_handler,
+        crate::deletar_tipo_handler,
+        crate::listar_produtos_handler,
+        crate::criar_produto_handler,
+        crate::deletar_produto_handler,
+        precos::listar_precos_handler,
+        precos::definir_preco_handler,
+        reposicao::obter_minimo_handler,
+        reposicao::definir_minimo_handler,
+        crate::listar_lotes_handler,
+        crate::criar_lote_handler,
+        crate::deletar_lote_handler,
+        crate::vender_lote_handler,
+        crate::abastecer_handler,
+        baixas::registrar_baixa_handler,
+        baixas::listar_baixas_handler,
+        baixas::resumo_baixas_handler,
+        rebaixas::listar_rebaixas_handler,
+        rebaixas::sugestoes_rebaixa_handler,
+        rebaixas::listar_regras_handler,
+        rebaixas::criar_regra_handler,
+        rebaixas::deletar_regra_handler,
+        rebaixas::listar_limites_handler,
+        rebaixas::definir_limite_handler,
+        rebaixas::aceitar_rebaixa_handler,
+        reposicao::alertas_estoque_handler,
+        previsao::velocidade_vendas_handler,
+        previsao::nao_vendera_a_tempo_handler,
+        pesquisa::pesquisar_handler,
+        relatorio::relatorio_handler,
+        pdf::relatorio_pdf_handler,
+        faixas_validade::faixas_validade_handler,
+        historico::historico_handler,
+        historico::snapshot_handler,
+        crate::exportar_csv_handler,
+        planilha::exportar_xlsx_handler,
+        crate::importar_handler,
+        backup::gerar_backup_handler,
+        backup::restaurar_backup_handler,
+        copias::listar_copias_handler,
+        copias::criar_copia_handler,
+        copias::baixar_copia_handler,
+        crate::produtos_a_vencer_handler,
+        eventos::eventos_handler,
+        sincronizacao::sincronizar_handler,
+    ),
+    components(schemas(CorpoErro)),
+    modifiers(&RespostaDeErro, &ChaveIdempotencia),
+)]
+pub struct DocumentacaoApi;
+
+/// Todo handler pode falhar com `ErroApp`; em vez de repetir isso em cada
+/// `#[utoipa::path]`, a resposta `default` é acrescentada aqui.
+struct RespostaDeErro;
+
+impl Modify for RespostaDeErro {
+    fn modify(&self, especificacao: &mut EspecificacaoOpenApi) {
+        let erro = ResponseBuilder::new()
+            .description("Erro com `codigo` estável e `mensagem` para mostrar ao usuário")
+            .content(
+                "application/json",
+                ContentBuilder::new().schema(Some(Ref::from_schema_name("CorpoErro"))).build(),
+            )
+            .build();
+
+        for item in especificacao.paths.paths.values_mut() {
+            let operacoes = [&mut item.get, &mut item.post, &mut item.put, &mut item.patch, &mut item.delete];
+            for operacao in operacoes.into_iter().flatten() {
+                operacao.responses.responses.entry("default".to_string()).or_insert_with(|| erro.clone().into());
+            }
+        }
+    }
+}
+
+/// O middleware `idempotencia` vale para toda escrita; o cabeçalho é
+/// documentado aqui em vez de em cada handler.
+struct ChaveIdempotencia;
+
+impl Modify for ChaveIdempotencia {
+    fn modify(&self, especificacao: &mut EspecificacaoOpenApi) {
+        let cabecalho = ParameterBuilder::new()
+            .name("Idempotency-Key")
+            .parameter_in(ParameterIn::Header)
+            .required(Required::False)
+            .description(Some(
+                "Uma por ação do usuário. Repetir a chave devolve a resposta da primeira vez \
+                 (com `Idempotent-Replayed: true`), sem aplicar a escrita de novo",
+            ))
+            .schema(Some(ObjectBuilder::new().schema_type(Type::String).max_length(Some(255))))
+            .build();
+
+        for item in especificacao.paths.paths.values_mut() {
+            let escritas = [&mut item.post, &mut item.put, &mut item.patch, &mut item.delete];
+            for operacao in escritas.into_iter().flatten() {
+                operacao.parameters.get_or_insert_with(Vec::new).push(cabecalho.clone());
+            }
+        }
+    }
+}
+
+// ===========================================
+// HANDLERS
+// ===========================================
+
+pub async fn especificacao_handler() -> Json<EspecificacaoOpenApi> {
+    Json(DocumentacaoApi::openapi())
+}
+
+/// Página de documentação navegável (Scalar). O script vem de CDN, então
+/// precisa de internet no navegador; a especificação em si não.
+pub async fn documentacao_handler() -> Html<String> {
+    Html(Scalar::new(DocumentacaoApi::openapi()).to_html())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::idempotencia;
+    use std::collections::BTreeSet;
+
+    /// Rotas registradas em `rotas_api` (main.rs), como "MÉTODO /caminho/{param}".
+    fn rotas_do_roteador() -> BTreeSet<String> {
+        let fonte = include_str!("main.rs");
+        let inicio = fonte.find("fn rotas_api()").expect("rotas_api não encontrada em main.rs");
+        let fim = inicio + fonte[inicio..].find("\n}\n").unwrap();
+
+        let mut rotas = BTreeSet::new();
+        for linha in fonte[inicio..fim].lines() {
+            let Some(resto) = linha.trim().strip_prefix(".route(\"") else { continue };
+            let (caminho, handlers) = resto.split_once('"').unwrap();
+            let caminho = caminho.split('/')
+                .map(|parte| match parte.strip_prefix(':') {
+                    Some(param) => format!("{{{}}}", param),
+                    None => parte.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+
+            // `get(a)`, `post(b)` ou encadeados, como `get(a).post(b)`
+            for chamada in handlers.trim_start_matches(',').split(").") {
+                let metodo = chamada.trim().split('(').next().unwrap();
+                rotas.insert(format!("{} {}", metodo.to_uppercase(), caminho));
+            }
+        }
+        rotas
+    }
+
+    fn rotas_da_especificacao() -> BTreeSet<String> {
+        let mut rotas = BTreeSet::new();
+        for (caminho, item) in DocumentacaoApi::openapi().paths.paths {
+            let metodos = [
+                ("GET", &item.get),
+                ("POST", &item.post),
+                ("PUT", &item.put),
+                ("PATCH", &item.patch),
+                ("DELETE", &item.delete),
+            ];
+            for (metodo, operacao) in metodos {
+                if operacao.is_some() {
+                    rotas.insert(format!("{} {}", metodo, caminho));
+                }
+            }
+        }
+        rotas
+    }
+
+    #[test]
+    fn toda_rota_esta_na_especificacao() {
+        let roteador = rotas_do_roteador();
+        let especificacao = rotas_da_especificacao();
+        assert!(roteador.len() > 40, "o teste não achou as rotas em main.rs: {:?}", roteador);
+
+        let faltando: Vec<_> = roteador.difference(&especificacao).collect();
+        assert!(faltando.is_empty(), "rotas sem #[utoipa::path] em DocumentacaoApi: {:?}", faltando);
+
+        let sobrando: Vec<_> = especificacao.difference(&roteador).collect();
+        assert!(sobrando.is_empty(), "rotas na especificação que não existem no roteador: {:?}", sobrando);
+    }
+
+    #[test]
+    fn toda_operacao_documenta_o_erro() {
+        let json = serde_json::to_value(DocumentacaoApi::openapi()).unwrap();
+        for (caminho, item) in json["paths"].as_object().unwrap() {
+            for (metodo, operacao) in item.as_object().unwrap() {
+                assert_eq!(
+                    operacao["responses"]["default"]["content"]["application/json"]["schema"]["$ref"],
+                    "#/components/schemas/CorpoErro",
+                    "{} {}", metodo, caminho
+                );
+            }
+        }
+        assert!(json["components"]["schemas"]["RelatorioItem"].is_object());
+    }
+
+    #[test]
+    fn escritas_aceitam_chave_de_idempotencia() {
+        let json = serde_json::to_value(DocumentacaoApi::openapi()).unwrap();
+        for (caminho, item) in json["paths"].as_object().unwrap() {
+            for (metodo, operacao) in item.as_object().unwrap() {
+                let cabecalhos: Vec<_> = operacao["parameters"].as_array().into_iter().flatten()
+                    .filter(|parametro| parametro["in"] == "header")
+                    .map(|parametro| parametro["name"].as_str().unwrap().to_lowercase())
+                    .collect();
+                assert_eq!(cabecalhos.contains(&idempotencia::CABECALHO.to_string()), metodo != "get", "{} {}", metodo, caminho);
+            }
+        }
+    }
+}
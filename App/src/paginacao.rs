@@ -0,0 +1,172 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::erro::ErroApp;
+
+/// Itens por página quando a requisição não informa `limite`.
+pub const LIMITE_PADRAO: u32 = 100;
+/// Maior `limite` aceito; listas maiores precisam ser lidas em páginas.
+pub const LIMITE_MAXIMO: u32 = 500;
+
+// ===========================================
+// ESTRUTURAS DE DADOS
+// ===========================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Ordem {
+    Nome,
+    /// Validade do lote; em produtos, a mais próxima entre os lotes com unidades
+    Validade,
+    /// Quantidade total do lote; em produtos, a soma dos lotes
+    Quantidade,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Direcao {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Paginação, ordenação e filtros comuns às listas de produtos e de lotes.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ParametrosLista {
+    /// Itens por página (padrão 100, máximo 500)
+    pub limite: Option<u32>,
+    /// Quantos itens pular
+    pub deslocamento: Option<u32>,
+    pub ordem: Option<Ordem>,
+    pub direcao: Option<Direcao>,
+    /// Só lotes com validade a partir desta data (AAAA-MM-DD, inclusiva)
+    pub validade_de: Option<String>,
+    /// Só lotes com validade até esta data (AAAA-MM-DD, inclusiva)
+    pub validade_ate: Option<String>,
+    /// `true`: só com unidades na prateleira; `false`: só sem
+    pub com_prateleira: Option<bool>,
+    /// Só itens desta seção
+    pub secao: Option<i32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FiltroLista {
+    pub limite: u32,
+    pub deslocamento: u32,
+    pub ordem: Option<Ordem>,
+    pub direcao: Direcao,
+    pub validade_de: Option<String>,
+    pub validade_ate: Option<String>,
+    pub com_prateleira: Option<bool>,
+    pub secao: Option<i32>,
+}
+
+/// Uma página da lista. `total` conta todos os itens que passam nos filtros,
+/// não só os desta página.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Pagina<T> {
+    pub itens: Vec<T>,
+    pub total: i64,
+    pub limite: u32,
+    pub deslocamento: u32,
+}
+
+// ===========================================
+// VALIDAÇÃO
+// ===========================================
+
+impl ParametrosLista {
+    /// Valida limite e datas e aplica os padrões.
+    pub fn filtro(self) -> Result<FiltroLista, ErroApp> {
+        let limite = self.limite.unwrap_or(LIMITE_PADRAO);
+        if limite == 0 || limite > LIMITE_MAXIMO {
+            return Err(ErroApp::invalido(
+                "limite_invalido",
+                format!("O limite deve estar entre 1 e {}", LIMITE_MAXIMO),
+            ));
+        }
+
+        let data_valida = |data: &Option<String>| {
+            data.as_deref().is_none_or(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").is_ok())
+        };
+        if !data_valida(&self.validade_de) || !data_valida(&self.validade_ate) {
+            return Err(ErroApp::invalido("data_invalida", "As datas devem estar no formato AAAA-MM-DD"));
+        }
+
+        Ok(FiltroLista {
+            limite,
+            deslocamento: self.deslocamento.unwrap_or(0),
+            ordem: self.ordem,
+            direcao: self.direcao.unwrap_or_default(),
+            validade_de: self.validade_de,
+            validade_ate: self.validade_ate,
+            com_prateleira: self.com_prateleira,
+            secao: self.secao,
+        })
+    }
+}
+
+impl FiltroLista {
+    /// Cláusula ORDER BY. `coluna` traduz a ordem pedida (ou `padrao`) para a
+    /// expressão SQL; `desempate` mantém a ordem estável entre páginas.
+    pub fn ordenacao(&self, padrao: Ordem, coluna: impl Fn(Ordem) -> &'static str, desempate: &str) -> String {
+        let direcao = match self.direcao {
+            Direcao::Asc => "ASC",
+            Direcao::Desc => "DESC",
+        };
+        // Sem validade (produto sem lotes com unidades) fica sempre no fim
+        format!(
+            "ORDER BY ({0}) IS NULL, {0} {1}, {2}",
+            coluna(self.ordem.unwrap_or(padrao)),
+            direcao,
+            desempate
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn padroes_e_limites() {
+        let filtro = ParametrosLista::default().filtro().unwrap();
+        assert_eq!((filtro.limite, filtro.deslocamento, filtro.direcao), (LIMITE_PADRAO, 0, Direcao::Asc));
+
+        let acima = ParametrosLista { limite: Some(LIMITE_MAXIMO + 1), ..Default::default() };
+        assert_eq!(acima.filtro().unwrap_err().codigo(), "limite_invalido");
+
+        let zero = ParametrosLista { limite: Some(0), ..Default::default() };
+        assert_eq!(zero.filtro().unwrap_err().codigo(), "limite_invalido");
+
+        let data = ParametrosLista { validade_ate: Some("31/12/2026".to_string()), ..Default::default() };
+        assert_eq!(data.filtro().unwrap_err().codigo(), "data_invalida");
+    }
+
+    #[test]
+    fn ordenacao_usa_o_padrao_e_desempata() {
+        let coluna = |ordem| match ordem {
+            Ordem::Nome => "p.nome",
+            Ordem::Validade => "l.validade",
+            Ordem::Quantidade => "l.quantidade_total",
+        };
+
+        let padrao = ParametrosLista::default().filtro().unwrap();
+        assert_eq!(
+            padrao.ordenacao(Ordem::Validade, coluna, "l.id"),
+            "ORDER BY (l.validade) IS NULL, l.validade ASC, l.id"
+        );
+
+        let pedida = ParametrosLista {
+            ordem: Some(Ordem::Quantidade),
+            direcao: Some(Direcao::Desc),
+            ..Default::default()
+        };
+        assert_eq!(
+            pedida.filtro().unwrap().ordenacao(Ordem::Validade, coluna, "l.id"),
+            "ORDER BY (l.quantidade_total) IS NULL, l.quantidade_total DESC, l.id"
+        );
+    }
+}
@@ -0,0 +1,405 @@
+use axum::{
+    http::header,
+    response::IntoResponse,
+};
+use chrono::{Local, NaiveDate};
+use pdf_writer::{Content, Finish, Name, Pdf, Rect, Ref, Str};
+use rusqlite::Connection;
+
+use crate::erro::{ErroApp, Query};
+use crate::init_db;
+use crate::precos::formatar_centavos;
+use crate::relatorio::{montar_relatorio, ParametrosRelatorio, RelatorioItem};
+
+// Página A4 em pontos
+const LARGURA_PAGINA: f32 = 595.0;
+const ALTURA_PAGINA: f32 = 842.0;
+const MARGEM: f32 = 36.0;
+const ALTURA_LINHA: f32 = 15.0;
+const TAMANHO_TEXTO: f32 = 9.0;
+const LADO_CAIXA: f32 = 8.0;
+
+const FONTE: Name = Name(b"F1");
+const FONTE_NEGRITO: Name = Name(b"F2");
+
+// ===========================================
+// DOCUMENTO EM TABELA
+// ===========================================
+
+pub struct Coluna {
+    pub titulo: &'static str,
+    pub largura: f32,
+    /// Alinha o texto à direita (números)
+    pub direita: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Estilo {
+    /// Faixa cinza de agrupamento; nunca fica sozinha no fim da página
+    Grupo,
+    Negrito,
+    Normal,
+}
+
+struct Linha {
+    celulas: Vec<String>,
+    estilo: Estilo,
+    /// Recuo da primeira coluna de texto, para mostrar a hierarquia
+    recuo: f32,
+    /// Desenha a caixinha de conferência na primeira coluna
+    caixa: bool,
+}
+
+/// Tabela paginada com cabeçalho, coluna de conferência e "Página X de Y".
+///
+/// A primeira coluna é sempre a da caixinha; as células de cada linha
+/// preenchem as demais colunas na ordem.
+pub struct DocumentoPdf {
+    titulo: String,
+    subtitulo: String,
+    colunas: Vec<Coluna>,
+    linhas: Vec<Linha>,
+}
+
+impl DocumentoPdf {
+    pub fn novo(titulo: &str, subtitulo: &str, colunas: Vec<Coluna>) -> Self {
+        let mut todas = vec![Coluna { titulo: "OK", largura: 22.0, direita: false }];
+        todas.extend(colunas);
+        DocumentoPdf {
+            titulo: titulo.to_string(),
+            subtitulo: subtitulo.to_string(),
+            colunas: todas,
+            linhas: Vec::new(),
+        }
+    }
+
+    pub fn grupo(&mut self, celulas: Vec<String>) {
+        self.linhas.push(Linha { celulas, estilo: Estilo::Grupo, recuo: 0.0, caixa: false });
+    }
+
+    pub fn subtotal(&mut self, celulas: Vec<String>, recuo: f32) {
+        self.linhas.push(Linha { celulas, estilo: Estilo::Negrito, recuo, caixa: false });
+    }
+
+    pub fn item(&mut self, celulas: Vec<String>, recuo: f32) {
+        self.linhas.push(Linha { celulas, estilo: Estilo::Normal, recuo, caixa: true });
+    }
+
+    /// Distribui as linhas em páginas, sem deixar um grupo sem nenhuma linha embaixo.
+    fn paginar(&self) -> Vec<&[Linha]> {
+        let topo = ALTURA_PAGINA - MARGEM - 3.5 * ALTURA_LINHA;
+        let por_pagina = (((topo - MARGEM - ALTURA_LINHA) / ALTURA_LINHA) as usize).max(2);
+
+        let mut paginas = Vec::new();
+        let mut inicio = 0;
+        while inicio < self.linhas.len() {
+            let mut fim = (inicio + por_pagina).min(self.linhas.len());
+            if fim < self.linhas.len() && fim - inicio > 1 && self.linhas[fim - 1].estilo == Estilo::Grupo {
+                fim -= 1;
+            }
+            paginas.push(&self.linhas[inicio..fim]);
+            inicio = fim;
+        }
+        if paginas.is_empty() {
+            paginas.push(&self.linhas[..]);
+        }
+        paginas
+    }
+
+    pub fn gerar(&self) -> Vec<u8> {
+        let catalogo = Ref::new(1);
+        let arvore = Ref::new(2);
+        let fonte = Ref::new(3);
+        let fonte_negrito = Ref::new(4);
+
+        let paginas = self.paginar();
+        let total = paginas.len();
+        let ids: Vec<(Ref, Ref)> = (0..total as i32)
+            .map(|i| (Ref::new(5 + 2 * i), Ref::new(6 + 2 * i)))
+            .collect();
+
+        let mut pdf = Pdf::new();
+        pdf.catalog(catalogo).pages(arvore);
+        pdf.pages(arvore).kids(ids.iter().map(|&(pagina, _)| pagina)).count(total as i32);
+        pdf.type1_font(fonte).base_font(Name(b"Helvetica")).encoding_predefined(Name(b"WinAnsiEncoding"));
+        pdf.type1_font(fonte_negrito).base_font(Name(b"Helvetica-Bold")).encoding_predefined(Name(b"WinAnsiEncoding"));
+
+        for (numero, (linhas, &(id_pagina, id_conteudo))) in paginas.iter().zip(&ids).enumerate() {
+            let mut pagina = pdf.page(id_pagina);
+            pagina.media_box(Rect::new(0.0, 0.0, LARGURA_PAGINA, ALTURA_PAGINA));
+            pagina.parent(arvore);
+            pagina.contents(id_conteudo);
+            let mut recursos = pagina.resources();
+            recursos.fonts().pair(FONTE, fonte).pair(FONTE_NEGRITO, fonte_negrito);
+            recursos.finish();
+            pagina.finish();
+
+            let conteudo = self.desenhar_pagina(linhas, numero + 1, total);
+            pdf.stream(id_conteudo, &conteudo);
+        }
+
+        pdf.finish()
+    }
+
+    fn desenhar_pagina(&self, linhas: &[Linha], numero: usize, total: usize) -> Vec<u8> {
+        let mut c = Content::new();
+        let largura_util = LARGURA_PAGINA - 2.0 * MARGEM;
+        let mut y = ALTURA_PAGINA - MARGEM - 14.0;
+
+        // Cabeçalho
+        texto(&mut c, FONTE_NEGRITO, 14.0, MARGEM, y, &self.titulo);
+        y -= ALTURA_LINHA;
+        texto(&mut c, FONTE, TAMANHO_TEXTO, MARGEM, y, &self.subtitulo);
+        y -= 1.5 * ALTURA_LINHA;
+
+        let titulos: Vec<String> = self.colunas.iter().map(|coluna| coluna.titulo.to_string()).collect();
+        self.desenhar_celulas(&mut c, FONTE_NEGRITO, y, &titulos, 0.0, 0);
+        c.set_line_width(0.8);
+        c.move_to(MARGEM, y - 4.0).line_to(MARGEM + largura_util, y - 4.0).stroke();
+        y -= ALTURA_LINHA;
+
+        for linha in linhas {
+            if linha.estilo == Estilo::Grupo {
+                c.set_fill_gray(0.88);
+                c.rect(MARGEM, y - 4.0, largura_util, ALTURA_LINHA - 1.0).fill_nonzero();
+                c.set_fill_gray(0.0);
+            }
+            if linha.caixa {
+                c.set_line_width(0.6);
+                c.rect(MARGEM + 6.0, y - 1.0, LADO_CAIXA, LADO_CAIXA).stroke();
+            }
+            let fonte = if linha.estilo == Estilo::Normal { FONTE } else { FONTE_NEGRITO };
+            self.desenhar_celulas(&mut c, fonte, y, &linha.celulas, linha.recuo, 1);
+            y -= ALTURA_LINHA;
+        }
+
+        // Rodapé
+        let rodape = format!("Página {} de {}", numero, total);
+        let largura_rodape = largura_texto(&rodape, TAMANHO_TEXTO);
+        texto(&mut c, FONTE, TAMANHO_TEXTO, LARGURA_PAGINA - MARGEM - largura_rodape, MARGEM - 12.0, &rodape);
+
+        c.finish()
+    }
+
+    /// Escreve as células a partir da coluna `primeira`, cortando o que não couber.
+    fn desenhar_celulas(&self, c: &mut Content, fonte: Name, y: f32, celulas: &[String], recuo: f32, primeira: usize) {
+        let mut x = MARGEM + self.colunas[..primeira].iter().map(|coluna| coluna.largura).sum::<f32>();
+        for (i, (coluna, celula)) in self.colunas[primeira..].iter().zip(celulas).enumerate() {
+            let recuo = if i == 0 { recuo } else { 0.0 };
+            let cabe = coluna.largura - recuo - 4.0;
+            let celula = cortar(celula, cabe, TAMANHO_TEXTO);
+            let largura = largura_texto(&celula, TAMANHO_TEXTO);
+            let inicio = if coluna.direita { x + coluna.largura - 4.0 - largura } else { x + recuo };
+            texto(c, fonte, TAMANHO_TEXTO, inicio, y, &celula);
+            x += coluna.largura;
+        }
+    }
+}
+
+fn texto(c: &mut Content, fonte: Name, tamanho: f32, x: f32, y: f32, conteudo: &str) {
+    c.begin_text();
+    c.set_font(fonte, tamanho);
+    c.next_line(x, y);
+    c.show(Str(&win_ansi(conteudo)));
+    c.end_text();
+}
+
+/// As fontes padrão do PDF usam WinAnsi: Latin-1 cobre os acentos do português.
+fn win_ansi(texto: &str) -> Vec<u8> {
+    texto.chars()
+        .map(|c| match c {
+            '…' => 0x85,
+            '–' => 0x96,
+            c if (c as u32) < 0x80 || (0xA0..=0xFF).contains(&(c as u32)) => c as u32 as u8,
+            _ => b'?',
+        })
+        .collect()
+}
+
+/// Largura aproximada em Helvetica: média de meio quadratim por caractere.
+fn largura_texto(texto: &str, tamanho: f32) -> f32 {
+    texto.chars().count() as f32 * tamanho * 0.52
+}
+
+fn cortar(texto: &str, largura: f32, tamanho: f32) -> String {
+    if largura_texto(texto, tamanho) <= largura {
+        return texto.to_string();
+    }
+    let cabem = ((largura / (tamanho * 0.52)) as usize).saturating_sub(1);
+    let mut cortado: String = texto.chars().take(cabem).collect();
+    cortado.push('…');
+    cortado
+}
+
+fn data_br(data: &str) -> String {
+    NaiveDate::parse_from_str(data, "%Y-%m-%d")
+        .map(|d| d.format("%d/%m/%Y").to_string())
+        .unwrap_or_else(|_| data.to_string())
+}
+
+fn gerado_em() -> String {
+    format!("Gerado em {}", Local::now().format("%d/%m/%Y %H:%M"))
+}
+
+fn resposta_pdf(nome_arquivo: &str, conteudo: Vec<u8>) -> impl IntoResponse {
+    (
+        [
+            (header::CONTENT_TYPE, "application/pdf".to_string()),
+            (header::CONTENT_DISPOSITION, format!("inline; filename=\"{}\"", nome_arquivo)),
+        ],
+        conteudo,
+    )
+}
+
+// ===========================================
+// LISTA DE LOTES A VENCER
+// ===========================================
+
+pub fn pdf_a_vencer(conn: &Connection, dias: i32) -> Result<Vec<u8>, rusqlite::Error> {
+    let mut documento = DocumentoPdf::novo(
+        &format!("Lotes a vencer em até {} dias", dias),
+        &gerado_em(),
+        vec![
+            Coluna { titulo: "Produto", largura: 160.0, direita: false },
+            Coluna { titulo: "Tipo", largura: 100.0, direita: false },
+            Coluna { titulo: "Lote", largura: 40.0, direita: true },
+            Coluna { titulo: "Validade", largura: 62.0, direita: true },
+            Coluna { titulo: "Dias", largura: 34.0, direita: true },
+            Coluna { titulo: "Prateleira", largura: 58.0, direita: true },
+            Coluna { titulo: "Estoque", largura: 47.0, direita: true },
+        ],
+    );
+
+    let mut stmt = conn.prepare(
+        "SELECT s.nome, t.nome, p.nome, l.id, l.validade,
+                CAST(julianday(l.validade) - julianday(date('now', 'localtime')) AS INTEGER),
+                l.quantidade_prateleira, l.quantidade_total - l.quantidade_prateleira
+         FROM lotes l
+         JOIN produtos p ON p.id = l.id_produto
+         JOIN tipos t ON t.id = p.id_tipo
+         JOIN secoes s ON s.id = t.id_secao
+         WHERE julianday(l.validade) - julianday('now') <= ?1
+         ORDER BY s.nome, s.id, l.validade, p.nome"
+    )?;
+    let mut linhas = stmt.query([dias])?;
+
+    let mut secao_atual: Option<String> = None;
+    while let Some(row) = linhas.next()? {
+        let secao: String = row.get(0)?;
+        if secao_atual.as_ref() != Some(&secao) {
+            documento.grupo(vec![secao.clone()]);
+            secao_atual = Some(secao);
+        }
+
+        let dias_restantes: i32 = row.get(5)?;
+        documento.item(vec![
+            row.get(2)?,
+            row.get(1)?,
+            row.get::<_, i32>(3)?.to_string(),
+            data_br(&row.get::<_, String>(4)?),
+            if dias_restantes < 0 { "vencido".to_string() } else { dias_restantes.to_string() },
+            row.get::<_, i32>(6)?.to_string(),
+            row.get::<_, i32>(7)?.to_string(),
+        ], 0.0);
+    }
+
+    Ok(documento.gerar())
+}
+
+pub async fn vencer_pdf_handler(dias: i32) -> Result<impl IntoResponse, ErroApp> {
+    let conn = init_db()?;
+    let conteudo = pdf_a_vencer(&conn, dias)?;
+
+    Ok(resposta_pdf(&format!("vencer-{}-dias.pdf", dias), conteudo))
+}
+
+// ===========================================
+// RELATÓRIO HIERÁRQUICO
+// ===========================================
+
+fn celulas_relatorio(item: &RelatorioItem) -> Vec<String> {
+    vec![
+        item.nome.clone(),
+        item.validade_mais_proxima.as_deref().map(data_br).unwrap_or_default(),
+        item.prateleira.to_string(),
+        item.estoque.to_string(),
+        item.total.to_string(),
+        formatar_centavos(item.valor_em_risco_centavos),
+    ]
+}
+
+/// Seções viram faixas; níveis intermediários saem em negrito e as folhas com caixinha.
+fn adicionar_item(documento: &mut DocumentoPdf, item: &RelatorioItem, nivel: usize) {
+    let recuo = 10.0 * nivel.saturating_sub(1) as f32;
+    if nivel == 0 {
+        documento.grupo(celulas_relatorio(item));
+    } else if item.filhos.is_empty() {
+        documento.item(celulas_relatorio(item), recuo);
+    } else {
+        documento.subtotal(celulas_relatorio(item), recuo);
+    }
+
+    for filho in &item.filhos {
+        adicionar_item(documento, filho, nivel + 1);
+    }
+}
+
+pub fn pdf_relatorio(itens: &[RelatorioItem], dias_risco: i32) -> Vec<u8> {
+    let mut documento = DocumentoPdf::novo(
+        "Relatório de estoque e validade",
+        &format!("{} · em risco: lotes que vencem em até {} dias", gerado_em(), dias_risco),
+        vec![
+            Coluna { titulo: "Item", largura: 220.0, direita: false },
+            Coluna { titulo: "Validade", largura: 62.0, direita: true },
+            Coluna { titulo: "Prateleira", largura: 52.0, direita: true },
+            Coluna { titulo: "Estoque", largura: 52.0, direita: true },
+            Coluna { titulo: "Total", largura: 45.0, direita: true },
+            Coluna { titulo: "Em risco (R$)", largura: 70.0, direita: true },
+        ],
+    );
+
+    for secao in itens {
+        adicionar_item(&mut documento, secao, 0);
+    }
+
+    documento.gerar()
+}
+
+/// Mesmos filtros de `/api/relatorio`, em PDF para imprimir.
+#[utoipa::path(get, path = "/relatorio.pdf", tag = "relatorios", params(ParametrosRelatorio),
+    responses((status = 200, content_type = "application/pdf", body = [u8])))]
+pub async fn relatorio_pdf_handler(Query(parametros): Query<ParametrosRelatorio>) -> Result<impl IntoResponse, ErroApp> {
+    let filtro = parametros.filtro()?;
+    let conn = init_db()?;
+
+    let itens = montar_relatorio(&conn, &filtro)?;
+
+    Ok(resposta_pdf("relatorio.pdf", pdf_relatorio(&itens, filtro.dias_risco)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pdf_a_vencer_gera_documento_com_os_lotes() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::criar_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO secoes (id, nome) VALUES (1, 'Mercearia');
+             INSERT INTO tipos (id, nome, id_secao) VALUES (1, 'Grãos', 1);
+             INSERT INTO produtos (id, nome, id_tipo) VALUES (1, 'ARROZ', 1), (2, 'FEIJAO', 1);
+             INSERT INTO lotes (id_produto, validade, quantidade_total, quantidade_prateleira) VALUES
+                 (1, date('now', 'localtime', '+2 days'), 10, 4),
+                 (2, date('now', 'localtime', '+90 days'), 5, 5);"
+        ).unwrap();
+
+        let pdf = pdf_a_vencer(&conn, 7).unwrap();
+        let texto = String::from_utf8_lossy(&pdf);
+
+        assert!(pdf.starts_with(b"%PDF-"));
+        assert!(texto.trim_end().ends_with("%%EOF"));
+        assert!(texto.contains("(ARROZ) Tj"), "o lote que vence deve aparecer no conteúdo");
+        assert!(!texto.contains("FEIJAO"));
+    }
+}
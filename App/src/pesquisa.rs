@@ -0,0 +1,261 @@
+use axum::response::Json;
+use rusqlite::{Connection, ToSql};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::erro::{ErroApp, Query};
+use crate::paginacao::{FiltroLista, Ordem, Pagina, ParametrosLista};
+use crate::{coluna_produto, init_db, CONSULTA_PRODUTOS, FILTROS_PRODUTOS};
+
+// ===========================================
+// ESTRUTURAS DE DADOS
+// ===========================================
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ParametrosPesquisa {
+    /// Nome, código de barras ou código de lote. Cada palavra casa pelo começo,
+    /// sem diferenciar acentos nem maiúsculas; vazio lista todos os produtos
+    pub q: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ResultadoPesquisa {
+    pub id: i32,
+    pub nome: String,
+    pub codigo_barras: Option<String>,
+    pub id_secao: i32,
+    pub secao: String,
+    pub id_tipo: i32,
+    pub tipo: String,
+    pub custo_centavos: Option<i64>,
+    pub preco_venda_centavos: Option<i64>,
+    /// Soma dos lotes do produto
+    pub quantidade: i32,
+    /// Validade mais próxima entre os lotes com unidades
+    pub validade_mais_proxima: Option<String>,
+}
+
+// ===========================================
+// ÍNDICE
+// ===========================================
+
+/// Código de barras do produto `p` e códigos dos seus lotes, separados por espaço.
+const CODIGOS_DO_PRODUTO: &str =
+    "concat_ws(' ', p.codigo_barras, (SELECT group_concat(l.codigo, ' ') FROM lotes l WHERE l.id_produto = p.id))";
+
+/// Refaz a linha do produto `id` no índice (ou só a apaga, se o produto não existe mais).
+fn reindexar(id: &str) -> String {
+    format!(
+        "DELETE FROM pesquisa_produtos WHERE rowid = {0};
+         INSERT INTO pesquisa_produtos (rowid, nome, codigos)
+         SELECT p.id, p.nome, {1} FROM produtos p WHERE p.id = {0};",
+        id, CODIGOS_DO_PRODUTO
+    )
+}
+
+/// Índice FTS5 dos produtos. O tokenizador `unicode61 remove_diacritics 2`
+/// ignora acentos e maiúsculas ("acucar" acha "AÇÚCAR"), e `prefix` deixa
+/// rápida a busca pelo começo das palavras. Gatilhos mantêm o índice em dia
+/// com `produtos` e com os códigos dos `lotes`.
+pub fn criar_indice(conn: &Connection) -> Result<(), rusqlite::Error> {
+    let existia: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE name = 'pesquisa_produtos'",
+        [],
+        |row| row.get(0)
+    )?;
+
+    conn.execute_batch(&format!(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS pesquisa_produtos USING fts5(
+             nome, codigos, tokenize = 'unicode61 remove_diacritics 2', prefix = '2 3'
+         );
+
+         CREATE TRIGGER IF NOT EXISTS pesquisa_produto_inserido AFTER INSERT ON produtos BEGIN
+             {novo}
+         END;
+         CREATE TRIGGER IF NOT EXISTS pesquisa_produto_alterado AFTER UPDATE OF nome, codigo_barras ON produtos BEGIN
+             {novo}
+         END;
+         CREATE TRIGGER IF NOT EXISTS pesquisa_produto_apagado AFTER DELETE ON produtos BEGIN
+             DELETE FROM pesquisa_produtos WHERE rowid = OLD.id;
+         END;
+
+         CREATE TRIGGER IF NOT EXISTS pesquisa_lote_inserido AFTER INSERT ON lotes
+         WHEN NEW.codigo IS NOT NULL BEGIN
+             {lote_novo}
+         END;
+         CREATE TRIGGER IF NOT EXISTS pesquisa_lote_alterado AFTER UPDATE OF codigo, id_produto ON lotes BEGIN
+             {lote_antigo}
+             {lote_novo}
+         END;
+         CREATE TRIGGER IF NOT EXISTS pesquisa_lote_apagado AFTER DELETE ON lotes
+         WHEN OLD.codigo IS NOT NULL BEGIN
+             {lote_antigo}
+         END;",
+        novo = reindexar("NEW.id"),
+        lote_novo = reindexar("NEW.id_produto"),
+        lote_antigo = reindexar("OLD.id_produto"),
+    ))?;
+
+    // Banco de antes do índice: indexa o que já existe
+    if !existia {
+        conn.execute(
+            &format!(
+                "INSERT INTO pesquisa_produtos (rowid, nome, codigos) SELECT p.id, p.nome, {} FROM produtos p",
+                CODIGOS_DO_PRODUTO
+            ),
+            [],
+        )?;
+    }
+
+    Ok(())
+}
+
+// ===========================================
+// PESQUISA
+// ===========================================
+
+/// Consulta FTS5 para o texto digitado: cada palavra entre aspas (operadores
+/// do FTS5 digitados pelo usuário viram texto comum) e com `*` para casar pelo
+/// começo. Todas as palavras precisam aparecer. `None` se não há palavras.
+fn consulta_fts(texto: &str) -> Option<String> {
+    let palavras: Vec<String> = texto
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|palavra| !palavra.is_empty())
+        .map(|palavra| format!("\"{}\"*", palavra))
+        .collect();
+    if palavras.is_empty() { None } else { Some(palavras.join(" ")) }
+}
+
+/// Sem `ordem` pedida, os resultados vêm por relevância (bm25), nome primeiro:
+/// achar a palavra no nome pesa mais que achar num código.
+pub fn pesquisar(conn: &Connection, texto: &str, filtro: &FiltroLista) -> Result<Pagina<ResultadoPesquisa>, rusqlite::Error> {
+    let consulta = consulta_fts(texto);
+    let (juncao, condicao) = match consulta {
+        Some(_) => ("JOIN pesquisa_produtos ON pesquisa_produtos.rowid = p.id", "pesquisa_produtos MATCH ?1"),
+        None => ("", "?1 IS NULL"),
+    };
+    let de_onde = format!(
+        "{} JOIN secoes s ON s.id = t.id_secao {} WHERE {} AND {}",
+        CONSULTA_PRODUTOS, juncao, condicao, FILTROS_PRODUTOS
+    );
+    let ordem = match (filtro.ordem, &consulta) {
+        (None, Some(_)) => "ORDER BY bm25(pesquisa_produtos, 10.0, 1.0), p.nome, p.id".to_string(),
+        _ => filtro.ordenacao(Ordem::Nome, coluna_produto, "p.id"),
+    };
+    let valores: [&dyn ToSql; 7] = [
+        &consulta, &filtro.secao, &filtro.com_prateleira, &filtro.validade_de, &filtro.validade_ate,
+        &filtro.limite, &filtro.deslocamento,
+    ];
+
+    let total = conn.query_row(&format!("SELECT COUNT(*) {}", de_onde), &valores[..5], |row| row.get(0))?;
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT p.id, p.nome, p.codigo_barras, s.id, s.nome, t.id, t.nome,
+                pa.custo_centavos, pa.preco_venda_centavos, COALESCE(e.quantidade, 0), e.validade
+         {} {} LIMIT ?6 OFFSET ?7",
+        de_onde, ordem
+    ))?;
+    let resultados = stmt.query_map(&valores[..], |row| {
+        Ok(ResultadoPesquisa {
+            id: row.get(0)?,
+            nome: row.get(1)?,
+            codigo_barras: row.get(2)?,
+            id_secao: row.get(3)?,
+            secao: row.get(4)?,
+            id_tipo: row.get(5)?,
+            tipo: row.get(6)?,
+            custo_centavos: row.get(7)?,
+            preco_venda_centavos: row.get(8)?,
+            quantidade: row.get(9)?,
+            validade_mais_proxima: row.get(10)?,
+        })
+    })?;
+
+    let mut itens = Vec::new();
+    for resultado in resultados {
+        itens.push(resultado?);
+    }
+
+    Ok(Pagina { itens, total, limite: filtro.limite, deslocamento: filtro.deslocamento })
+}
+
+#[utoipa::path(get, path = "/pesquisar", tag = "produtos", params(ParametrosPesquisa, ParametrosLista),
+    responses((status = 200, description = "Por relevância, se `ordem` não for informada", body = Pagina<ResultadoPesquisa>)))]
+pub async fn pesquisar_handler(
+    Query(pesquisa): Query<ParametrosPesquisa>,
+    Query(parametros): Query<ParametrosLista>
+) -> Result<Json<Pagina<ResultadoPesquisa>>, ErroApp> {
+    let filtro = parametros.filtro()?;
+    let conn = init_db()?;
+
+    Ok(Json(pesquisar(&conn, pesquisa.q.as_deref().unwrap_or(""), &filtro)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::params;
+
+    fn banco() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::criar_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO secoes (id, nome) VALUES (1, 'Mercearia'), (2, 'Frios');
+             INSERT INTO tipos (id, nome, id_secao) VALUES (1, 'Doces', 1), (2, 'Queijos', 2);
+             INSERT INTO produtos (id, nome, id_tipo, codigo_barras) VALUES
+                 (1, 'AÇÚCAR CRISTAL', 1, '7891000100103'),
+                 (2, 'DOCE DE LEITE COM AÇÚCAR', 1, NULL),
+                 (3, 'QUEIJO PRATO', 2, NULL);
+             INSERT INTO lotes (id_produto, validade, quantidade_total, quantidade_prateleira, codigo) VALUES
+                 (1, '2030-01-10', 5, 2, 'L2024-03A'),
+                 (1, '2030-02-10', 3, 0, NULL),
+                 (3, '2030-01-05', 4, 4, 'QX-77');",
+        ).unwrap();
+        conn
+    }
+
+    fn nomes(conn: &Connection, texto: &str) -> Vec<String> {
+        let filtro = ParametrosLista::default().filtro().unwrap();
+        pesquisar(conn, texto, &filtro).unwrap().itens.into_iter().map(|r| r.nome).collect()
+    }
+
+    #[test]
+    fn ignora_acentos_e_casa_pelo_comeco() {
+        let conn = banco();
+        assert_eq!(nomes(&conn, "acucar"), ["AÇÚCAR CRISTAL", "DOCE DE LEITE COM AÇÚCAR"]);
+        assert_eq!(nomes(&conn, "açu cris"), ["AÇÚCAR CRISTAL"]);
+        assert_eq!(nomes(&conn, "789100"), ["AÇÚCAR CRISTAL"]);
+        assert_eq!(nomes(&conn, "l2024-03a"), ["AÇÚCAR CRISTAL"]);
+        assert_eq!(nomes(&conn, "\"OR prato*"), Vec::<String>::new());
+        assert_eq!(nomes(&conn, "").len(), 3);
+    }
+
+    #[test]
+    fn traz_caminho_e_validade_mais_proxima() {
+        let conn = banco();
+        let filtro = ParametrosLista::default().filtro().unwrap();
+        let pagina = pesquisar(&conn, "cristal", &filtro).unwrap();
+        let acucar = &pagina.itens[0];
+        assert_eq!((pagina.total, acucar.secao.as_str(), acucar.tipo.as_str()), (1, "Mercearia", "Doces"));
+        assert_eq!((acucar.quantidade, acucar.validade_mais_proxima.as_deref()), (8, Some("2030-01-10")));
+    }
+
+    #[test]
+    fn indice_acompanha_as_alteracoes() {
+        let conn = banco();
+        conn.execute("UPDATE produtos SET nome = 'QUEIJO MINAS' WHERE id = 3", []).unwrap();
+        assert_eq!(nomes(&conn, "minas"), ["QUEIJO MINAS"]);
+        assert!(nomes(&conn, "prato").is_empty());
+        assert_eq!(nomes(&conn, "qx"), ["QUEIJO MINAS"]);
+
+        conn.execute("DELETE FROM lotes WHERE codigo = 'QX-77'", []).unwrap();
+        assert!(nomes(&conn, "qx").is_empty());
+
+        conn.execute("INSERT INTO lotes (id_produto, validade, quantidade_total, codigo) VALUES (3, '2030-03-01', 1, 'NOVO9')", []).unwrap();
+        assert_eq!(nomes(&conn, "novo9"), ["QUEIJO MINAS"]);
+
+        conn.execute("DELETE FROM produtos WHERE id = ?1", params![3]).unwrap();
+        assert!(nomes(&conn, "minas").is_empty());
+    }
+}
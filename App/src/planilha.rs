@@ -0,0 +1,244 @@
+use axum::{
+    http::header,
+    response::IntoResponse,
+};
+use calamine::{Data, DataType, Reader, Xlsx};
+use chrono::NaiveDate;
+use rusqlite::Connection;
+use rust_xlsxwriter::{Format, Workbook, Worksheet, XlsxError};
+use serde::Deserialize;
+use std::io::Cursor;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::erro::{ErroApp, Query};
+use crate::init_db;
+
+/// Mesmas colunas do CSV, em todas as planilhas, para a importação aceitar qualquer uma.
+const CABECALHO: [&str; 6] = ["Seção", "Tipo", "Produto", "Validade", "Total", "Prateleira"];
+
+// ===========================================
+// ESTRUTURAS DE DADOS
+// ===========================================
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Planilhas {
+    /// Uma planilha por seção
+    #[default]
+    PorSecao,
+    /// Tudo numa planilha só
+    Unica,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ParametrosExportacao {
+    pub planilhas: Option<Planilhas>,
+}
+
+struct LinhaExportada {
+    secao: String,
+    tipo: String,
+    produto: String,
+    validade: String,
+    total: i32,
+    prateleira: i32,
+}
+
+// ===========================================
+// EXPORTAÇÃO
+// ===========================================
+
+/// O Excel não aceita nomes de planilha com `[]:*?/\`, vazios ou com mais de 31 caracteres.
+fn nome_planilha(nome: &str, usados: &mut Vec<String>) -> String {
+    let limpo: String = nome.chars()
+        .map(|c| if "[]:*?/\\".contains(c) { '-' } else { c })
+        .take(31)
+        .collect();
+    let base = if limpo.trim().is_empty() { "Seção".to_string() } else { limpo.trim().to_string() };
+
+    let mut nome = base.clone();
+    let mut n = 2;
+    while usados.iter().any(|usado| usado.to_lowercase() == nome.to_lowercase()) {
+        let sufixo = format!(" ({})", n);
+        nome = base.chars().take(31 - sufixo.chars().count()).collect::<String>() + &sufixo;
+        n += 1;
+    }
+    usados.push(nome.clone());
+    nome
+}
+
+fn escrever_planilha(planilha: &mut Worksheet, linhas: &[LinhaExportada]) -> Result<(), XlsxError> {
+    let negrito = Format::new().set_bold();
+    let data = Format::new().set_num_format("dd/mm/yyyy");
+
+    for (coluna, titulo) in CABECALHO.iter().enumerate() {
+        planilha.write_string_with_format(0, coluna as u16, *titulo, &negrito)?;
+    }
+
+    for (i, linha) in linhas.iter().enumerate() {
+        let row = i as u32 + 1;
+        planilha.write_string(row, 0, &linha.secao)?;
+        planilha.write_string(row, 1, &linha.tipo)?;
+        planilha.write_string(row, 2, &linha.produto)?;
+        match NaiveDate::parse_from_str(&linha.validade, "%Y-%m-%d") {
+            Ok(validade) => planilha.write_date_with_format(row, 3, validade, &data)?,
+            Err(_) => planilha.write_string(row, 3, &linha.validade)?,
+        };
+        planilha.write_number(row, 4, linha.total)?;
+        planilha.write_number(row, 5, linha.prateleira)?;
+    }
+
+    planilha.set_freeze_panes(1, 0)?;
+    planilha.autofit();
+    Ok(())
+}
+
+pub fn gerar_xlsx(conn: &Connection, planilhas: &Planilhas) -> Result<Vec<u8>, ErroApp> {
+    let mut stmt = conn.prepare(
+        "SELECT s.nome, t.nome, p.nome, l.validade, l.quantidade_total, l.quantidade_prateleira
+         FROM secoes s
+         JOIN tipos t ON s.id = t.id_secao
+         JOIN produtos p ON t.id = p.id_tipo
+         JOIN lotes l ON p.id = l.id_produto
+         ORDER BY s.nome, t.nome, p.nome"
+    )?;
+
+    let linhas = stmt.query_map([], |row| {
+        Ok(LinhaExportada {
+            secao: row.get(0)?,
+            tipo: row.get(1)?,
+            produto: row.get(2)?,
+            validade: row.get(3)?,
+            total: row.get(4)?,
+            prateleira: row.get(5)?,
+        })
+    })?;
+
+    let mut todas = Vec::new();
+    for linha in linhas {
+        todas.push(linha?);
+    }
+
+    let mut workbook = Workbook::new();
+    let mut usados = Vec::new();
+    match planilhas {
+        Planilhas::Unica => {
+            let planilha = workbook.add_worksheet();
+            planilha.set_name(nome_planilha("Lotes", &mut usados)).map_err(ErroApp::interno)?;
+            escrever_planilha(planilha, &todas).map_err(ErroApp::interno)?;
+        }
+        Planilhas::PorSecao => {
+            // As linhas já vêm ordenadas por seção
+            for grupo in todas.chunk_by(|a, b| a.secao == b.secao) {
+                let planilha = workbook.add_worksheet();
+                planilha.set_name(nome_planilha(&grupo[0].secao, &mut usados)).map_err(ErroApp::interno)?;
+                escrever_planilha(planilha, grupo).map_err(ErroApp::interno)?;
+            }
+            if todas.is_empty() {
+                escrever_planilha(workbook.add_worksheet(), &todas).map_err(ErroApp::interno)?;
+            }
+        }
+    }
+
+    workbook.save_to_buffer().map_err(ErroApp::interno)
+}
+
+#[utoipa::path(get, path = "/exportar.xlsx", tag = "dados", params(ParametrosExportacao),
+    responses((status = 200, content_type = "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet", body = [u8])))]
+pub async fn exportar_xlsx_handler(Query(parametros): Query<ParametrosExportacao>) -> Result<impl IntoResponse, ErroApp> {
+    let conn = init_db()?;
+    let conteudo = gerar_xlsx(&conn, &parametros.planilhas.unwrap_or_default())?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"estoque.xlsx\""),
+        ],
+        conteudo,
+    ))
+}
+
+// ===========================================
+// IMPORTAÇÃO
+// ===========================================
+
+/// Arquivos .xlsx são zip: começam com "PK\x03\x04".
+pub fn parece_xlsx(conteudo: &[u8]) -> bool {
+    conteudo.starts_with(b"PK\x03\x04")
+}
+
+/// Texto da célula no mesmo formato que viria no CSV: datas como AAAA-MM-DD
+/// e números inteiros sem casas decimais.
+fn texto_celula(celula: &Data) -> String {
+    match celula {
+        Data::DateTime(_) | Data::DateTimeIso(_) => celula.as_date()
+            .map(|data| data.format("%Y-%m-%d").to_string())
+            .unwrap_or_default(),
+        Data::Float(numero) if numero.fract() == 0.0 => format!("{}", *numero as i64),
+        Data::Empty => String::new(),
+        _ => celula.to_string(),
+    }
+}
+
+/// Lê as linhas de todas as planilhas, pulando o cabeçalho de cada uma.
+pub fn linhas_xlsx(conteudo: Vec<u8>) -> Result<Vec<Vec<String>>, calamine::XlsxError> {
+    let mut workbook = Xlsx::new(Cursor::new(conteudo))?;
+
+    let mut linhas = Vec::new();
+    for nome in workbook.sheet_names() {
+        let planilha = workbook.worksheet_range(&nome)?;
+        for row in planilha.rows().skip(1) {
+            linhas.push(row.iter().map(texto_celula).collect());
+        }
+    }
+
+    Ok(linhas)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lotes(conn: &Connection) -> Vec<(String, String, String, String, i32, i32)> {
+        let mut stmt = conn.prepare(
+            "SELECT s.nome, t.nome, p.nome, l.validade, l.quantidade_total, l.quantidade_prateleira
+             FROM lotes l
+             JOIN produtos p ON p.id = l.id_produto
+             JOIN tipos t ON t.id = p.id_tipo
+             JOIN secoes s ON s.id = t.id_secao
+             ORDER BY s.nome, t.nome, p.nome, l.validade"
+        ).unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn planilha_exportada_volta_igual_na_importacao() {
+        let origem = Connection::open_in_memory().unwrap();
+        crate::criar_schema(&origem).unwrap();
+        origem.execute_batch(
+            "INSERT INTO secoes (id, nome) VALUES (1, 'Mercearia'), (2, 'Padaria');
+             INSERT INTO tipos (id, nome, id_secao) VALUES (1, 'Grãos', 1), (2, 'Pães', 2);
+             INSERT INTO produtos (id, nome, id_tipo) VALUES (1, 'ARROZ', 1), (2, 'FEIJÃO', 1), (3, 'FRANCÊS', 2);
+             INSERT INTO lotes (id_produto, validade, quantidade_total, quantidade_prateleira) VALUES
+                 (1, '2030-01-01', 10, 4),
+                 (1, '2030-06-15', 7, 0),
+                 (2, '2031-12-31', 3, 3),
+                 (3, '2030-02-28', 12, 12);"
+        ).unwrap();
+
+        for planilhas in [Planilhas::PorSecao, Planilhas::Unica] {
+            let conteudo = gerar_xlsx(&origem, &planilhas).unwrap();
+            assert!(parece_xlsx(&conteudo));
+
+            let linhas = linhas_xlsx(conteudo).unwrap();
+            let destino = Connection::open_in_memory().unwrap();
+            crate::criar_schema(&destino).unwrap();
+            assert_eq!(crate::importar_linhas(&destino, &linhas).unwrap(), (4, 0));
+
+            assert_eq!(lotes(&destino), lotes(&origem), "{:?}", planilhas);
+        }
+    }
+}
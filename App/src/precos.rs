@@ -0,0 +1,233 @@
+use axum::{http::StatusCode, response::Json};
+use chrono::{NaiveDate, NaiveDateTime};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Deserializer, Serialize};
+use utoipa::ToSchema;
+
+use crate::erro::{Entrada, ErroApp, Path as AxumPath};
+use crate::init_db;
+
+// ===========================================
+// ESTRUTURAS DE DADOS
+// ===========================================
+
+/// Valores monetários são sempre guardados em centavos inteiros.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Preco {
+    pub id: i32,
+    pub id_produto: i32,
+    pub custo_centavos: i64,
+    pub preco_venda_centavos: i64,
+    pub vigente_desde: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct PrecoData {
+    #[serde(deserialize_with = "texto_ou_numero")]
+    pub custo: String,
+    #[serde(deserialize_with = "texto_ou_numero")]
+    pub preco_venda: String,
+    /// AAAA-MM-DD ou AAAA-MM-DD HH:MM:SS; sem ela, vale a partir de agora
+    pub vigente_desde: Option<String>,
+}
+
+/// Formulários mandam "4,99"; JSON pode mandar "4,99" ou 4.99.
+fn texto_ou_numero<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Valor {
+        Texto(String),
+        Numero(serde_json::Number),
+    }
+
+    Ok(match Valor::deserialize(deserializer)? {
+        Valor::Texto(texto) => texto,
+        Valor::Numero(numero) => numero.to_string(),
+    })
+}
+
+// ===========================================
+// CONVERSÃO DE VALORES
+// ===========================================
+
+/// Converte "4,99", "4.99", "1.234,56" ou "4" em centavos sem passar por
+/// ponto flutuante. Com vírgula, os pontos são separadores de milhar; sem
+/// vírgula, o ponto é o separador decimal.
+pub fn centavos_de_texto(texto: &str) -> Option<i64> {
+    let texto = texto.trim();
+    let (inteiros, decimais) = match texto.rsplit_once(',') {
+        Some((i, d)) => (sem_separador_de_milhar(i)?, d),
+        None => match texto.split_once('.') {
+            Some((i, d)) => (i.to_string(), d),
+            None => (texto.to_string(), ""),
+        },
+    };
+
+    if inteiros.is_empty() && decimais.is_empty() {
+        return None;
+    }
+    if !inteiros.chars().all(|c| c.is_ascii_digit())
+        || !decimais.chars().all(|c| c.is_ascii_digit())
+        || decimais.len() > 2
+    {
+        return None;
+    }
+
+    let reais: i64 = if inteiros.is_empty() { 0 } else { inteiros.parse().ok()? };
+    let centavos: i64 = match decimais.len() {
+        0 => 0,
+        1 => decimais.parse::<i64>().ok()? * 10,
+        _ => decimais.parse().ok()?,
+    };
+
+    reais.checked_mul(100)?.checked_add(centavos)
+}
+
+/// "1.234" vira "1234"; grupos fora do padrão de três dígitos são recusados.
+fn sem_separador_de_milhar(inteiros: &str) -> Option<String> {
+    let mut grupos = inteiros.split('.');
+    let primeiro = grupos.next()?;
+    let mut resultado = primeiro.to_string();
+    for grupo in grupos {
+        if primeiro.is_empty() || primeiro.len() > 3 || grupo.len() != 3 {
+            return None;
+        }
+        resultado.push_str(grupo);
+    }
+    Some(resultado)
+}
+
+/// Aceita só datas válidas, para a comparação de texto com `datetime()` funcionar.
+fn vigencia_de_texto(texto: &str) -> Result<String, ErroApp> {
+    let texto = texto.trim();
+    NaiveDate::parse_from_str(texto, "%Y-%m-%d").map(|data| data.to_string())
+        .or_else(|_| NaiveDateTime::parse_from_str(texto, "%Y-%m-%d %H:%M:%S").map(|momento| momento.to_string()))
+        .map_err(|_| ErroApp::invalido(
+            "data_invalida",
+            format!("Vigência inválida: \"{}\" (use AAAA-MM-DD ou AAAA-MM-DD HH:MM:SS)", texto),
+        ))
+}
+
+/// Formata centavos como "1234,56".
+pub fn formatar_centavos(centavos: i64) -> String {
+    let sinal = if centavos < 0 { "-" } else { "" };
+    let absoluto = centavos.unsigned_abs();
+    format!("{}{},{:02}", sinal, absoluto / 100, absoluto % 100)
+}
+
+// ===========================================
+// HANDLERS DE PREÇOS
+// ===========================================
+
+/// Lista o histórico de preços do produto, do mais recente ao mais antigo.
+#[utoipa::path(get, path = "/produtos/{id}/precos", tag = "precos", params(("id" = i32, Path, description = "Id do produto")),
+    responses((status = 200, body = Vec<Preco>)))]
+pub async fn listar_precos_handler(AxumPath(produto_id): AxumPath<i32>) -> Result<Json<Vec<Preco>>, ErroApp> {
+    let conn = init_db()?;
+
+    let mut stmt = conn.prepare(
+        "SELECT id, id_produto, custo_centavos, preco_venda_centavos, vigente_desde
+         FROM precos WHERE id_produto = ?1
+         ORDER BY vigente_desde DESC, id DESC"
+    )?;
+
+    let precos = stmt.query_map([produto_id], |row| {
+        Ok(Preco {
+            id: row.get(0)?,
+            id_produto: row.get(1)?,
+            custo_centavos: row.get(2)?,
+            preco_venda_centavos: row.get(3)?,
+            vigente_desde: row.get(4)?,
+        })
+    })?;
+
+    let mut resultado = Vec::new();
+    for preco in precos {
+        resultado.push(preco?);
+    }
+
+    Ok(Json(resultado))
+}
+
+/// Registra um novo preço. Os anteriores ficam no histórico para que
+/// relatórios de datas passadas continuem usando os preços da época.
+#[utoipa::path(post, path = "/produtos/{id}/precos", tag = "precos", params(("id" = i32, Path, description = "Id do produto")), request_body(content((PrecoData = "application/json"), (PrecoData = "application/x-www-form-urlencoded"))),
+    responses((status = 201, body = Preco)))]
+pub async fn definir_preco_handler(
+    AxumPath(produto_id): AxumPath<i32>,
+    Entrada(preco): Entrada<PrecoData>
+) -> Result<(StatusCode, Json<Preco>), ErroApp> {
+    let conn = init_db()?;
+
+    let custo = centavos_de_texto(&preco.custo)
+        .ok_or_else(|| ErroApp::invalido("preco_invalido", format!("Custo inválido: \"{}\"", preco.custo)))?;
+    let venda = centavos_de_texto(&preco.preco_venda)
+        .ok_or_else(|| ErroApp::invalido("preco_invalido", format!("Preço de venda inválido: \"{}\"", preco.preco_venda)))?;
+    let vigente_desde = preco.vigente_desde.as_deref().map(vigencia_de_texto).transpose()?;
+
+    conn.query_row(
+        "SELECT 1 FROM produtos WHERE id = ?1",
+        [produto_id],
+        |_| Ok(())
+    ).optional()?.ok_or(ErroApp::NaoEncontrado("Produto não encontrado"))?;
+
+    let preco = conn.query_row(
+        "INSERT INTO precos (id_produto, custo_centavos, preco_venda_centavos, vigente_desde)
+         VALUES (?1, ?2, ?3, COALESCE(?4, datetime('now', 'localtime')))
+         RETURNING id, id_produto, custo_centavos, preco_venda_centavos, vigente_desde",
+        params![produto_id, custo, venda, vigente_desde],
+        |row| Ok(Preco {
+            id: row.get(0)?,
+            id_produto: row.get(1)?,
+            custo_centavos: row.get(2)?,
+            preco_venda_centavos: row.get(3)?,
+            vigente_desde: row.get(4)?,
+        })
+    )?;
+
+    Ok((StatusCode::CREATED, Json(preco)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn centavos_aceitam_virgula_ponto_e_milhares() {
+        assert_eq!(centavos_de_texto("4,99"), Some(499));
+        assert_eq!(centavos_de_texto("4.99"), Some(499));
+        assert_eq!(centavos_de_texto(" 4 "), Some(400));
+        assert_eq!(centavos_de_texto("4,5"), Some(450));
+        assert_eq!(centavos_de_texto(",5"), Some(50));
+        assert_eq!(centavos_de_texto("4,"), Some(400));
+        assert_eq!(centavos_de_texto("1.234,56"), Some(123456));
+        assert_eq!(centavos_de_texto("12.345.678,90"), Some(1234567890));
+    }
+
+    #[test]
+    fn centavos_recusam_valores_ambiguos_ou_invalidos() {
+        for texto in [
+            "", ",", ".", "abc", "-4,99", "-1", "+1", "4,999", "4.999", "1.234",
+            "1,234.56", "1.23,45", "1234.567,00", ".123,00", "4,9a", "1 234,56",
+            "99999999999999999999",
+        ] {
+            assert_eq!(centavos_de_texto(texto), None, "{:?}", texto);
+        }
+    }
+
+    #[test]
+    fn formata_centavos() {
+        assert_eq!(formatar_centavos(123456), "1234,56");
+        assert_eq!(formatar_centavos(5), "0,05");
+        assert_eq!(formatar_centavos(-250), "-2,50");
+    }
+
+    #[test]
+    fn vigencia_so_aceita_datas_validas() {
+        assert_eq!(vigencia_de_texto("2026-03-01").unwrap(), "2026-03-01");
+        assert_eq!(vigencia_de_texto("2026-03-01 08:30:00").unwrap(), "2026-03-01 08:30:00");
+        for texto in ["amanhã", "01/03/2026", "2026-02-30", "2026-3-1x", ""] {
+            assert_eq!(vigencia_de_texto(texto).unwrap_err().codigo(), "data_invalida", "{:?}", texto);
+        }
+    }
+}
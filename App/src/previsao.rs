@@ -0,0 +1,263 @@
+use axum::response::Json;
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::config;
+use crate::erro::{ErroApp, Query};
+use crate::init_db;
+
+
+// ===========================================
+// ESTRUTURAS DE DADOS
+// ===========================================
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct VelocidadeProduto {
+    pub id_produto: i32,
+    pub produto: String,
+    pub vendidos_na_janela: i32,
+    pub media_diaria: f64,
+    pub estoque_atual: i32,
+    /// Dias até o estoque atual acabar no ritmo de vendas; ausente se não houve vendas
+    pub dias_de_cobertura: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ProjecaoLote {
+    pub id_lote: i32,
+    pub id_produto: i32,
+    pub produto: String,
+    pub id_secao: i32,
+    pub validade: String,
+    pub dias_restantes: i32,
+    pub quantidade: i32,
+    pub media_diaria_vendas: f64,
+    pub venda_prevista: i32,
+    pub sobra_prevista: i32,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ParametrosPrevisao {
+    /// Quantos dias de vendas entram na média diária
+    pub janela_dias: Option<i32>,
+}
+
+// ===========================================
+// CÁLCULO
+// ===========================================
+
+/// Média diária de unidades vendidas por produto nos últimos `janela_dias` dias.
+pub fn media_diaria_por_produto(conn: &Connection, janela_dias: i32) -> Result<HashMap<i32, f64>, rusqlite::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT id_produto, SUM(quantidade) FROM vendas
+         WHERE data >= datetime('now', 'localtime', '-' || ?1 || ' days')
+         GROUP BY id_produto"
+    )?;
+
+    let medias = stmt.query_map([janela_dias], |row| {
+        Ok((row.get::<_, i32>(0)?, row.get::<_, i32>(1)? as f64 / janela_dias as f64))
+    })?;
+
+    medias.collect()
+}
+
+/// Projeta quanto de cada lote com estoque será vendido até a validade.
+///
+/// Os lotes de um mesmo produto são consumidos em ordem de validade: a demanda
+/// prevista atende primeiro o lote que vence antes e só o que sobra dela chega
+/// aos lotes seguintes. Lotes já vencidos não recebem demanda, e lotes com
+/// validade que não é data ficam de fora.
+pub fn projetar_lotes(conn: &Connection, janela_dias: i32) -> Result<Vec<ProjecaoLote>, rusqlite::Error> {
+    let medias = media_diaria_por_produto(conn, janela_dias)?;
+
+    let mut stmt = conn.prepare(
+        "SELECT l.id, l.id_produto, p.nome, t.id_secao, l.validade,
+                CAST(julianday(l.validade) - julianday(date('now', 'localtime')) AS INTEGER),
+                l.quantidade_total
+         FROM lotes l
+         JOIN produtos p ON p.id = l.id_produto
+         JOIN tipos t ON t.id = p.id_tipo
+         WHERE l.quantidade_total > 0
+           AND julianday(l.validade) IS NOT NULL
+         ORDER BY l.id_produto, l.validade, l.id"
+    )?;
+
+    let lotes = stmt.query_map([], |row| {
+        Ok(ProjecaoLote {
+            id_lote: row.get(0)?,
+            id_produto: row.get(1)?,
+            produto: row.get(2)?,
+            id_secao: row.get(3)?,
+            validade: row.get(4)?,
+            dias_restantes: row.get(5)?,
+            quantidade: row.get(6)?,
+            media_diaria_vendas: 0.0,
+            venda_prevista: 0,
+            sobra_prevista: 0,
+        })
+    })?;
+
+    let mut resultado: Vec<ProjecaoLote> = Vec::new();
+    let mut produto_atual = None;
+    let mut demanda_consumida = 0;
+
+    for lote in lotes {
+        let mut lote = lote?;
+
+        if produto_atual != Some(lote.id_produto) {
+            produto_atual = Some(lote.id_produto);
+            demanda_consumida = 0;
+        }
+
+        let media = medias.get(&lote.id_produto).copied().unwrap_or(0.0);
+        let demanda_ate_vencer = if lote.dias_restantes > 0 {
+            (media * lote.dias_restantes as f64).floor() as i32
+        } else {
+            0
+        };
+
+        let disponivel = (demanda_ate_vencer - demanda_consumida).max(0);
+        let venda = lote.quantidade.min(disponivel);
+        demanda_consumida += venda;
+
+        lote.media_diaria_vendas = media;
+        lote.venda_prevista = venda;
+        lote.sobra_prevista = lote.quantidade - venda;
+        resultado.push(lote);
+    }
+
+    Ok(resultado)
+}
+
+// ===========================================
+// HANDLERS DE PREVISÃO
+// ===========================================
+
+#[utoipa::path(get, path = "/previsao/vendas", tag = "previsao", params(ParametrosPrevisao),
+    responses((status = 200, body = Vec<VelocidadeProduto>)))]
+pub async fn velocidade_vendas_handler(Query(parametros): Query<ParametrosPrevisao>) -> Result<Json<Vec<VelocidadeProduto>>, ErroApp> {
+    let conn = init_db()?;
+    let janela = parametros.janela_dias.unwrap_or(config::atual().alertas.janela_vendas_dias);
+
+    if janela <= 0 {
+        return Err(ErroApp::invalido("janela_invalida", "A janela de vendas deve ser maior que zero"));
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT p.id, p.nome,
+                (SELECT COALESCE(SUM(v.quantidade), 0) FROM vendas v
+                 WHERE v.id_produto = p.id
+                   AND v.data >= datetime('now', 'localtime', '-' || ?1 || ' days')) as vendidos,
+                (SELECT COALESCE(SUM(l.quantidade_total), 0) FROM lotes l
+                 WHERE l.id_produto = p.id) as estoque
+         FROM produtos p
+         ORDER BY p.nome"
+    )?;
+
+    let produtos = stmt.query_map(params![janela], |row| {
+        let vendidos: i32 = row.get(2)?;
+        let estoque: i32 = row.get(3)?;
+        let media = vendidos as f64 / janela as f64;
+        Ok(VelocidadeProduto {
+            id_produto: row.get(0)?,
+            produto: row.get(1)?,
+            vendidos_na_janela: vendidos,
+            media_diaria: media,
+            estoque_atual: estoque,
+            dias_de_cobertura: if media > 0.0 { Some(estoque as f64 / media) } else { None },
+        })
+    })?;
+
+    let mut resultado = Vec::new();
+    for produto in produtos {
+        resultado.push(produto?);
+    }
+
+    Ok(Json(resultado))
+}
+
+/// Lotes que, no ritmo atual de vendas, não vão vender tudo antes da validade.
+#[utoipa::path(get, path = "/previsao/encalhe", tag = "previsao", params(ParametrosPrevisao),
+    responses((status = 200, body = Vec<ProjecaoLote>)))]
+pub async fn nao_vendera_a_tempo_handler(Query(parametros): Query<ParametrosPrevisao>) -> Result<Json<Vec<ProjecaoLote>>, ErroApp> {
+    let conn = init_db()?;
+    let janela = parametros.janela_dias.unwrap_or(config::atual().alertas.janela_vendas_dias);
+
+    if janela <= 0 {
+        return Err(ErroApp::invalido("janela_invalida", "A janela de vendas deve ser maior que zero"));
+    }
+
+    let mut resultado: Vec<ProjecaoLote> = projetar_lotes(&conn, janela)
+        ?
+        .into_iter()
+        .filter(|lote| lote.sobra_prevista > 0)
+        .collect();
+
+    resultado.sort_by(|a, b| a.validade.cmp(&b.validade).then(b.sobra_prevista.cmp(&a.sobra_prevista)));
+
+    Ok(Json(resultado))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn demanda_atende_primeiro_o_lote_que_vence_antes() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::criar_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO secoes (id, nome) VALUES (1, 'Laticínios');
+             INSERT INTO tipos (id, nome, id_secao) VALUES (1, 'Iogurte', 1);
+             INSERT INTO produtos (id, nome, id_tipo) VALUES (1, 'Natural', 1), (2, 'Grego', 1);
+             INSERT INTO lotes (id, id_produto, validade, quantidade_total) VALUES
+                 (1, 1, date('now', 'localtime', '+10 days'), 20),
+                 (2, 1, date('now', 'localtime', '+5 days'), 6),
+                 (3, 1, date('now', 'localtime', '-1 days'), 3),
+                 (4, 1, date('now', 'localtime', '+30 days'), 0),
+                 (5, 2, date('now', 'localtime', '+5 days'), 4);
+             -- 20 unidades em 10 dias: 2 por dia
+             INSERT INTO vendas (id_lote, id_produto, quantidade, data) VALUES
+                 (2, 1, 12, datetime('now', 'localtime', '-2 days')),
+                 (2, 1, 8, datetime('now', 'localtime', '-9 days')),
+                 (2, 1, 50, datetime('now', 'localtime', '-20 days'));"
+        ).unwrap();
+
+        let projecao = projetar_lotes(&conn, 10).unwrap();
+        let resumo: Vec<(i32, i32, i32, i32)> = projecao.iter()
+            .map(|lote| (lote.id_lote, lote.dias_restantes, lote.venda_prevista, lote.sobra_prevista))
+            .collect();
+
+        assert_eq!(resumo, [
+            // Vencido: não vende nada
+            (3, -1, 0, 3),
+            // 5 dias × 2 = 10 de demanda, mas o lote só tem 6
+            (2, 5, 6, 0),
+            // 10 dias × 2 = 20, menos os 6 já vendidos do lote anterior
+            (1, 10, 14, 6),
+            // Produto sem vendas na janela: sobra tudo
+            (5, 5, 0, 4),
+        ]);
+        assert_eq!(projecao[0].media_diaria_vendas, 2.0);
+    }
+
+    #[test]
+    fn lote_com_validade_malformada_fica_de_fora() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::criar_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO secoes (id, nome) VALUES (1, 'Laticínios');
+             INSERT INTO tipos (id, nome, id_secao) VALUES (1, 'Iogurte', 1);
+             INSERT INTO produtos (id, nome, id_tipo) VALUES (1, 'Natural', 1);
+             INSERT INTO lotes (id, id_produto, validade, quantidade_total) VALUES
+                 (1, 1, '31/12/2030', 5),
+                 (2, 1, date('now', 'localtime', '+3 days'), 4);"
+        ).unwrap();
+
+        let projecao = projetar_lotes(&conn, 10).unwrap();
+        let lotes: Vec<i32> = projecao.iter().map(|lote| lote.id_lote).collect();
+        assert_eq!(lotes, [2]);
+    }
+}
@@ -0,0 +1,452 @@
+use axum::{http::StatusCode, response::Json};
+use rusqlite::{params, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::config;
+use crate::erro::{Entrada, ErroApp, Path as AxumPath, Query};
+use crate::init_db;
+use crate::previsao::projetar_lotes;
+
+// ===========================================
+// ESTRUTURAS DE DADOS
+// ===========================================
+
+/// Uma regra vale para lotes que vencem em até `dias_restantes` dias e cuja
+/// sobra prevista (em % do estoque do lote) é de pelo menos `sobra_minima_percentual`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RegraRebaixa {
+    pub id: i32,
+    pub dias_restantes: i32,
+    pub sobra_minima_percentual: i32,
+    pub desconto_percentual: i32,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RegraRebaixaData {
+    pub dias_restantes: i32,
+    #[serde(default)]
+    pub sobra_minima_percentual: i32,
+    pub desconto_percentual: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LimiteRebaixa {
+    pub id_secao: i32,
+    pub desconto_maximo: i32,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SugestaoRebaixa {
+    pub id_lote: i32,
+    pub id_produto: i32,
+    pub produto: String,
+    pub id_secao: i32,
+    pub validade: String,
+    pub dias_restantes: i32,
+    pub quantidade: i32,
+    pub media_diaria_vendas: f64,
+    pub sobra_prevista: i32,
+    pub desconto_sugerido: i32,
+    pub desconto_atual: Option<i32>,
+    pub preco_atual_centavos: Option<i64>,
+    pub preco_sugerido_centavos: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Rebaixa {
+    pub id: i32,
+    pub id_lote: i32,
+    pub produto: String,
+    pub validade: String,
+    pub desconto_percentual: i32,
+    pub preco_original_centavos: i64,
+    pub preco_centavos: i64,
+    pub data: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AceitarRebaixaData {
+    pub desconto_percentual: i32,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ParametrosSugestao {
+    /// Quantos dias de vendas entram na média diária
+    pub janela_dias: Option<i32>,
+}
+
+// ===========================================
+// CÁLCULO
+// ===========================================
+
+/// Aplica o desconto arredondando para o centavo mais próximo.
+pub fn preco_com_desconto(preco_centavos: i64, desconto_percentual: i32) -> i64 {
+    (preco_centavos * (100 - desconto_percentual as i64) + 50) / 100
+}
+
+/// Parte do lote que deve sobrar, em pontos percentuais de 0 a 100. A conta é
+/// feita em i64 porque `sobra * 100` estoura i32 em lotes com milhões de unidades.
+fn sobra_percentual(sobra_prevista: i32, quantidade: i32) -> i32 {
+    if quantidade <= 0 {
+        return 0;
+    }
+    (sobra_prevista as i64 * 100 / quantidade as i64).clamp(0, 100) as i32
+}
+
+/// Maior desconto entre as regras que casam com o lote, limitado pelo máximo da seção.
+fn desconto_para(
+    regras: &[RegraRebaixa],
+    dias_restantes: i32,
+    sobra_percentual: i32,
+    desconto_maximo: Option<i32>,
+) -> Option<i32> {
+    let desconto = regras.iter()
+        .filter(|r| dias_restantes <= r.dias_restantes && sobra_percentual >= r.sobra_minima_percentual)
+        .map(|r| r.desconto_percentual)
+        .max()?;
+
+    let desconto = match desconto_maximo {
+        Some(maximo) => desconto.min(maximo),
+        None => desconto,
+    };
+
+    if desconto > 0 { Some(desconto) } else { None }
+}
+
+fn carregar_regras(conn: &rusqlite::Connection) -> Result<Vec<RegraRebaixa>, rusqlite::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT id, dias_restantes, sobra_minima_percentual, desconto_percentual
+         FROM regras_rebaixa ORDER BY dias_restantes, sobra_minima_percentual"
+    )?;
+
+    let regras = stmt.query_map([], |row| {
+        Ok(RegraRebaixa {
+            id: row.get(0)?,
+            dias_restantes: row.get(1)?,
+            sobra_minima_percentual: row.get(2)?,
+            desconto_percentual: row.get(3)?,
+        })
+    })?;
+
+    regras.collect()
+}
+
+// ===========================================
+// HANDLERS DE SUGESTÕES
+// ===========================================
+
+#[utoipa::path(get, path = "/rebaixas/sugestoes", tag = "rebaixas", params(ParametrosSugestao),
+    responses((status = 200, body = Vec<SugestaoRebaixa>)))]
+pub async fn sugestoes_rebaixa_handler(Query(parametros): Query<ParametrosSugestao>) -> Result<Json<Vec<SugestaoRebaixa>>, ErroApp> {
+    let conn = init_db()?;
+    let janela = parametros.janela_dias.unwrap_or(config::atual().alertas.janela_vendas_dias);
+
+    if janela <= 0 {
+        return Err(ErroApp::invalido("janela_invalida", "A janela de vendas deve ser maior que zero"));
+    }
+
+    let regras = carregar_regras(&conn)?;
+    let Some(dias_maximo) = regras.iter().map(|r| r.dias_restantes).max() else {
+        return Ok(Json(Vec::new()));
+    };
+
+    // Preço, limite da seção e rebaixa atual de cada lote candidato
+    let mut stmt = conn.prepare(
+        "SELECT l.id, pa.preco_venda_centavos, lr.desconto_maximo, ra.desconto_percentual
+         FROM lotes l
+         JOIN produtos p ON p.id = l.id_produto
+         JOIN tipos t ON t.id = p.id_tipo
+         LEFT JOIN precos_atuais pa ON pa.id_produto = l.id_produto
+         LEFT JOIN limites_rebaixa lr ON lr.id_secao = t.id_secao
+         LEFT JOIN rebaixas_atuais ra ON ra.id_lote = l.id
+         WHERE l.quantidade_total > 0
+           AND julianday(l.validade) - julianday(date('now', 'localtime')) BETWEEN 0 AND ?1"
+    )?;
+
+    let linhas = stmt.query_map([dias_maximo], |row| {
+        Ok((
+            row.get::<_, i32>(0)?,
+            (row.get::<_, Option<i64>>(1)?, row.get::<_, Option<i32>>(2)?, row.get::<_, Option<i32>>(3)?),
+        ))
+    })?;
+
+    let mut candidatos = HashMap::new();
+    for linha in linhas {
+        let (id_lote, dados) = linha?;
+        candidatos.insert(id_lote, dados);
+    }
+
+    let projecoes = projetar_lotes(&conn, janela)?;
+
+    let mut resultado = Vec::new();
+    for lote in projecoes {
+        let Some(&(preco, maximo, atual)) = candidatos.get(&lote.id_lote) else {
+            continue;
+        };
+        if lote.sobra_prevista == 0 {
+            continue;
+        }
+        let sobra_percentual = sobra_percentual(lote.sobra_prevista, lote.quantidade);
+
+        let Some(desconto) = desconto_para(&regras, lote.dias_restantes, sobra_percentual, maximo) else {
+            continue;
+        };
+        if atual.is_some_and(|a| a >= desconto) {
+            continue;
+        }
+
+        resultado.push(SugestaoRebaixa {
+            id_lote: lote.id_lote,
+            id_produto: lote.id_produto,
+            produto: lote.produto,
+            id_secao: lote.id_secao,
+            validade: lote.validade,
+            dias_restantes: lote.dias_restantes,
+            quantidade: lote.quantidade,
+            media_diaria_vendas: lote.media_diaria_vendas,
+            sobra_prevista: lote.sobra_prevista,
+            desconto_sugerido: desconto,
+            desconto_atual: atual,
+            preco_atual_centavos: preco,
+            preco_sugerido_centavos: preco.map(|p| preco_com_desconto(p, desconto)),
+        });
+    }
+
+    resultado.sort_by(|a, b| a.validade.cmp(&b.validade).then_with(|| a.produto.cmp(&b.produto)));
+
+    Ok(Json(resultado))
+}
+
+/// Aceita uma rebaixa para o lote, gravando o novo preço calculado sobre o preço de venda atual.
+#[utoipa::path(post, path = "/lotes/{id}/rebaixa", tag = "rebaixas", params(("id" = i32, Path, description = "Id do lote")), request_body(content((AceitarRebaixaData = "application/json"), (AceitarRebaixaData = "application/x-www-form-urlencoded"))),
+    responses((status = 201, body = Rebaixa)))]
+pub async fn aceitar_rebaixa_handler(
+    AxumPath(id): AxumPath<i32>,
+    Entrada(dados): Entrada<AceitarRebaixaData>
+) -> Result<(StatusCode, Json<Rebaixa>), ErroApp> {
+    let conn = init_db()?;
+
+    if !(1..=99).contains(&dados.desconto_percentual) {
+        return Err(ErroApp::invalido("desconto_invalido", "O desconto deve estar entre 1% e 99%"));
+    }
+
+    let (preco, maximo): (Option<i64>, Option<i32>) = conn.query_row(
+        "SELECT pa.preco_venda_centavos, lr.desconto_maximo
+         FROM lotes l
+         JOIN produtos p ON p.id = l.id_produto
+         JOIN tipos t ON t.id = p.id_tipo
+         LEFT JOIN precos_atuais pa ON pa.id_produto = l.id_produto
+         LEFT JOIN limites_rebaixa lr ON lr.id_secao = t.id_secao
+         WHERE l.id = ?1",
+        [id],
+        |row| Ok((row.get(0)?, row.get(1)?))
+    ).optional()?.ok_or(ErroApp::NaoEncontrado("Lote não encontrado"))?;
+
+    let preco = preco.ok_or_else(|| ErroApp::invalido("sem_preco", "O produto deste lote não tem preço de venda"))?;
+    if let Some(maximo) = maximo.filter(|&m| dados.desconto_percentual > m) {
+        return Err(ErroApp::invalido(
+            "desconto_acima_do_limite",
+            format!("O desconto máximo nesta seção é {}%", maximo),
+        ));
+    }
+
+    let novo_preco = preco_com_desconto(preco, dados.desconto_percentual);
+
+    conn.execute(
+        "INSERT INTO rebaixas (id_lote, desconto_percentual, preco_original_centavos, preco_centavos)
+         VALUES (?1, ?2, ?3, ?4)",
+        params![id, dados.desconto_percentual, preco, novo_preco],
+    )?;
+
+    let rebaixa = conn.query_row(
+        &format!("{} WHERE r.id = ?1", SELECT_REBAIXAS),
+        [conn.last_insert_rowid()],
+        rebaixa_da_linha
+    )?;
+
+    Ok((StatusCode::CREATED, Json(rebaixa)))
+}
+
+const SELECT_REBAIXAS: &str =
+    "SELECT r.id, r.id_lote, COALESCE(p.nome, ''), COALESCE(l.validade, ''),
+            r.desconto_percentual, r.preco_original_centavos, r.preco_centavos, r.data
+     FROM rebaixas r
+     LEFT JOIN lotes l ON l.id = r.id_lote
+     LEFT JOIN produtos p ON p.id = l.id_produto";
+
+fn rebaixa_da_linha(row: &Row) -> rusqlite::Result<Rebaixa> {
+    Ok(Rebaixa {
+        id: row.get(0)?,
+        id_lote: row.get(1)?,
+        produto: row.get(2)?,
+        validade: row.get(3)?,
+        desconto_percentual: row.get(4)?,
+        preco_original_centavos: row.get(5)?,
+        preco_centavos: row.get(6)?,
+        data: row.get(7)?,
+    })
+}
+
+#[utoipa::path(get, path = "/rebaixas", tag = "rebaixas",
+    responses((status = 200, body = Vec<Rebaixa>)))]
+pub async fn listar_rebaixas_handler() -> Result<Json<Vec<Rebaixa>>, ErroApp> {
+    let conn = init_db()?;
+
+    let mut stmt = conn.prepare(&format!("{} ORDER BY r.data DESC, r.id DESC", SELECT_REBAIXAS))?;
+
+    let rebaixas = stmt.query_map([], rebaixa_da_linha)?;
+
+    let mut resultado = Vec::new();
+    for rebaixa in rebaixas {
+        resultado.push(rebaixa?);
+    }
+
+    Ok(Json(resultado))
+}
+
+// ===========================================
+// HANDLERS DE REGRAS
+// ===========================================
+
+#[utoipa::path(get, path = "/rebaixas/regras", tag = "rebaixas",
+    responses((status = 200, body = Vec<RegraRebaixa>)))]
+pub async fn listar_regras_handler() -> Result<Json<Vec<RegraRebaixa>>, ErroApp> {
+    let conn = init_db()?;
+    let regras = carregar_regras(&conn)?;
+    Ok(Json(regras))
+}
+
+#[utoipa::path(post, path = "/rebaixas/regras", tag = "rebaixas", request_body(content((RegraRebaixaData = "application/json"), (RegraRebaixaData = "application/x-www-form-urlencoded"))),
+    responses((status = 201, body = RegraRebaixa)))]
+pub async fn criar_regra_handler(Entrada(regra): Entrada<RegraRebaixaData>) -> Result<(StatusCode, Json<RegraRebaixa>), ErroApp> {
+    let conn = init_db()?;
+
+    let regra = conn.query_row(
+        "INSERT INTO regras_rebaixa (dias_restantes, sobra_minima_percentual, desconto_percentual)
+         VALUES (?1, ?2, ?3)
+         RETURNING id, dias_restantes, sobra_minima_percentual, desconto_percentual",
+        params![regra.dias_restantes, regra.sobra_minima_percentual, regra.desconto_percentual],
+        |row| Ok(RegraRebaixa {
+            id: row.get(0)?,
+            dias_restantes: row.get(1)?,
+            sobra_minima_percentual: row.get(2)?,
+            desconto_percentual: row.get(3)?,
+        })
+    )?;
+
+    Ok((StatusCode::CREATED, Json(regra)))
+}
+
+#[utoipa::path(delete, path = "/rebaixas/regras/{id}", tag = "rebaixas", params(("id" = i32, Path, description = "Id da regra")),
+    responses((status = 204, description = "Regra apagada")))]
+pub async fn deletar_regra_handler(AxumPath(id): AxumPath<i32>) -> Result<StatusCode, ErroApp> {
+    let conn = init_db()?;
+
+    let apagadas = conn.execute(
+        "DELETE FROM regras_rebaixa WHERE id = ?1",
+        [id],
+    )?;
+    if apagadas == 0 {
+        return Err(ErroApp::NaoEncontrado("Regra não encontrada"));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(get, path = "/rebaixas/limites", tag = "rebaixas",
+    responses((status = 200, body = Vec<LimiteRebaixa>)))]
+pub async fn listar_limites_handler() -> Result<Json<Vec<LimiteRebaixa>>, ErroApp> {
+    let conn = init_db()?;
+
+    let mut stmt = conn.prepare(
+        "SELECT id_secao, desconto_maximo FROM limites_rebaixa ORDER BY id_secao"
+    )?;
+
+    let limites = stmt.query_map([], |row| {
+        Ok(LimiteRebaixa {
+            id_secao: row.get(0)?,
+            desconto_maximo: row.get(1)?,
+        })
+    })?;
+
+    let mut resultado = Vec::new();
+    for limite in limites {
+        resultado.push(limite?);
+    }
+
+    Ok(Json(resultado))
+}
+
+/// Define (ou substitui) o desconto máximo permitido em uma seção.
+#[utoipa::path(post, path = "/rebaixas/limites", tag = "rebaixas", request_body(content((LimiteRebaixa = "application/json"), (LimiteRebaixa = "application/x-www-form-urlencoded"))),
+    responses((status = 200, body = LimiteRebaixa)))]
+pub async fn definir_limite_handler(Entrada(limite): Entrada<LimiteRebaixa>) -> Result<Json<LimiteRebaixa>, ErroApp> {
+    let conn = init_db()?;
+
+    conn.query_row(
+        "SELECT 1 FROM secoes WHERE id = ?1",
+        [limite.id_secao],
+        |_| Ok(())
+    ).optional()?.ok_or(ErroApp::NaoEncontrado("Seção não encontrada"))?;
+
+    conn.execute(
+        "INSERT INTO limites_rebaixa (id_secao, desconto_maximo) VALUES (?1, ?2)
+         ON CONFLICT(id_secao) DO UPDATE SET desconto_maximo = excluded.desconto_maximo",
+        params![limite.id_secao, limite.desconto_maximo],
+    )?;
+
+    Ok(Json(limite))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn regra(dias_restantes: i32, sobra_minima_percentual: i32, desconto_percentual: i32) -> RegraRebaixa {
+        RegraRebaixa { id: 0, dias_restantes, sobra_minima_percentual, desconto_percentual }
+    }
+
+    #[test]
+    fn maior_desconto_entre_as_regras_que_casam() {
+        let regras = [regra(7, 0, 10), regra(3, 0, 30), regra(7, 50, 20)];
+
+        // Longe da validade: nenhuma regra
+        assert_eq!(desconto_para(&regras, 8, 100, None), None);
+        // Limites inclusivos: 7 dias e 50% de sobra casam com as duas regras de 7 dias
+        assert_eq!(desconto_para(&regras, 7, 50, None), Some(20));
+        assert_eq!(desconto_para(&regras, 7, 49, None), Some(10));
+        // Perto de vencer vale a regra mais forte
+        assert_eq!(desconto_para(&regras, 2, 0, None), Some(30));
+        assert_eq!(desconto_para(&regras, -1, 0, None), Some(30));
+        assert_eq!(desconto_para(&[], 0, 100, None), None);
+    }
+
+    #[test]
+    fn desconto_limitado_pelo_maximo_da_secao() {
+        let regras = [regra(3, 0, 30)];
+
+        assert_eq!(desconto_para(&regras, 1, 0, Some(15)), Some(15));
+        assert_eq!(desconto_para(&regras, 1, 0, Some(50)), Some(30));
+        // Máximo zero: a seção não aceita rebaixas
+        assert_eq!(desconto_para(&regras, 1, 0, Some(0)), None);
+    }
+
+    #[test]
+    fn sobra_percentual_de_lote_enorme_nao_estoura() {
+        assert_eq!(sobra_percentual(30_000_000, 60_000_000), 50);
+        assert_eq!(sobra_percentual(i32::MAX, i32::MAX), 100);
+        // Fora do intervalo fica preso entre 0 e 100
+        assert_eq!(sobra_percentual(50, 10), 100);
+        assert_eq!(sobra_percentual(-5, 10), 0);
+        assert_eq!(sobra_percentual(5, 0), 0);
+    }
+
+    #[test]
+    fn preco_com_desconto_arredonda_para_o_centavo() {
+        assert_eq!(preco_com_desconto(1000, 30), 700);
+        assert_eq!(preco_com_desconto(999, 15), 849);
+        assert_eq!(preco_com_desconto(5, 50), 3);
+    }
+}
@@ -0,0 +1,83 @@
+use axum::{body::Body, http::Request};
+use tracing::{Level, Span};
+use tracing_appender::{non_blocking::WorkerGuard, rolling::{RollingFileAppender, Rotation}};
+use tracing_subscriber::{
+    filter::LevelFilter,
+    fmt::{self, format::{DefaultFields, Format}, MakeWriter},
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    Layer, Registry,
+};
+
+use crate::config::Config;
+
+// ===========================================
+// INICIALIZAÇÃO
+// ===========================================
+
+type Camada = Box<dyn Layer<Registry> + Send + Sync>;
+
+fn camada<W>(json: bool, camada: fmt::Layer<Registry, DefaultFields, Format, W>) -> Camada
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    if json {
+        camada.json().boxed()
+    } else {
+        camada.boxed()
+    }
+}
+
+/// Liga o log no terminal e, se `log_arquivos` > 0, em `dados/logs/validade.AAAA-MM-DD.log`,
+/// um arquivo por dia. A guarda devolvida precisa viver até o fim do `main`,
+/// senão as últimas linhas do arquivo se perdem.
+pub fn iniciar(config: &Config) -> Option<WorkerGuard> {
+    let nivel: LevelFilter = config.log.parse().unwrap_or(LevelFilter::INFO);
+    let json = config.log_formato == "json";
+
+    let mut camadas = vec![camada(json, fmt::layer())];
+    let mut guarda = None;
+
+    if config.log_arquivos > 0 {
+        let pasta = config.dados.join("logs");
+        let arquivo = RollingFileAppender::builder()
+            .rotation(Rotation::DAILY)
+            .filename_prefix("validade")
+            .filename_suffix("log")
+            .max_log_files(config.log_arquivos)
+            .build(&pasta);
+
+        match arquivo {
+            Ok(arquivo) => {
+                let (escritor, g) = tracing_appender::non_blocking(arquivo);
+                camadas.push(camada(json, fmt::layer().with_writer(escritor).with_ansi(false)));
+                guarda = Some(g);
+            }
+            Err(e) => eprintln!("⚠️  Log em arquivo desligado: não foi possível usar {}: {}", pasta.display(), e),
+        }
+    }
+
+    tracing_subscriber::registry().with(camadas).with(nivel).init();
+    guarda
+}
+
+// ===========================================
+// REQUISIÇÕES
+// ===========================================
+
+/// Span de cada requisição. O `id` vem do cabeçalho `x-request-id`, que o
+/// cliente pode mandar ou o servidor gera, e volta na resposta.
+pub fn span_requisicao(requisicao: &Request<Body>) -> Span {
+    let id = requisicao.headers()
+        .get("x-request-id")
+        .and_then(|valor| valor.to_str().ok())
+        .unwrap_or("");
+
+    tracing::span!(
+        Level::INFO,
+        "requisicao",
+        id = %id,
+        metodo = %requisicao.method(),
+        caminho = %requisicao.uri(),
+    )
+}
@@ -0,0 +1,712 @@
+use axum::response::Json;
+use chrono::{Duration, Local, NaiveDate};
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::config;
+use crate::erro::{ErroApp, Query};
+use crate::init_db;
+
+
+// ===========================================
+// ESTRUTURAS DE DADOS
+// ===========================================
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RelatorioItem {
+    pub id: i32,
+    pub nome: String,
+    pub tipo: String,
+    pub total: i32,
+    pub prateleira: i32,
+    pub estoque: i32,
+    pub valor_em_risco_centavos: i64,
+    pub valor_perdido_centavos: i64,
+    pub lotes_rebaixados: i32,
+    /// Preço da última rebaixa aceita (somente em itens do tipo "lote")
+    pub preco_rebaixado_centavos: Option<i64>,
+    /// Validade mais próxima entre os lotes descendentes que ainda têm unidades
+    pub validade_mais_proxima: Option<String>,
+    #[schema(no_recursion)]
+    pub filhos: Vec<RelatorioItem>,
+}
+
+impl RelatorioItem {
+    fn novo(id: i32, nome: String, tipo: &str) -> Self {
+        RelatorioItem {
+            id,
+            nome,
+            tipo: tipo.to_string(),
+            total: 0,
+            prateleira: 0,
+            estoque: 0,
+            valor_em_risco_centavos: 0,
+            valor_perdido_centavos: 0,
+            lotes_rebaixados: 0,
+            preco_rebaixado_centavos: None,
+            validade_mais_proxima: None,
+            filhos: Vec::new(),
+        }
+    }
+
+    /// Soma as quantidades e valores do filho e o adiciona à lista de filhos.
+    /// O valor perdido não é somado aqui porque as baixas são contadas por produto.
+    fn adicionar_filho(&mut self, filho: RelatorioItem) {
+        self.total += filho.total;
+        self.prateleira += filho.prateleira;
+        self.estoque += filho.estoque;
+        self.valor_em_risco_centavos += filho.valor_em_risco_centavos;
+        self.lotes_rebaixados += filho.lotes_rebaixados;
+        if let Some(validade) = &filho.validade_mais_proxima {
+            if self.validade_mais_proxima.as_ref().is_none_or(|atual| validade < atual) {
+                self.validade_mais_proxima = Some(validade.clone());
+            }
+        }
+        self.filhos.push(filho);
+    }
+}
+
+/// Último nível da hierarquia que aparece em `filhos`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Profundidade {
+    Secao,
+    Tipo,
+    Produto,
+    #[default]
+    Lote,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ParametrosRelatorio {
+    /// Lotes que vencem em até este número de dias entram no valor em risco
+    pub dias_risco: Option<i32>,
+    pub secao: Option<i32>,
+    pub tipo: Option<i32>,
+    pub produto: Option<i32>,
+    pub profundidade: Option<Profundidade>,
+    #[serde(default)]
+    pub sem_vazios: bool,
+    /// Janela de validade dos lotes (AAAA-MM-DD, inclusiva)
+    pub validade_de: Option<String>,
+    pub validade_ate: Option<String>,
+    /// Atalho para `validade_ate` = hoje + N dias
+    pub vence_em_dias: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FiltroRelatorio {
+    pub dias_risco: i32,
+    pub secao: Option<i32>,
+    pub tipo: Option<i32>,
+    pub produto: Option<i32>,
+    pub profundidade: Profundidade,
+    /// Remove ramos sem nenhuma unidade em estoque
+    pub sem_vazios: bool,
+    pub validade_de: Option<String>,
+    pub validade_ate: Option<String>,
+}
+
+impl Default for FiltroRelatorio {
+    fn default() -> Self {
+        FiltroRelatorio {
+            dias_risco: config::atual().alertas.dias_risco,
+            secao: None,
+            tipo: None,
+            produto: None,
+            profundidade: Profundidade::Lote,
+            sem_vazios: false,
+            validade_de: None,
+            validade_ate: None,
+        }
+    }
+}
+
+impl ParametrosRelatorio {
+    /// Valida as datas e resolve `vence_em_dias`.
+    pub fn filtro(self) -> Result<FiltroRelatorio, ErroApp> {
+        let data_valida = |data: &Option<String>| {
+            data.as_deref().is_none_or(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").is_ok())
+        };
+        if !data_valida(&self.validade_de) || !data_valida(&self.validade_ate) {
+            return Err(ErroApp::invalido("data_invalida", "As datas devem estar no formato AAAA-MM-DD"));
+        }
+
+        let limite_dias = self.vence_em_dias
+            .map(|dias| {
+                Duration::try_days(dias)
+                    .and_then(|duracao| Local::now().date_naive().checked_add_signed(duracao))
+                    .map(|data| data.format("%Y-%m-%d").to_string())
+                    .ok_or_else(|| ErroApp::invalido(
+                        "vence_em_dias_invalido",
+                        format!("vence_em_dias fora do intervalo de datas suportado: {}", dias),
+                    ))
+            })
+            .transpose()?;
+        let validade_ate = match (self.validade_ate, limite_dias) {
+            (Some(ate), Some(limite)) => Some(ate.min(limite)),
+            (ate, limite) => ate.or(limite),
+        };
+
+        Ok(FiltroRelatorio {
+            dias_risco: self.dias_risco.unwrap_or(config::atual().alertas.dias_risco),
+            secao: self.secao,
+            tipo: self.tipo,
+            produto: self.produto,
+            profundidade: self.profundidade.unwrap_or_default(),
+            sem_vazios: self.sem_vazios,
+            validade_de: self.validade_de,
+            validade_ate,
+        })
+    }
+}
+
+// ===========================================
+// MONTAGEM DO RELATÓRIO
+// ===========================================
+
+/// Monta a hierarquia seção → tipo → produto → lote com um punhado de consultas
+/// sobre tabelas inteiras, agregando em memória.
+///
+/// Os filtros de seção, tipo e produto mantêm só o caminho até o item pedido.
+/// O filtro de validade age sobre os lotes antes das somas, então os totais
+/// refletem apenas os lotes da janela. A profundidade só corta `filhos`.
+pub fn montar_relatorio(conn: &Connection, filtro: &FiltroRelatorio) -> Result<Vec<RelatorioItem>, rusqlite::Error> {
+    // Baixas por produto (inclui as de lotes já apagados) e por lote
+    let mut perdas_produto: HashMap<i32, i64> = HashMap::new();
+    let mut perdas_lote: HashMap<i32, i64> = HashMap::new();
+    {
+        let mut stmt = conn.prepare(
+            "SELECT id_produto, id_lote, SUM(valor_centavos)
+             FROM baixas_valoradas
+             GROUP BY id_produto, id_lote"
+        )?;
+        let mut linhas = stmt.query([])?;
+        while let Some(row) = linhas.next()? {
+            let valor: i64 = row.get(2)?;
+            *perdas_produto.entry(row.get(0)?).or_default() += valor;
+            *perdas_lote.entry(row.get(1)?).or_default() += valor;
+        }
+    }
+
+    // Lotes agrupados pelo produto, na ordem de id
+    let mut lotes_por_produto: HashMap<i32, Vec<RelatorioItem>> = HashMap::new();
+    {
+        let mut stmt = conn.prepare(
+            "SELECT l.id, l.id_produto, l.validade, l.quantidade_total, l.quantidade_prateleira,
+                    CASE WHEN julianday(l.validade) - julianday('now') <= ?1
+                         THEN l.quantidade_total * COALESCE(pa.custo_centavos, 0) ELSE 0 END,
+                    ra.preco_centavos
+             FROM lotes l
+             LEFT JOIN precos_atuais pa ON l.id_produto = pa.id_produto
+             LEFT JOIN rebaixas_atuais ra ON l.id = ra.id_lote
+             WHERE (?2 IS NULL OR l.id_produto = ?2)
+               AND (?3 IS NULL OR date(l.validade) >= date(?3))
+               AND (?4 IS NULL OR date(l.validade) <= date(?4))
+             ORDER BY l.id"
+        )?;
+        let mut linhas = stmt.query(params![
+            filtro.dias_risco,
+            filtro.produto,
+            filtro.validade_de,
+            filtro.validade_ate
+        ])?;
+        while let Some(row) = linhas.next()? {
+            let id: i32 = row.get(0)?;
+            let validade: String = row.get(2)?;
+            let total: i32 = row.get(3)?;
+            let prateleira: i32 = row.get(4)?;
+            let preco_rebaixado: Option<i64> = row.get(6)?;
+
+            let mut lote = RelatorioItem::novo(id, format!("Lote {}", validade), "lote");
+            lote.total = total;
+            lote.prateleira = prateleira;
+            lote.estoque = total - prateleira;
+            lote.valor_em_risco_centavos = row.get(5)?;
+            lote.valor_perdido_centavos = perdas_lote.get(&id).copied().unwrap_or(0);
+            lote.lotes_rebaixados = if preco_rebaixado.is_some() { 1 } else { 0 };
+            lote.preco_rebaixado_centavos = preco_rebaixado;
+            if total > 0 {
+                lote.validade_mais_proxima = Some(validade);
+            }
+
+            lotes_por_produto.entry(row.get(1)?).or_default().push(lote);
+        }
+    }
+
+    // Ramo vazio (se pedido) ou que ficou sem filhos por causa de um filtro de nível abaixo
+    let descartar = |item: &RelatorioItem, filtrado_abaixo: bool| {
+        (filtro.sem_vazios && item.total == 0) || (filtrado_abaixo && item.filhos.is_empty())
+    };
+
+    let mut produtos_por_tipo: HashMap<i32, Vec<RelatorioItem>> = HashMap::new();
+    {
+        let mut stmt = conn.prepare(
+            "SELECT id, nome, id_tipo FROM produtos WHERE (?1 IS NULL OR id = ?1) ORDER BY id"
+        )?;
+        let mut linhas = stmt.query([filtro.produto])?;
+        while let Some(row) = linhas.next()? {
+            let id: i32 = row.get(0)?;
+            let mut produto = RelatorioItem::novo(id, row.get(1)?, "produto");
+            for lote in lotes_por_produto.remove(&id).unwrap_or_default() {
+                if !descartar(&lote, false) {
+                    produto.adicionar_filho(lote);
+                }
+            }
+            produto.valor_perdido_centavos = perdas_produto.get(&id).copied().unwrap_or(0);
+
+            if !descartar(&produto, false) {
+                if filtro.profundidade < Profundidade::Lote {
+                    produto.filhos.clear();
+                }
+                produtos_por_tipo.entry(row.get(2)?).or_default().push(produto);
+            }
+        }
+    }
+
+    let mut tipos_por_secao: HashMap<i32, Vec<RelatorioItem>> = HashMap::new();
+    {
+        let mut stmt = conn.prepare(
+            "SELECT id, nome, id_secao FROM tipos WHERE (?1 IS NULL OR id = ?1) ORDER BY id"
+        )?;
+        let mut linhas = stmt.query([filtro.tipo])?;
+        while let Some(row) = linhas.next()? {
+            let id: i32 = row.get(0)?;
+            let mut tipo = RelatorioItem::novo(id, row.get(1)?, "tipo");
+            for produto in produtos_por_tipo.remove(&id).unwrap_or_default() {
+                tipo.valor_perdido_centavos += produto.valor_perdido_centavos;
+                tipo.adicionar_filho(produto);
+            }
+
+            if !descartar(&tipo, filtro.produto.is_some()) {
+                if filtro.profundidade < Profundidade::Produto {
+                    tipo.filhos.clear();
+                }
+                tipos_por_secao.entry(row.get(2)?).or_default().push(tipo);
+            }
+        }
+    }
+
+    let mut resultado = Vec::new();
+    {
+        let mut stmt = conn.prepare(
+            "SELECT id, nome FROM secoes WHERE (?1 IS NULL OR id = ?1) ORDER BY id"
+        )?;
+        let mut linhas = stmt.query([filtro.secao])?;
+        while let Some(row) = linhas.next()? {
+            let id: i32 = row.get(0)?;
+            let mut secao = RelatorioItem::novo(id, row.get(1)?, "secao");
+            for tipo in tipos_por_secao.remove(&id).unwrap_or_default() {
+                secao.valor_perdido_centavos += tipo.valor_perdido_centavos;
+                secao.adicionar_filho(tipo);
+            }
+
+            if !descartar(&secao, filtro.tipo.is_some() || filtro.produto.is_some()) {
+                if filtro.profundidade < Profundidade::Tipo {
+                    secao.filhos.clear();
+                }
+                resultado.push(secao);
+            }
+        }
+    }
+
+    Ok(resultado)
+}
+
+// ===========================================
+// HANDLER DO RELATÓRIO
+// ===========================================
+
+#[utoipa::path(get, path = "/relatorio", tag = "relatorios", params(ParametrosRelatorio),
+    responses((status = 200, description = "Seções, com tipos, produtos e lotes em `filhos`", body = Vec<RelatorioItem>)))]
+pub async fn relatorio_handler(Query(parametros): Query<ParametrosRelatorio>) -> Result<Json<Vec<RelatorioItem>>, ErroApp> {
+    let filtro = parametros.filtro()?;
+    let conn = init_db()?;
+
+    let resultado = montar_relatorio(&conn, &filtro)?;
+
+    Ok(Json(resultado))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::params;
+    use std::time::Instant;
+
+    /// Implementação anterior, com uma consulta por seção, tipo e produto.
+    /// Fica aqui como referência para garantir que a saída não mudou.
+    fn relatorio_por_consultas_aninhadas(conn: &Connection, dias_risco: i32) -> Result<Vec<RelatorioItem>, rusqlite::Error> {
+        let mut stmt = conn.prepare(
+            "SELECT s.id, s.nome,
+                    COALESCE(SUM(l.quantidade_total), 0) as total,
+                    COALESCE(SUM(l.quantidade_prateleira), 0) as prateleira,
+                    COALESCE(SUM(CASE WHEN julianday(l.validade) - julianday('now') <= ?1
+                                      THEN l.quantidade_total * COALESCE(pa.custo_centavos, 0) END), 0) as em_risco,
+                    (SELECT COALESCE(SUM(bv.valor_centavos), 0)
+                     FROM baixas_valoradas bv
+                     JOIN produtos p2 ON p2.id = bv.id_produto
+                     JOIN tipos t2 ON t2.id = p2.id_tipo
+                     WHERE t2.id_secao = s.id) as perdido,
+                    COUNT(ra.id_lote) as rebaixados
+             FROM secoes s
+             LEFT JOIN tipos t ON s.id = t.id_secao
+             LEFT JOIN produtos p ON t.id = p.id_tipo
+             LEFT JOIN precos_atuais pa ON p.id = pa.id_produto
+             LEFT JOIN lotes l ON p.id = l.id_produto
+             LEFT JOIN rebaixas_atuais ra ON l.id = ra.id_lote
+             GROUP BY s.id"
+        )?;
+
+        let mut resultado = Vec::new();
+        let secoes = stmt.query_map([dias_risco], |row| {
+            Ok(RelatorioItem {
+                id: row.get(0)?,
+                nome: row.get(1)?,
+                tipo: "secao".to_string(),
+                total: row.get(2)?,
+                prateleira: row.get(3)?,
+                estoque: row.get::<_, i32>(2)? - row.get::<_, i32>(3)?,
+                valor_em_risco_centavos: row.get(4)?,
+                valor_perdido_centavos: row.get(5)?,
+                lotes_rebaixados: row.get(6)?,
+                preco_rebaixado_centavos: None,
+                validade_mais_proxima: None,
+                filhos: Vec::new(),
+            })
+        })?;
+
+        for secao in secoes {
+            let mut secao_item = secao?;
+
+            let mut stmt_tipos = conn.prepare(
+                "SELECT t.id, t.nome,
+                        COALESCE(SUM(l.quantidade_total), 0) as total,
+                        COALESCE(SUM(l.quantidade_prateleira), 0) as prateleira,
+                        COALESCE(SUM(CASE WHEN julianday(l.validade) - julianday('now') <= ?2
+                                          THEN l.quantidade_total * COALESCE(pa.custo_centavos, 0) END), 0) as em_risco,
+                        (SELECT COALESCE(SUM(bv.valor_centavos), 0)
+                         FROM baixas_valoradas bv
+                         JOIN produtos p2 ON p2.id = bv.id_produto
+                         WHERE p2.id_tipo = t.id) as perdido,
+                        COUNT(ra.id_lote) as rebaixados
+                 FROM tipos t
+                 LEFT JOIN produtos p ON t.id = p.id_tipo
+                 LEFT JOIN precos_atuais pa ON p.id = pa.id_produto
+                 LEFT JOIN lotes l ON p.id = l.id_produto
+                 LEFT JOIN rebaixas_atuais ra ON l.id = ra.id_lote
+                 WHERE t.id_secao = ?1
+                 GROUP BY t.id"
+            )?;
+
+            let tipos = stmt_tipos.query_map(params![secao_item.id, dias_risco], |row| {
+                Ok(RelatorioItem {
+                    id: row.get(0)?,
+                    nome: row.get(1)?,
+                    tipo: "tipo".to_string(),
+                    total: row.get(2)?,
+                    prateleira: row.get(3)?,
+                    estoque: row.get::<_, i32>(2)? - row.get::<_, i32>(3)?,
+                    valor_em_risco_centavos: row.get(4)?,
+                    valor_perdido_centavos: row.get(5)?,
+                    lotes_rebaixados: row.get(6)?,
+                    preco_rebaixado_centavos: None,
+                    validade_mais_proxima: None,
+                    filhos: Vec::new(),
+                })
+            })?;
+
+            for tipo in tipos {
+                let mut tipo_item = tipo?;
+
+                let mut stmt_produtos = conn.prepare(
+                    "SELECT p.id, p.nome,
+                            COALESCE(SUM(l.quantidade_total), 0) as total,
+                            COALESCE(SUM(l.quantidade_prateleira), 0) as prateleira,
+                            COALESCE(SUM(CASE WHEN julianday(l.validade) - julianday('now') <= ?2
+                                              THEN l.quantidade_total * COALESCE(pa.custo_centavos, 0) END), 0) as em_risco,
+                            (SELECT COALESCE(SUM(bv.valor_centavos), 0)
+                             FROM baixas_valoradas bv
+                             WHERE bv.id_produto = p.id) as perdido,
+                            COUNT(ra.id_lote) as rebaixados
+                     FROM produtos p
+                     LEFT JOIN precos_atuais pa ON p.id = pa.id_produto
+                     LEFT JOIN lotes l ON p.id = l.id_produto
+                     LEFT JOIN rebaixas_atuais ra ON l.id = ra.id_lote
+                     WHERE p.id_tipo = ?1
+                     GROUP BY p.id"
+                )?;
+
+                let produtos = stmt_produtos.query_map(params![tipo_item.id, dias_risco], |row| {
+                    Ok(RelatorioItem {
+                        id: row.get(0)?,
+                        nome: row.get(1)?,
+                        tipo: "produto".to_string(),
+                        total: row.get(2)?,
+                        prateleira: row.get(3)?,
+                        estoque: row.get::<_, i32>(2)? - row.get::<_, i32>(3)?,
+                        valor_em_risco_centavos: row.get(4)?,
+                        valor_perdido_centavos: row.get(5)?,
+                        lotes_rebaixados: row.get(6)?,
+                        preco_rebaixado_centavos: None,
+                        validade_mais_proxima: None,
+                        filhos: Vec::new(),
+                    })
+                })?;
+
+                for produto in produtos {
+                    let mut produto_item = produto?;
+
+                    let mut stmt_lotes = conn.prepare(
+                        "SELECT l.id, l.validade, l.quantidade_total, l.quantidade_prateleira,
+                                CASE WHEN julianday(l.validade) - julianday('now') <= ?2
+                                     THEN l.quantidade_total * COALESCE(pa.custo_centavos, 0) ELSE 0 END as em_risco,
+                                (SELECT COALESCE(SUM(bv.valor_centavos), 0)
+                                 FROM baixas_valoradas bv
+                                 WHERE bv.id_lote = l.id) as perdido,
+                                CASE WHEN ra.id_lote IS NULL THEN 0 ELSE 1 END as rebaixados,
+                                ra.preco_centavos
+                         FROM lotes l
+                         LEFT JOIN precos_atuais pa ON l.id_produto = pa.id_produto
+                         LEFT JOIN rebaixas_atuais ra ON l.id = ra.id_lote
+                         WHERE l.id_produto = ?1"
+                    )?;
+
+                    let lotes = stmt_lotes.query_map(params![produto_item.id, dias_risco], |row| {
+                        Ok(RelatorioItem {
+                            id: row.get(0)?,
+                            nome: format!("Lote {}", row.get::<_, String>(1)?),
+                            tipo: "lote".to_string(),
+                            total: row.get(2)?,
+                            prateleira: row.get(3)?,
+                            estoque: row.get::<_, i32>(2)? - row.get::<_, i32>(3)?,
+                            valor_em_risco_centavos: row.get(4)?,
+                            valor_perdido_centavos: row.get(5)?,
+                            lotes_rebaixados: row.get(6)?,
+                            preco_rebaixado_centavos: row.get(7)?,
+                            validade_mais_proxima: None,
+                            filhos: Vec::new(),
+                        })
+                    })?;
+
+                    for lote in lotes {
+                        produto_item.filhos.push(lote?);
+                    }
+
+                    tipo_item.filhos.push(produto_item);
+                }
+
+                secao_item.filhos.push(tipo_item);
+            }
+
+            resultado.push(secao_item);
+        }
+
+        Ok(resultado)
+    }
+
+    /// Banco em memória com `secoes * tipos * produtos * lotes` lotes, preços,
+    /// baixas e rebaixas espalhados, além de ramos vazios em todos os níveis.
+    fn banco_sintetico(secoes: i32, tipos: i32, produtos: i32, lotes: i32) -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        crate::criar_schema(&conn).unwrap();
+
+        let tx = conn.transaction().unwrap();
+        {
+            let mut secao_stmt = tx.prepare("INSERT INTO secoes (nome) VALUES (?1)").unwrap();
+            let mut tipo_stmt = tx.prepare("INSERT INTO tipos (nome, id_secao) VALUES (?1, ?2)").unwrap();
+            let mut produto_stmt = tx.prepare("INSERT INTO produtos (nome, id_tipo) VALUES (?1, ?2)").unwrap();
+            let mut preco_stmt = tx.prepare(
+                "INSERT INTO precos (id_produto, custo_centavos, preco_venda_centavos, vigente_desde)
+                 VALUES (?1, ?2, ?3, '2020-01-01')"
+            ).unwrap();
+            let mut lote_stmt = tx.prepare(
+                "INSERT INTO lotes (id_produto, validade, quantidade_total, quantidade_prateleira)
+                 VALUES (?1, date('now', ?2 || ' days'), ?3, ?4)"
+            ).unwrap();
+            let mut baixa_stmt = tx.prepare(
+                "INSERT INTO baixas (id_lote, id_produto, motivo, quantidade) VALUES (?1, ?2, 'vencido', ?3)"
+            ).unwrap();
+            let mut rebaixa_stmt = tx.prepare(
+                "INSERT INTO rebaixas (id_lote, desconto_percentual, preco_original_centavos, preco_centavos)
+                 VALUES (?1, 30, 1000, 700)"
+            ).unwrap();
+
+            for s in 0..=secoes {
+                secao_stmt.execute([format!("SECAO {}", s)]).unwrap();
+                let id_secao = tx.last_insert_rowid();
+                if s == secoes {
+                    continue; // seção vazia
+                }
+
+                for t in 0..=tipos {
+                    tipo_stmt.execute(params![format!("TIPO {}", t), id_secao]).unwrap();
+                    let id_tipo = tx.last_insert_rowid();
+                    if t == tipos {
+                        continue; // tipo vazio
+                    }
+
+                    for p in 0..=produtos {
+                        produto_stmt.execute(params![format!("PRODUTO {}", p), id_tipo]).unwrap();
+                        let id_produto = tx.last_insert_rowid();
+                        if p == produtos {
+                            continue; // produto sem lotes
+                        }
+                        if id_produto % 2 == 0 {
+                            preco_stmt.execute(params![id_produto, 100 + id_produto % 500, 250 + id_produto % 700]).unwrap();
+                        }
+
+                        for l in 0..lotes {
+                            let dias = (id_produto + l as i64 * 7) % 90 - 10;
+                            let total = 10 + (id_produto + l as i64) % 50;
+                            lote_stmt.execute(params![id_produto, dias.to_string(), total, total / 2]).unwrap();
+                            let id_lote = tx.last_insert_rowid();
+
+                            if id_lote % 7 == 0 {
+                                baixa_stmt.execute(params![id_lote, id_produto, 1 + id_lote % 3]).unwrap();
+                            }
+                            if id_lote % 11 == 0 {
+                                rebaixa_stmt.execute([id_lote]).unwrap();
+                            }
+                        }
+                    }
+                }
+            }
+
+            // Baixa de um lote que já foi apagado continua contando para o produto
+            baixa_stmt.execute(params![999_999, 2, 5]).unwrap();
+        }
+        tx.commit().unwrap();
+
+        conn
+    }
+
+    /// A implementação anterior não calculava a validade mais próxima.
+    fn sem_validade_mais_proxima(itens: Vec<RelatorioItem>) -> Vec<RelatorioItem> {
+        itens.into_iter()
+            .map(|item| RelatorioItem {
+                validade_mais_proxima: None,
+                filhos: sem_validade_mais_proxima(item.filhos),
+                ..item
+            })
+            .collect()
+    }
+
+    fn json(itens: &[RelatorioItem]) -> serde_json::Value {
+        serde_json::to_value(itens).unwrap()
+    }
+
+    #[test]
+    fn saida_igual_a_implementacao_anterior() {
+        let conn = banco_sintetico(3, 3, 4, 3);
+
+        let novo = sem_validade_mais_proxima(montar_relatorio(&conn, &FiltroRelatorio::default()).unwrap());
+        let antigo = sem_validade_mais_proxima(relatorio_por_consultas_aninhadas(&conn, 7).unwrap());
+
+        assert_eq!(json(&novo), json(&antigo));
+    }
+
+    #[test]
+    fn filtros_profundidade_e_validade_mais_proxima() {
+        let conn = banco_sintetico(2, 2, 2, 3);
+
+        // Só o caminho até o tipo 2, cortando nos produtos
+        let filtro = FiltroRelatorio {
+            tipo: Some(2),
+            profundidade: Profundidade::Produto,
+            ..FiltroRelatorio::default()
+        };
+        let relatorio = montar_relatorio(&conn, &filtro).unwrap();
+        assert_eq!(relatorio.len(), 1);
+        assert_eq!(relatorio[0].filhos.len(), 1);
+        assert_eq!(relatorio[0].filhos[0].id, 2);
+        assert!(relatorio[0].filhos[0].filhos.iter().all(|p| p.filhos.is_empty()));
+        assert_eq!(relatorio[0].total, relatorio[0].filhos[0].total);
+
+        // Ramos vazios somem: seção, tipo e produto sem lotes
+        let filtro = FiltroRelatorio { sem_vazios: true, ..FiltroRelatorio::default() };
+        let relatorio = montar_relatorio(&conn, &filtro).unwrap();
+        assert_eq!(relatorio.len(), 2);
+        assert!(relatorio.iter().all(|s| s.filhos.len() == 2 && s.filhos.iter().all(|t| t.filhos.len() == 2)));
+
+        // A janela de validade limita os lotes e as somas
+        let hoje = Local::now().date_naive().format("%Y-%m-%d").to_string();
+        let filtro = FiltroRelatorio {
+            validade_ate: Some(hoje.clone()),
+            sem_vazios: true,
+            ..FiltroRelatorio::default()
+        };
+        let relatorio = montar_relatorio(&conn, &filtro).unwrap();
+        let vencendo: i32 = conn.query_row(
+            "SELECT COALESCE(SUM(quantidade_total), 0) FROM lotes WHERE validade <= ?1",
+            [&hoje],
+            |row| row.get(0),
+        ).unwrap();
+        assert_eq!(relatorio.iter().map(|s| s.total).sum::<i32>(), vencendo);
+
+        // A validade mais próxima de cada seção é a menor entre seus lotes
+        let relatorio = montar_relatorio(&conn, &FiltroRelatorio::default()).unwrap();
+        for secao in relatorio.iter().filter(|s| s.total > 0) {
+            let menor: String = conn.query_row(
+                "SELECT MIN(l.validade) FROM lotes l
+                 JOIN produtos p ON p.id = l.id_produto
+                 JOIN tipos t ON t.id = p.id_tipo
+                 WHERE t.id_secao = ?1 AND l.quantidade_total > 0",
+                [secao.id],
+                |row| row.get(0),
+            ).unwrap();
+            assert_eq!(secao.validade_mais_proxima.as_deref(), Some(menor.as_str()));
+        }
+    }
+
+    #[test]
+    fn vence_em_dias_fora_do_calendario_e_recusado() {
+        let parametros = |dias: i64| -> ParametrosRelatorio {
+            serde_json::from_value(serde_json::json!({ "vence_em_dias": dias })).unwrap()
+        };
+
+        let hoje = Local::now().date_naive();
+        let filtro = parametros(30).filtro().unwrap();
+        assert_eq!(filtro.validade_ate, Some((hoje + Duration::days(30)).format("%Y-%m-%d").to_string()));
+
+        for dias in [i64::MAX, i64::MIN, 10_000_000_000, 365 * 300_000] {
+            assert_eq!(parametros(dias).filtro().unwrap_err().codigo(), "vence_em_dias_invalido", "{}", dias);
+        }
+    }
+
+    #[test]
+    fn relatorio_com_50k_lotes() {
+        // 10 seções x 10 tipos x 50 produtos x 10 lotes = 50.000 lotes
+        let conn = banco_sintetico(10, 10, 50, 10);
+        let lotes: i64 = conn.query_row("SELECT COUNT(*) FROM lotes", [], |row| row.get(0)).unwrap();
+        assert_eq!(lotes, 50_000);
+
+        let relatorio = montar_relatorio(&conn, &FiltroRelatorio::default()).unwrap();
+
+        assert_eq!(relatorio.len(), 11);
+        assert_eq!(relatorio.iter().map(|s| s.filhos.len()).sum::<usize>(), 110);
+    }
+
+    /// Compara com a implementação anterior no banco de 50k lotes. Demora cerca
+    /// de um minuto em modo debug, por isso só roda com `cargo test -- --ignored`.
+    /// Os tempos só são impressos (veja com `--nocapture`): depender deles para
+    /// passar deixaria o teste instável em máquina carregada.
+    #[test]
+    #[ignore]
+    fn benchmark_50k_lotes_contra_implementacao_anterior() {
+        let conn = banco_sintetico(10, 10, 50, 10);
+
+        let inicio = Instant::now();
+        let novo = montar_relatorio(&conn, &FiltroRelatorio::default()).unwrap();
+        let tempo_novo = inicio.elapsed();
+
+        let inicio = Instant::now();
+        let antigo = relatorio_por_consultas_aninhadas(&conn, 7).unwrap();
+        let tempo_antigo = inicio.elapsed();
+
+        assert_eq!(json(&sem_validade_mais_proxima(novo)), json(&sem_validade_mais_proxima(antigo)));
+        println!("relatório levou {:?} (antes: {:?})", tempo_novo, tempo_antigo);
+    }
+}
@@ -0,0 +1,253 @@
+use axum::response::Json;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::config;
+use crate::erro::{Entrada, ErroApp, Path as AxumPath, Query};
+use crate::init_db;
+use crate::previsao::media_diaria_por_produto;
+
+// ===========================================
+// ESTRUTURAS DE DADOS
+// ===========================================
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct EstoqueMinimo {
+    pub id_produto: i32,
+    pub minimo_prateleira: i32,
+    pub minimo_total: i32,
+    pub vida_util_dias: Option<i32>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct EstoqueMinimoData {
+    pub minimo_prateleira: i32,
+    pub minimo_total: i32,
+    pub vida_util_dias: Option<i32>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AlertaEstoque {
+    pub id_produto: i32,
+    pub produto: String,
+    pub secao: String,
+    pub tipo: String,
+    pub total: i32,
+    pub prateleira: i32,
+    pub estoque: i32,
+    pub minimo_prateleira: i32,
+    pub minimo_total: i32,
+    pub media_diaria_vendas: f64,
+    pub vida_util_dias: Option<i32>,
+    /// Unidades a levar do estoque para a prateleira
+    pub repor_prateleira: i32,
+    /// Unidades a pedir ao fornecedor
+    pub quantidade_sugerida: i32,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ParametrosAlerta {
+    /// Quantos dias de vendas entram na média diária
+    pub janela_dias: Option<i32>,
+    /// Quantos dias de venda o pedido deve cobrir, limitado pela vida útil
+    pub dias_cobertura: Option<i32>,
+}
+
+// ===========================================
+// CÁLCULO
+// ===========================================
+
+/// Quantidade a pedir para voltar ao mínimo e cobrir a demanda do período.
+///
+/// O período de cobertura nunca passa da vida útil do produto, para não
+/// pedir mais perecíveis do que dá para vender antes de vencerem.
+fn quantidade_a_pedir(
+    total: i32,
+    minimo_total: i32,
+    media_diaria: f64,
+    dias_cobertura: i32,
+    vida_util_dias: Option<i32>,
+) -> i32 {
+    let horizonte = match vida_util_dias {
+        Some(vida_util) => dias_cobertura.min(vida_util.max(0)),
+        None => dias_cobertura,
+    };
+    let demanda = (media_diaria * horizonte as f64).ceil() as i32;
+
+    // `as i32` já satura; somas também, para coberturas enormes não estourarem
+    minimo_total.saturating_add(demanda).saturating_sub(total).max(0)
+}
+
+// ===========================================
+// HANDLERS DE ESTOQUE MÍNIMO
+// ===========================================
+
+#[utoipa::path(get, path = "/produtos/{id}/minimos", tag = "reposicao", params(("id" = i32, Path, description = "Id do produto")),
+    responses((status = 200, body = EstoqueMinimo)))]
+pub async fn obter_minimo_handler(AxumPath(produto_id): AxumPath<i32>) -> Result<Json<EstoqueMinimo>, ErroApp> {
+    let conn = init_db()?;
+
+    let minimo = conn.query_row(
+        "SELECT id_produto, minimo_prateleira, minimo_total, vida_util_dias
+         FROM estoque_minimo WHERE id_produto = ?1",
+        [produto_id],
+        |row| {
+            Ok(EstoqueMinimo {
+                id_produto: row.get(0)?,
+                minimo_prateleira: row.get(1)?,
+                minimo_total: row.get(2)?,
+                vida_util_dias: row.get(3)?,
+            })
+        }
+    ).optional()?.ok_or(ErroApp::NaoEncontrado("Estoque mínimo não definido para este produto"))?;
+
+    Ok(Json(minimo))
+}
+
+/// Define (ou substitui) os níveis mínimos de um produto.
+#[utoipa::path(post, path = "/produtos/{id}/minimos", tag = "reposicao", params(("id" = i32, Path, description = "Id do produto")), request_body(content((EstoqueMinimoData = "application/json"), (EstoqueMinimoData = "application/x-www-form-urlencoded"))),
+    responses((status = 200, body = EstoqueMinimo)))]
+pub async fn definir_minimo_handler(
+    AxumPath(produto_id): AxumPath<i32>,
+    Entrada(minimo): Entrada<EstoqueMinimoData>
+) -> Result<Json<EstoqueMinimo>, ErroApp> {
+    let conn = init_db()?;
+
+    if minimo.minimo_prateleira > minimo.minimo_total {
+        return Err(ErroApp::invalido(
+            "prateleira_maior_que_total",
+            "O mínimo na prateleira não pode ser maior que o mínimo total",
+        ));
+    }
+
+    conn.query_row(
+        "SELECT 1 FROM produtos WHERE id = ?1",
+        [produto_id],
+        |_| Ok(())
+    ).optional()?.ok_or(ErroApp::NaoEncontrado("Produto não encontrado"))?;
+
+    let minimo = conn.query_row(
+        "INSERT INTO estoque_minimo (id_produto, minimo_prateleira, minimo_total, vida_util_dias)
+         VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT(id_produto) DO UPDATE SET
+             minimo_prateleira = excluded.minimo_prateleira,
+             minimo_total = excluded.minimo_total,
+             vida_util_dias = excluded.vida_util_dias
+         RETURNING id_produto, minimo_prateleira, minimo_total, vida_util_dias",
+        params![produto_id, minimo.minimo_prateleira, minimo.minimo_total, minimo.vida_util_dias],
+        |row| Ok(EstoqueMinimo {
+            id_produto: row.get(0)?,
+            minimo_prateleira: row.get(1)?,
+            minimo_total: row.get(2)?,
+            vida_util_dias: row.get(3)?,
+        })
+    )?;
+
+    Ok(Json(minimo))
+}
+
+/// Produtos abaixo do mínimo na prateleira ou no total. Lotes vencidos não contam.
+#[utoipa::path(get, path = "/alertas/estoque", tag = "reposicao", params(ParametrosAlerta),
+    responses((status = 200, body = Vec<AlertaEstoque>)))]
+pub async fn alertas_estoque_handler(Query(parametros): Query<ParametrosAlerta>) -> Result<Json<Vec<AlertaEstoque>>, ErroApp> {
+    let conn = init_db()?;
+    let janela = parametros.janela_dias.unwrap_or(config::atual().alertas.janela_vendas_dias);
+    let dias_cobertura = parametros.dias_cobertura.unwrap_or(config::atual().alertas.dias_cobertura);
+
+    if janela <= 0 {
+        return Err(ErroApp::invalido("janela_invalida", "A janela de vendas deve ser maior que zero"));
+    }
+    if dias_cobertura < 0 {
+        return Err(ErroApp::invalido("cobertura_invalida", "Os dias de cobertura não podem ser negativos"));
+    }
+
+    let medias = media_diaria_por_produto(&conn, janela)?;
+
+    let mut stmt = conn.prepare(
+        "SELECT p.id, p.nome, s.nome, t.nome,
+                COALESCE(SUM(l.quantidade_total), 0) as total,
+                COALESCE(SUM(l.quantidade_prateleira), 0) as prateleira,
+                em.minimo_prateleira, em.minimo_total, em.vida_util_dias
+         FROM estoque_minimo em
+         JOIN produtos p ON p.id = em.id_produto
+         JOIN tipos t ON t.id = p.id_tipo
+         JOIN secoes s ON s.id = t.id_secao
+         LEFT JOIN lotes l ON p.id = l.id_produto
+              AND julianday(l.validade) >= julianday(date('now', 'localtime'))
+         GROUP BY p.id
+         HAVING total < em.minimo_total OR prateleira < em.minimo_prateleira
+         ORDER BY s.nome, t.nome, p.nome"
+    )?;
+
+    let alertas = stmt.query_map([], |row| {
+        let id_produto: i32 = row.get(0)?;
+        let total: i32 = row.get(4)?;
+        let prateleira: i32 = row.get(5)?;
+        let minimo_prateleira: i32 = row.get(6)?;
+        let minimo_total: i32 = row.get(7)?;
+        let vida_util_dias: Option<i32> = row.get(8)?;
+        let media = medias.get(&id_produto).copied().unwrap_or(0.0);
+        let estoque = total - prateleira;
+
+        Ok(AlertaEstoque {
+            id_produto,
+            produto: row.get(1)?,
+            secao: row.get(2)?,
+            tipo: row.get(3)?,
+            total,
+            prateleira,
+            estoque,
+            minimo_prateleira,
+            minimo_total,
+            media_diaria_vendas: media,
+            vida_util_dias,
+            repor_prateleira: (minimo_prateleira - prateleira).clamp(0, estoque.max(0)),
+            quantidade_sugerida: quantidade_a_pedir(total, minimo_total, media, dias_cobertura, vida_util_dias),
+        })
+    })?;
+
+    let mut resultado = Vec::new();
+    for alerta in alertas {
+        resultado.push(alerta?);
+    }
+
+    Ok(Json(resultado))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pede_o_minimo_mais_a_demanda_do_periodo() {
+        // 10 de mínimo + 2,5/dia por 7 dias = 17,5 → 18, menos os 8 em estoque
+        assert_eq!(quantidade_a_pedir(8, 10, 2.5, 7, None), 20);
+        // Sem vendas, só completa o mínimo
+        assert_eq!(quantidade_a_pedir(3, 10, 0.0, 30, None), 7);
+        // Demanda fracionária arredonda para cima
+        assert_eq!(quantidade_a_pedir(0, 0, 0.1, 1, None), 1);
+    }
+
+    #[test]
+    fn nao_pede_quando_o_estoque_ja_cobre() {
+        assert_eq!(quantidade_a_pedir(50, 10, 2.0, 7, None), 0);
+        assert_eq!(quantidade_a_pedir(24, 10, 2.0, 7, None), 0);
+    }
+
+    #[test]
+    fn cobertura_limitada_pela_vida_util() {
+        // 30 dias pedidos, mas o produto só dura 5: 5 × 4 = 20
+        assert_eq!(quantidade_a_pedir(0, 0, 4.0, 30, Some(5)), 20);
+        // Vida útil maior que a cobertura não muda nada
+        assert_eq!(quantidade_a_pedir(0, 0, 4.0, 3, Some(5)), 12);
+        // Vida útil inválida conta como zero dias: só o mínimo
+        assert_eq!(quantidade_a_pedir(0, 6, 4.0, 30, Some(-1)), 6);
+    }
+
+    #[test]
+    fn valores_extremos_nao_estouram() {
+        assert_eq!(quantidade_a_pedir(0, i32::MAX, 1000.0, i32::MAX, None), i32::MAX);
+        assert_eq!(quantidade_a_pedir(i32::MAX, 0, 0.0, 0, None), 0);
+    }
+}
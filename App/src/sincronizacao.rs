@@ -0,0 +1,506 @@
+use axum::response::Json;
+use chrono::{DateTime, Local};
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::erro::{ErroApp, Json as JsonEntrada};
+use crate::eventos::{self, Evento};
+use crate::{abastecer, criar_lote, init_db, lote_da_linha, vender, Lote, LoteData, Produto, Secao, Tipo, COLUNAS_LOTE};
+
+/// Maior número de operações aceito em uma sincronização; filas maiores vão em partes.
+const MAXIMO_OPERACOES: usize = 1000;
+
+/// Por quanto tempo o resultado de uma operação fica guardado. Reenviar a
+/// mesma operação dentro deste prazo não a aplica de novo.
+const DIAS_GUARDANDO_OPERACOES: i32 = 90;
+
+/// Tabelas acompanhadas pelo registro de alterações.
+const TABELAS_SINCRONIZADAS: [&str; 4] = ["secoes", "tipos", "produtos", "lotes"];
+
+// ===========================================
+// ESTRUTURAS DE DADOS
+// ===========================================
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct PedidoSincronizacao {
+    /// `token` devolvido pela sincronização anterior; sem ele, vem o estoque inteiro
+    pub desde: Option<i64>,
+    /// Operações feitas sem rede, na ordem em que foram feitas
+    #[serde(default)]
+    pub operacoes: Vec<Operacao>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct Operacao {
+    /// Gerado pelo aparelho (ex.: UUID). Reenviar o mesmo id não aplica a operação de novo
+    pub id: String,
+    /// Quando a operação foi feita no aparelho (RFC 3339); vira a data da venda
+    pub momento: String,
+    #[serde(flatten)]
+    pub acao: Acao,
+}
+
+/// O lote de `vender` e `abastecer` é `id_lote` (já sincronizado) ou
+/// `lote_operacao`, o id da operação `criar_lote` que o criou.
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(tag = "acao", rename_all = "snake_case")]
+pub enum Acao {
+    Vender {
+        id_lote: Option<i32>,
+        lote_operacao: Option<String>,
+        quantidade: i32,
+    },
+    Abastecer {
+        id_lote: Option<i32>,
+        lote_operacao: Option<String>,
+        quantidade: i32,
+    },
+    CriarLote(LoteData),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EstadoOperacao {
+    Aplicada,
+    /// Não pôde ser aplicada (ex.: `estoque_insuficiente`); não adianta reenviar
+    Conflito,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ResultadoOperacao {
+    pub id: String,
+    pub estado: EstadoOperacao,
+    /// A operação já tinha sido recebida antes; este é o resultado daquela vez
+    pub repetida: bool,
+    /// Lote afetado (para `criar_lote`, o id dado pelo servidor)
+    pub id_lote: Option<i32>,
+    /// Código estável do conflito, como nas respostas de erro
+    pub codigo: Option<String>,
+    pub mensagem: Option<String>,
+}
+
+/// Registros criados ou alterados desde o token, no estado atual, e ids dos apagados.
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct Alteracoes {
+    pub secoes: Vec<Secao>,
+    pub tipos: Vec<Tipo>,
+    pub produtos: Vec<Produto>,
+    pub lotes: Vec<Lote>,
+    pub apagados: Apagados,
+}
+
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct Apagados {
+    pub secoes: Vec<i32>,
+    pub tipos: Vec<i32>,
+    pub produtos: Vec<i32>,
+    pub lotes: Vec<i32>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RespostaSincronizacao {
+    /// Um por operação enviada, na mesma ordem
+    pub resultados: Vec<ResultadoOperacao>,
+    /// Mandar como `desde` na próxima sincronização
+    pub token: i64,
+    pub alteracoes: Alteracoes,
+}
+
+// ===========================================
+// BANCO DE DADOS
+// ===========================================
+
+/// Registro de alterações (uma linha por registro, com a versão da última
+/// mudança) e resultados das operações já recebidas. Gatilhos mantêm o
+/// registro em dia, qualquer que seja o caminho da escrita.
+pub fn criar_tabelas(conn: &Connection) -> Result<(), rusqlite::Error> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS alteracoes (
+             versao INTEGER PRIMARY KEY AUTOINCREMENT,
+             tabela TEXT NOT NULL,
+             id_registro INTEGER NOT NULL
+         );
+         CREATE UNIQUE INDEX IF NOT EXISTS idx_alteracoes_registro ON alteracoes (tabela, id_registro);
+
+         CREATE TABLE IF NOT EXISTS operacoes_sincronizadas (
+             id TEXT PRIMARY KEY,
+             estado TEXT NOT NULL CHECK (estado IN ('aplicada', 'conflito')),
+             id_lote INTEGER,
+             codigo TEXT,
+             mensagem TEXT,
+             recebida_em TEXT NOT NULL DEFAULT (datetime('now', 'localtime'))
+         );",
+    )?;
+
+    let registrar = |nome: &str, evento: &str, tabela: &str, id: &str| {
+        format!(
+            "CREATE TRIGGER IF NOT EXISTS {nome} AFTER {evento} ON {tabela} BEGIN
+                 INSERT OR REPLACE INTO alteracoes (tabela, id_registro) VALUES ('{alterada}', {id});
+             END;",
+            alterada = if tabela == "precos" { "produtos" } else { tabela },
+        )
+    };
+
+    let mut gatilhos = String::new();
+    for tabela in TABELAS_SINCRONIZADAS {
+        gatilhos += &registrar(&format!("alteracao_{}_inserido", tabela), "INSERT", tabela, "NEW.id");
+        gatilhos += &registrar(&format!("alteracao_{}_alterado", tabela), "UPDATE", tabela, "NEW.id");
+        gatilhos += &registrar(&format!("alteracao_{}_apagado", tabela), "DELETE", tabela, "OLD.id");
+    }
+    // O preço vem junto com o produto
+    gatilhos += &registrar("alteracao_precos_inserido", "INSERT", "precos", "NEW.id_produto");
+
+    conn.execute_batch(&gatilhos)
+}
+
+// ===========================================
+// SINCRONIZAÇÃO
+// ===========================================
+
+/// Aplica as operações uma a uma, cada uma na sua transação junto com o
+/// registro do resultado, e depois lê as alterações desde `desde`.
+/// Um erro interno interrompe o pedido; as operações já aplicadas ficam
+/// registradas, então reenviar o pedido inteiro é seguro.
+pub fn sincronizar(conn: &mut Connection, pedido: PedidoSincronizacao) -> Result<RespostaSincronizacao, ErroApp> {
+    if pedido.operacoes.len() > MAXIMO_OPERACOES {
+        return Err(ErroApp::invalido(
+            "operacoes_demais",
+            format!("Envie no máximo {} operações por vez", MAXIMO_OPERACOES),
+        ));
+    }
+
+    conn.execute(
+        "DELETE FROM operacoes_sincronizadas WHERE recebida_em < datetime('now', 'localtime', ?1)",
+        [format!("-{} days", DIAS_GUARDANDO_OPERACOES)],
+    )?;
+
+    let mut resultados = Vec::with_capacity(pedido.operacoes.len());
+    for operacao in pedido.operacoes {
+        resultados.push(processar(conn, operacao)?);
+    }
+
+    let tx = conn.transaction()?;
+    let token = tx.query_row("SELECT COALESCE(MAX(versao), 0) FROM alteracoes", [], |row| row.get(0))?;
+    let alteracoes = alteracoes_desde(&tx, pedido.desde)?;
+    tx.commit()?;
+
+    Ok(RespostaSincronizacao { resultados, token, alteracoes })
+}
+
+fn processar(conn: &mut Connection, operacao: Operacao) -> Result<ResultadoOperacao, ErroApp> {
+    let Operacao { id, momento, acao } = operacao;
+
+    // IMMEDIATE reserva a escrita já na consulta: dois envios simultâneos
+    // da mesma operação não passam os dois pela checagem
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    if let Some(anterior) = resultado_registrado(&tx, &id)? {
+        return Ok(anterior);
+    }
+
+    match aplicar(&tx, &momento, acao) {
+        Ok((lote, evento)) => {
+            let resultado = ResultadoOperacao {
+                id,
+                estado: EstadoOperacao::Aplicada,
+                repetida: false,
+                id_lote: Some(lote.id),
+                codigo: None,
+                mensagem: None,
+            };
+            registrar_resultado(&tx, &resultado)?;
+            tx.commit()?;
+            eventos::publicar(evento);
+            Ok(resultado)
+        }
+        Err(ErroApp::Interno(detalhe)) => Err(ErroApp::Interno(detalhe)),
+        Err(conflito) => {
+            // Um conflito pode ter sido detectado depois de alguma escrita:
+            // desfaz tudo e grava só o resultado, numa transação nova
+            tx.rollback()?;
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            if let Some(anterior) = resultado_registrado(&tx, &id)? {
+                return Ok(anterior);
+            }
+            let resultado = ResultadoOperacao {
+                id,
+                estado: EstadoOperacao::Conflito,
+                repetida: false,
+                id_lote: None,
+                codigo: Some(conflito.codigo().to_string()),
+                mensagem: Some(conflito.mensagem().to_string()),
+            };
+            registrar_resultado(&tx, &resultado)?;
+            tx.commit()?;
+            Ok(resultado)
+        }
+    }
+}
+
+fn registrar_resultado(conn: &Connection, resultado: &ResultadoOperacao) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO operacoes_sincronizadas (id, estado, id_lote, codigo, mensagem) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            resultado.id,
+            match resultado.estado {
+                EstadoOperacao::Aplicada => "aplicada",
+                EstadoOperacao::Conflito => "conflito",
+            },
+            resultado.id_lote,
+            resultado.codigo,
+            resultado.mensagem
+        ],
+    )?;
+
+    Ok(())
+}
+
+fn aplicar(conn: &Connection, momento: &str, acao: Acao) -> Result<(Lote, Evento), ErroApp> {
+    let momento = DateTime::parse_from_rfc3339(momento)
+        .map_err(|_| ErroApp::invalido("data_invalida", "O momento deve estar no formato RFC 3339"))?
+        .with_timezone(&Local);
+
+    let lote = match acao {
+        Acao::Vender { id_lote, lote_operacao, quantidade } => {
+            // Relógio do aparelho adiantado não registra venda no futuro
+            let data = momento.min(Local::now()).format("%Y-%m-%d %H:%M:%S").to_string();
+            let id = lote_referido(conn, id_lote, lote_operacao.as_deref())?;
+            vender(conn, id, quantidade, Some(&data))?
+        }
+        Acao::Abastecer { id_lote, lote_operacao, quantidade } => {
+            let id = lote_referido(conn, id_lote, lote_operacao.as_deref())?;
+            abastecer(conn, id, quantidade)?
+        }
+        Acao::CriarLote(dados) => {
+            let lote = criar_lote(conn, dados)?;
+            let evento = Evento::LoteCriado { id: lote.id, id_produto: lote.id_produto };
+            return Ok((lote, evento));
+        }
+    };
+
+    let evento = Evento::LoteAlterado { id: lote.id, id_produto: lote.id_produto };
+    Ok((lote, evento))
+}
+
+fn lote_referido(conn: &Connection, id_lote: Option<i32>, lote_operacao: Option<&str>) -> Result<i32, ErroApp> {
+    match (id_lote, lote_operacao) {
+        (Some(id), _) => Ok(id),
+        (None, Some(operacao)) => conn.query_row(
+            "SELECT id_lote FROM operacoes_sincronizadas WHERE id = ?1 AND id_lote IS NOT NULL",
+            [operacao],
+            |row| row.get(0)
+        ).optional()?.ok_or(ErroApp::NaoEncontrado("Lote não encontrado")),
+        (None, None) => Err(ErroApp::invalido("lote_obrigatorio", "Informe id_lote ou lote_operacao")),
+    }
+}
+
+fn resultado_registrado(conn: &Connection, id: &str) -> Result<Option<ResultadoOperacao>, rusqlite::Error> {
+    conn.query_row(
+        "SELECT estado, id_lote, codigo, mensagem FROM operacoes_sincronizadas WHERE id = ?1",
+        [id],
+        |row| Ok(ResultadoOperacao {
+            id: id.to_string(),
+            estado: if row.get::<_, String>(0)? == "aplicada" { EstadoOperacao::Aplicada } else { EstadoOperacao::Conflito },
+            repetida: true,
+            id_lote: row.get(1)?,
+            codigo: row.get(2)?,
+            mensagem: row.get(3)?,
+        })
+    ).optional()
+}
+
+/// Sem `desde`, todos os registros; com, só os alterados depois dele.
+fn alteracoes_desde(conn: &Connection, desde: Option<i64>) -> Result<Alteracoes, rusqlite::Error> {
+    let alterados = |tabela: &str, alias: &str| {
+        format!(
+            "(?1 IS NULL OR {}.id IN (SELECT id_registro FROM alteracoes WHERE tabela = '{}' AND versao > ?1))",
+            alias, tabela
+        )
+    };
+
+    let mut alteracoes = Alteracoes::default();
+
+    let mut stmt = conn.prepare(&format!("SELECT s.id, s.nome FROM secoes s WHERE {} ORDER BY s.id", alterados("secoes", "s")))?;
+    for secao in stmt.query_map([desde], |row| Ok(Secao { id: row.get(0)?, nome: row.get(1)? }))? {
+        alteracoes.secoes.push(secao?);
+    }
+
+    let mut stmt = conn.prepare(&format!("SELECT t.id, t.nome, t.id_secao FROM tipos t WHERE {} ORDER BY t.id", alterados("tipos", "t")))?;
+    for tipo in stmt.query_map([desde], |row| Ok(Tipo { id: row.get(0)?, nome: row.get(1)?, id_secao: row.get(2)? }))? {
+        alteracoes.tipos.push(tipo?);
+    }
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT p.id, p.nome, p.id_tipo, p.codigo_barras, pa.custo_centavos, pa.preco_venda_centavos
+         FROM produtos p LEFT JOIN precos_atuais pa ON pa.id_produto = p.id
+         WHERE {} ORDER BY p.id",
+        alterados("produtos", "p")
+    ))?;
+    let produtos = stmt.query_map([desde], |row| {
+        Ok(Produto {
+            id: row.get(0)?,
+            nome: row.get(1)?,
+            id_tipo: row.get(2)?,
+            codigo_barras: row.get(3)?,
+            custo_centavos: row.get(4)?,
+            preco_venda_centavos: row.get(5)?,
+        })
+    })?;
+    for produto in produtos {
+        alteracoes.produtos.push(produto?);
+    }
+
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM lotes l WHERE {} ORDER BY l.id", COLUNAS_LOTE, alterados("lotes", "l")))?;
+    for lote in stmt.query_map([desde], lote_da_linha)? {
+        alteracoes.lotes.push(lote?);
+    }
+
+    if desde.is_some() {
+        for tabela in TABELAS_SINCRONIZADAS {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT a.id_registro FROM alteracoes a
+                 WHERE a.tabela = '{0}' AND a.versao > ?1
+                   AND NOT EXISTS (SELECT 1 FROM {0} x WHERE x.id = a.id_registro)
+                 ORDER BY a.id_registro",
+                tabela
+            ))?;
+            let ids = stmt.query_map([desde], |row| row.get(0))?.collect::<Result<Vec<i32>, _>>()?;
+            let apagados = match tabela {
+                "secoes" => &mut alteracoes.apagados.secoes,
+                "tipos" => &mut alteracoes.apagados.tipos,
+                "produtos" => &mut alteracoes.apagados.produtos,
+                _ => &mut alteracoes.apagados.lotes,
+            };
+            *apagados = ids;
+        }
+    }
+
+    Ok(alteracoes)
+}
+
+// ===========================================
+// HANDLERS
+// ===========================================
+
+/// Envia a fila de operações feitas sem rede e recebe o que mudou no
+/// servidor. Conflitos vêm por operação, com status 200; só pedidos
+/// malformados respondem com erro.
+#[utoipa::path(post, path = "/sincronizar", tag = "sincronizacao", request_body = PedidoSincronizacao,
+    responses((status = 200, body = RespostaSincronizacao)))]
+pub async fn sincronizar_handler(JsonEntrada(pedido): JsonEntrada<PedidoSincronizacao>) -> Result<Json<RespostaSincronizacao>, ErroApp> {
+    let mut conn = init_db()?;
+
+    Ok(Json(sincronizar(&mut conn, pedido)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn banco() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::criar_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO secoes (id, nome) VALUES (1, 'Mercearia');
+             INSERT INTO tipos (id, nome, id_secao) VALUES (1, 'Doces', 1);
+             INSERT INTO produtos (id, nome, id_tipo) VALUES (1, 'BALA', 1);
+             INSERT INTO lotes (id, id_produto, validade, quantidade_total, quantidade_prateleira)
+             VALUES (1, 1, '2030-01-01', 10, 3);",
+        ).unwrap();
+        conn
+    }
+
+    fn pedido(desde: Option<i64>, operacoes: serde_json::Value) -> PedidoSincronizacao {
+        serde_json::from_value(serde_json::json!({ "desde": desde, "operacoes": operacoes })).unwrap()
+    }
+
+    #[test]
+    fn aplica_uma_vez_e_informa_conflitos() {
+        let mut conn = banco();
+        let operacoes = serde_json::json!([
+            { "id": "a", "momento": "2026-01-05T10:00:00-03:00", "acao": "vender", "id_lote": 1, "quantidade": 2 },
+            { "id": "b", "momento": "2026-01-05T10:01:00-03:00", "acao": "vender", "id_lote": 1, "quantidade": 5 },
+            { "id": "c", "momento": "2026-01-05T10:02:00-03:00", "acao": "criar_lote",
+              "id_produto": 1, "validade": "2030-02-01", "quantidade_total": 4, "quantidade_prateleira": 0 },
+            { "id": "d", "momento": "2026-01-05T10:03:00-03:00", "acao": "abastecer", "lote_operacao": "c", "quantidade": 4 },
+        ]);
+
+        let primeira = sincronizar(&mut conn, pedido(None, operacoes.clone())).unwrap();
+        let estados: Vec<_> = primeira.resultados.iter().map(|r| (r.estado, r.codigo.as_deref())).collect();
+        assert_eq!(estados, [
+            (EstadoOperacao::Aplicada, None),
+            (EstadoOperacao::Conflito, Some("estoque_insuficiente")),
+            (EstadoOperacao::Aplicada, None),
+            (EstadoOperacao::Aplicada, None),
+        ]);
+        let novo = primeira.resultados[2].id_lote.unwrap();
+        assert_eq!(primeira.resultados[3].id_lote, Some(novo));
+
+        // Reenvio: nada é aplicado de novo
+        let segunda = sincronizar(&mut conn, pedido(Some(primeira.token), operacoes)).unwrap();
+        assert!(segunda.resultados.iter().all(|r| r.repetida));
+        assert_eq!(segunda.resultados[2].id_lote, Some(novo));
+        assert!(segunda.alteracoes.lotes.is_empty());
+
+        let (prateleira, vendas, data): (i32, i32, String) = conn.query_row(
+            "SELECT l.quantidade_prateleira, (SELECT COUNT(*) FROM vendas), (SELECT data FROM vendas)
+             FROM lotes l WHERE l.id = 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        ).unwrap();
+        assert_eq!((prateleira, vendas), (1, 1));
+        assert!(data.starts_with("2026-01-05"));
+    }
+
+    #[test]
+    fn operacao_e_resultado_sao_gravados_juntos() {
+        let mut conn = banco();
+        let operacoes = serde_json::json!([
+            { "id": "a", "momento": "2026-01-05T10:00:00-03:00", "acao": "vender", "id_lote": 1, "quantidade": 2 },
+        ]);
+        let prateleira_e_vendas = |conn: &Connection| -> (i32, i32) {
+            conn.query_row(
+                "SELECT quantidade_prateleira, (SELECT COUNT(*) FROM vendas) FROM lotes WHERE id = 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?))
+            ).unwrap()
+        };
+
+        // Se o resultado não puder ser gravado, a venda também não fica
+        conn.execute_batch(
+            "CREATE TEMP TRIGGER falha BEFORE INSERT ON operacoes_sincronizadas
+             BEGIN SELECT RAISE(ABORT, 'falha simulada'); END;"
+        ).unwrap();
+        assert!(sincronizar(&mut conn, pedido(None, operacoes.clone())).is_err());
+        assert_eq!(prateleira_e_vendas(&conn), (3, 0));
+
+        // Na nova tentativa a operação ainda é aplicada, uma vez só
+        conn.execute_batch("DROP TRIGGER temp.falha").unwrap();
+        let resposta = sincronizar(&mut conn, pedido(None, operacoes)).unwrap();
+        assert_eq!((resposta.resultados[0].estado, resposta.resultados[0].repetida), (EstadoOperacao::Aplicada, false));
+        assert_eq!(prateleira_e_vendas(&conn), (1, 1));
+    }
+
+    #[test]
+    fn devolve_so_o_que_mudou_desde_o_token() {
+        let mut conn = banco();
+        let tudo = sincronizar(&mut conn, pedido(None, serde_json::json!([]))).unwrap();
+        assert_eq!((tudo.alteracoes.secoes.len(), tudo.alteracoes.lotes.len()), (1, 1));
+
+        conn.execute("UPDATE lotes SET quantidade_total = 9 WHERE id = 1", []).unwrap();
+        conn.execute("INSERT INTO tipos (id, nome, id_secao) VALUES (2, 'Biscoitos', 1)", []).unwrap();
+        conn.execute("DELETE FROM tipos WHERE id = 2", []).unwrap();
+
+        let delta = sincronizar(&mut conn, pedido(Some(tudo.token), serde_json::json!([]))).unwrap();
+        assert!(delta.token > tudo.token);
+        assert!(delta.alteracoes.secoes.is_empty() && delta.alteracoes.tipos.is_empty());
+        assert_eq!(delta.alteracoes.lotes[0].quantidade_total, 9);
+        assert_eq!(delta.alteracoes.apagados.tipos, [2]);
+
+        // Apagar a seção apaga em cascata, e os filhos também aparecem
+        conn.execute("DELETE FROM secoes WHERE id = 1", []).unwrap();
+        let cascata = sincronizar(&mut conn, pedido(Some(delta.token), serde_json::json!([]))).unwrap();
+        assert_eq!(cascata.alteracoes.apagados.lotes, [1]);
+        assert_eq!(cascata.alteracoes.apagados.secoes, [1]);
+    }
+}
@@ -0,0 +1,289 @@
+use argon2::{
+    password_hash::{
+        rand_core::{OsRng, RngCore},
+        PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+    },
+    Argon2,
+};
+use axum::{
+    extract::Request,
+    http::header,
+    middleware::Next,
+    response::Response,
+};
+use blake2::{Blake2s256, Digest};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fs;
+use std::path::Path;
+
+use crate::config;
+use crate::erro::ErroApp;
+use crate::init_db;
+
+/// Banco de usuários antigo, na pasta de dados, migrado para `produtos.db` na primeira subida.
+const LOGINS_ANTIGO: &str = "logins.db";
+/// Cookie com o token da sessão, gravado pelo `/login`.
+pub const COOKIE_SESSAO: &str = "sessao";
+/// Depois disso é preciso entrar de novo.
+pub const DURACAO_SESSAO_HORAS: u32 = 12;
+
+// ===========================================
+// SENHAS
+// ===========================================
+
+pub fn gerar_hash(senha: &str) -> Result<String, argon2::password_hash::Error> {
+    let sal = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default().hash_password(senha.as_bytes(), &sal)?.to_string())
+}
+
+pub fn senha_confere(senha: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|hash| Argon2::default().verify_password(senha.as_bytes(), &hash).is_ok())
+        .unwrap_or(false)
+}
+
+/// Cria o usuário; devolve `false` se o nome já existir.
+pub fn criar_usuario(conn: &Connection, nome: &str, senha: &str, admin: bool) -> Result<bool, Box<dyn std::error::Error>> {
+    let hash = gerar_hash(senha)?;
+    let inseridos = conn.execute(
+        "INSERT OR IGNORE INTO usuarios (nome, senha_hash, admin) VALUES (?1, ?2, ?3)",
+        params![nome, hash, admin],
+    )?;
+    Ok(inseridos > 0)
+}
+
+// ===========================================
+// SESSÕES
+// ===========================================
+
+/// Guarda só o hash do token: quem lê o banco (ou uma cópia dele) não
+/// consegue usar as sessões abertas.
+pub fn criar_tabelas(conn: &Connection) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sessoes (
+            token_hash BLOB PRIMARY KEY,
+            id_usuario INTEGER NOT NULL,
+            criada_em TEXT NOT NULL DEFAULT (datetime('now', 'localtime')),
+            FOREIGN KEY (id_usuario) REFERENCES usuarios(id) ON DELETE CASCADE
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+fn hash_do_token(token: &str) -> Vec<u8> {
+    Blake2s256::digest(token.as_bytes()).to_vec()
+}
+
+/// Apaga as sessões vencidas e abre uma nova; devolve o token para o cookie.
+pub fn abrir_sessao(conn: &Connection, id_usuario: i32) -> Result<String, rusqlite::Error> {
+    conn.execute(
+        "DELETE FROM sessoes WHERE criada_em < datetime('now', 'localtime', ?1)",
+        [format!("-{} hours", DURACAO_SESSAO_HORAS)],
+    )?;
+
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let token: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+    conn.execute(
+        "INSERT INTO sessoes (token_hash, id_usuario) VALUES (?1, ?2)",
+        params![hash_do_token(&token), id_usuario],
+    )?;
+
+    Ok(token)
+}
+
+/// `Some(admin)` se o token for de uma sessão ainda válida.
+fn sessao_e_admin(conn: &Connection, token: &str) -> Result<Option<bool>, rusqlite::Error> {
+    conn.query_row(
+        "SELECT u.admin FROM sessoes s
+         JOIN usuarios u ON u.id = s.id_usuario
+         WHERE s.token_hash = ?1 AND s.criada_em >= datetime('now', 'localtime', ?2)",
+        params![hash_do_token(token), format!("-{} hours", DURACAO_SESSAO_HORAS)],
+        |row| row.get(0),
+    ).optional()
+}
+
+/// `Set-Cookie` da sessão. Com TLS ligado o cookie só vai por HTTPS.
+pub fn cookie_da_sessao(token: &str) -> String {
+    format!(
+        "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Strict{}",
+        COOKIE_SESSAO,
+        token,
+        DURACAO_SESSAO_HORAS * 60 * 60,
+        if config::atual().tls.ativo { "; Secure" } else { "" },
+    )
+}
+
+fn token_da_requisicao(requisicao: &Request) -> Option<&str> {
+    requisicao.headers().get_all(header::COOKIE).iter()
+        .filter_map(|valor| valor.to_str().ok())
+        .flat_map(|valor| valor.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(nome, _)| *nome == COOKIE_SESSAO)
+        .map(|(_, token)| token)
+}
+
+/// Middleware das rotas de administração: exige uma sessão aberta pelo
+/// `/login` de um usuário admin.
+pub async fn exigir_admin(requisicao: Request, proximo: Next) -> Result<Response, ErroApp> {
+    let token = token_da_requisicao(&requisicao)
+        .ok_or(ErroApp::NaoAutenticado("Entre com um usuário admin para continuar"))?;
+
+    match sessao_e_admin(&init_db()?, token)? {
+        None => Err(ErroApp::NaoAutenticado("Sessão inválida ou expirada; entre de novo")),
+        Some(false) => Err(ErroApp::Proibido("Só usuários admin podem fazer isso")),
+        Some(true) => Ok(proximo.run(requisicao).await),
+    }
+}
+
+// ===========================================
+// MIGRAÇÃO E PRIMEIRO ADMIN
+// ===========================================
+
+/// Traz os usuários de `dados/logins.db` (senhas em texto puro) para a tabela
+/// `usuarios`, já com hash. Só o primeiro vira admin, e só se ainda não houver
+/// nenhum; os demais entram como usuários comuns.
+pub fn migrar_logins_antigo(conn: &Connection) -> Result<usize, Box<dyn std::error::Error>> {
+    migrar_logins(conn, &config::atual().dados.join(LOGINS_ANTIGO))
+}
+
+fn ler_logins(caminho: &Path) -> Result<Vec<(String, String)>, rusqlite::Error> {
+    let antigo = Connection::open(caminho)?;
+    let mut stmt = antigo.prepare("SELECT nome, password FROM usuarios ORDER BY id")?;
+    let linhas = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    linhas.collect()
+}
+
+/// Gravada a migração, o arquivo antigo vira `logins.db.migrado` em vez de ser
+/// apagado na hora. Na subida seguinte ele é apagado, para as senhas em texto
+/// puro não ficarem no disco, mas só se todos os seus usuários estiverem no
+/// banco novo.
+fn migrar_logins(conn: &Connection, caminho: &Path) -> Result<usize, Box<dyn std::error::Error>> {
+    let migrado = caminho.with_extension("db.migrado");
+    if migrado.exists() {
+        let faltando = ler_logins(&migrado)?.into_iter()
+            .map(|(nome, _)| conn.query_row("SELECT COUNT(*) = 0 FROM usuarios WHERE nome = ?1", [nome], |row| row.get(0)))
+            .collect::<Result<Vec<bool>, _>>()?
+            .contains(&true);
+        if faltando {
+            tracing::warn!("⚠️  {} tem usuários que não estão no banco; apague-o à mão depois de conferir", migrado.display());
+        } else {
+            fs::remove_file(&migrado)?;
+            tracing::info!("🧹 {} apagado: tinha senhas em texto puro", migrado.display());
+        }
+    }
+
+    if !caminho.exists() {
+        return Ok(0);
+    }
+
+    let antigos = ler_logins(caminho)?;
+
+    let tx = conn.unchecked_transaction()?;
+    let mut falta_admin: bool = tx.query_row("SELECT NOT EXISTS (SELECT 1 FROM usuarios WHERE admin)", [], |row| row.get(0))?;
+    let mut migrados = 0;
+    for (nome, senha) in &antigos {
+        if criar_usuario(&tx, nome, senha, falta_admin)? {
+            migrados += 1;
+            falta_admin = false;
+        }
+    }
+    tx.commit()?;
+
+    fs::rename(caminho, &migrado)?;
+    tracing::info!("📦 {} renomeado para {}; será apagado na próxima subida", caminho.display(), migrado.display());
+    Ok(migrados)
+}
+
+/// Roda ao subir o servidor: migra o banco antigo e, se ainda não houver
+/// nenhum usuário, cria o admin informado na linha de comando ou no ambiente.
+pub fn preparar_usuarios(conn: &Connection, admin: Option<(String, String)>) -> Result<(), Box<dyn std::error::Error>> {
+    let migrados = migrar_logins_antigo(conn)?;
+    if migrados > 0 {
+        tracing::info!("👤 {} usuários migrados de {}", migrados, LOGINS_ANTIGO);
+    }
+
+    let existentes: i64 = conn.query_row("SELECT COUNT(*) FROM usuarios", [], |row| row.get(0))?;
+    if existentes > 0 {
+        return Ok(());
+    }
+
+    let admin = admin.filter(|(nome, senha)| !nome.trim().is_empty() && !senha.is_empty());
+    match admin {
+        Some((nome, senha)) => {
+            let nome = nome.trim();
+            criar_usuario(conn, nome, &senha, true)?;
+            tracing::info!("👤 Usuário admin '{}' criado", nome);
+        }
+        None => tracing::warn!(
+            "⚠️  Nenhum usuário cadastrado. Informe --admin-usuario e --admin-senha \
+             (ou ADMIN_USUARIO e ADMIN_SENHA) para criar o primeiro admin."
+        ),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sessao_vale_ate_vencer_e_conhece_o_papel() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::criar_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO usuarios (id, nome, senha_hash, admin) VALUES (1, 'ana', '-', 1), (2, 'rui', '-', 0);"
+        ).unwrap();
+
+        let admin = abrir_sessao(&conn, 1).unwrap();
+        let comum = abrir_sessao(&conn, 2).unwrap();
+        assert_eq!(admin.len(), 64);
+        assert_ne!(admin, comum);
+
+        assert_eq!(sessao_e_admin(&conn, &admin).unwrap(), Some(true));
+        assert_eq!(sessao_e_admin(&conn, &comum).unwrap(), Some(false));
+        assert_eq!(sessao_e_admin(&conn, "token-inventado").unwrap(), None);
+
+        // O banco não guarda o token em si
+        let guardado: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM sessoes WHERE token_hash = ?1", [admin.as_bytes()], |row| row.get(0)
+        ).unwrap();
+        assert_eq!(guardado, 0);
+
+        conn.execute("UPDATE sessoes SET criada_em = datetime('now', 'localtime', '-13 hours')", []).unwrap();
+        assert_eq!(sessao_e_admin(&conn, &admin).unwrap(), None);
+    }
+
+    #[test]
+    fn logins_antigos_migram_com_um_so_admin_e_o_arquivo_sai_depois() {
+        let pasta = std::env::temp_dir().join(format!("validade-logins-{}", std::process::id()));
+        fs::create_dir_all(&pasta).unwrap();
+        let caminho = pasta.join(LOGINS_ANTIGO);
+        let migrado = caminho.with_extension("db.migrado");
+        Connection::open(&caminho).unwrap().execute_batch(
+            "CREATE TABLE usuarios (id INTEGER PRIMARY KEY, nome TEXT NOT NULL UNIQUE, password TEXT NOT NULL);
+             INSERT INTO usuarios VALUES (1, 'ana', 'segredo'), (2, 'rui', 'outra');"
+        ).unwrap();
+
+        let conn = Connection::open_in_memory().unwrap();
+        crate::criar_schema(&conn).unwrap();
+
+        assert_eq!(migrar_logins(&conn, &caminho).unwrap(), 2);
+        let usuarios: Vec<(String, bool, String)> = conn.prepare("SELECT nome, admin, senha_hash FROM usuarios ORDER BY id").unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap()
+            .collect::<Result<_, _>>().unwrap();
+        assert_eq!(usuarios.iter().map(|(nome, admin, _)| (nome.as_str(), *admin)).collect::<Vec<_>>(), [("ana", true), ("rui", false)]);
+        assert!(senha_confere("segredo", &usuarios[0].2));
+        // O arquivo antigo fica renomeado até a próxima subida
+        assert!(!caminho.exists() && migrado.exists());
+
+        assert_eq!(migrar_logins(&conn, &caminho).unwrap(), 0);
+        assert!(!migrado.exists());
+
+        fs::remove_dir_all(&pasta).unwrap();
+    }
+}